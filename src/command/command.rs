@@ -1,24 +1,87 @@
 // Command execution module for handling different types of commands
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::io;
-use std::process::Command;
 use std::env;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
+
+use crossterm::terminal::size;
+use serde::Deserialize;
+
+use crate::completion::completion::{complete_path, current_word, Candidate, Completion};
+use crate::pty::pty::PtyProcess;
+use crate::session::session::{SharedSession, ShellSession};
 
 pub enum CommandResult {
     Output(String),
     Error(String),
     Empty,
     DirectoryChanged(PathBuf),
+    // A child process attached to a pseudo-terminal; the terminal drives it
+    Spawned(PtyProcess),
+    // The command was handed to the persistent shell, which the terminal
+    // drives until the shell reports the command finished
+    Session(SharedSession),
 }
 
+// Builtins are given the command line split into words, with the command
+// name first; the shell fallback gets the whole line as a single argument
 pub trait CommandExecutor {
     fn execute(&self, args: &[&str]) -> io::Result<CommandResult>;
     fn name(&self) -> &str;
     fn help(&self) -> &str;
+    
+    // Command names this executor handles when registered as a builtin
+    fn names(&self) -> Vec<&str> {
+        vec![self.name()]
+    }
+    
+    // Handle any command starting with one of these, like `git-`
+    fn prefixes(&self) -> Vec<&str> {
+        Vec::new()
+    }
+    
+    // Tab completions for `args[cursor]`, the argument under the cursor as
+    // far as it's typed. `args` are the command's words, name first.
+    // Replacements are plain words, quoted by the caller; no candidates
+    // means paths get completed instead.
+    fn complete(&self, _args: &[&str], _cursor: usize) -> Vec<Candidate> {
+        Vec::new()
+    }
 }
 
-// Bash command executor - runs commands in bash
-pub struct BashExecutor;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ShellMode {
+    // One long-lived bash for all commands, so exports, aliases, functions
+    // and `cd` stick the way they do in a normal shell
+    Session,
+    // A fresh `bash -c` per command; job control is done by the terminal
+    PerCommand,
+}
+
+// Bash command executor - runs commands in bash on a pseudo-terminal
+pub struct BashExecutor {
+    mode: ShellMode,
+    // The bash to run, a name looked up in $PATH or a path
+    program: String,
+    // Started by the first command, and again whenever the shell exited
+    session: RefCell<Option<SharedSession>>,
+}
+
+impl BashExecutor {
+    pub fn new(mode: ShellMode) -> Self {
+        Self::with_program(mode, "bash")
+    }
+    
+    pub fn with_program(mode: ShellMode, program: &str) -> Self {
+        Self {
+            mode,
+            program: program.to_string(),
+            session: RefCell::new(None),
+        }
+    }
+}
 
 impl CommandExecutor for BashExecutor {
     fn execute(&self, args: &[&str]) -> io::Result<CommandResult> {
@@ -28,27 +91,16 @@ impl CommandExecutor for BashExecutor {
         
         let command = args.join(" ");
         
-        // Special handling for cd command since it affects the process state
-        if command.trim().starts_with("cd ") {
-            return self.handle_cd_command(&command);
-        }
+        // Start the child with the same size as the real terminal
+        let (cols, rows) = size().unwrap_or((80, 24));
         
-        let output = Command::new("bash")
-            .arg("-c")
-            .arg(&command)
-            .output()?;
-            
-        if !output.status.success() {
-            let error = String::from_utf8_lossy(&output.stderr).to_string();
-            return Ok(CommandResult::Error(error));
+        if self.mode == ShellMode::Session {
+            return self.run_in_session(&command, cols, rows);
         }
         
-        let output_str = String::from_utf8_lossy(&output.stdout).to_string();
-        if output_str.is_empty() {
-            Ok(CommandResult::Empty)
-        } else {
-            Ok(CommandResult::Output(output_str))
-        }
+        let process = PtyProcess::spawn_in(&self.program, &command, cols, rows)?;
+        
+        Ok(CommandResult::Spawned(process))
     }
     
     fn name(&self) -> &str {
@@ -61,11 +113,52 @@ impl CommandExecutor for BashExecutor {
 }
 
 impl BashExecutor {
-    // Special handling for cd command
-    fn handle_cd_command(&self, command: &str) -> io::Result<CommandResult> {
-        // Extract the directory path from the cd command
-        let parts: Vec<&str> = command.split_whitespace().collect();
+    fn run_in_session(&self, command: &str, cols: u16, rows: u16) -> io::Result<CommandResult> {
+        let mut session = self.session.borrow_mut();
         
+        let alive = match session.as_ref() {
+            Some(shared) => !shared.borrow_mut().is_finished()?,
+            None => false,
+        };
+        if !alive {
+            *session = Some(ShellSession::start_with(&self.program, cols, rows)?.shared());
+        }
+        
+        let Some(shared) = session.as_ref() else {
+            return Ok(CommandResult::Error("Shell session not started".to_string()));
+        };
+        shared.borrow_mut().run(command)?;
+        Ok(CommandResult::Session(shared.clone()))
+    }
+}
+
+// cd executor - a `bash -c` can't change our directory, so without a
+// persistent shell `cd` has to run in-process
+pub struct CdExecutor;
+
+impl CommandExecutor for CdExecutor {
+    fn execute(&self, args: &[&str]) -> io::Result<CommandResult> {
+        self.handle_cd_command(args)
+    }
+    
+    fn name(&self) -> &str {
+        "cd"
+    }
+    
+    fn help(&self) -> &str {
+        "Changes the current directory"
+    }
+    
+    fn complete(&self, args: &[&str], cursor: usize) -> Vec<Candidate> {
+        match (cursor, env::current_dir()) {
+            (1, Ok(cwd)) => complete_path(args[1], &cwd, true),
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl CdExecutor {
+    fn handle_cd_command(&self, parts: &[&str]) -> io::Result<CommandResult> {
         if parts.len() < 2 {
             // Just "cd" without args should go to home directory
             if let Some(home) = dirs::home_dir() {
@@ -115,40 +208,317 @@ impl BashExecutor {
     }
 }
 
-// Command registry to manage available commands
+// Command registry to manage available commands. The first word of a
+// command line picks who runs it: a builtin registered under that name or
+// prefix, else an alias is expanded, else the line goes to the shell.
 pub struct CommandRegistry {
     executors: Vec<Box<dyn CommandExecutor>>,
+    aliases: HashMap<String, String>,
+    shell: Box<dyn CommandExecutor>,
+    shell_mode: ShellMode,
+}
+
+impl Default for CommandRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CommandRegistry {
     pub fn new() -> Self {
+        Self::with_shell_mode(ShellMode::Session)
+    }
+    
+    pub fn with_shell_mode(shell_mode: ShellMode) -> Self {
+        Self::with_shell(shell_mode, "bash")
+    }
+    
+    // Commands for the shell go to `program`, a bash
+    pub fn with_shell(shell_mode: ShellMode, program: &str) -> Self {
         let mut registry = Self {
             executors: Vec::new(),
+            aliases: HashMap::new(),
+            shell: Box::new(BashExecutor::with_program(shell_mode, program)),
+            shell_mode,
         };
         
         // Register default executors
-        registry.register(Box::new(BashExecutor));
+        if shell_mode == ShellMode::PerCommand {
+            registry.register(Box::new(CdExecutor));
+        }
         
         registry
     }
     
+    pub fn shell_mode(&self) -> ShellMode {
+        self.shell_mode
+    }
+    
+    // Add a builtin; later registrations win over earlier ones
     pub fn register(&mut self, executor: Box<dyn CommandExecutor>) {
         self.executors.push(executor);
     }
     
-    pub fn execute_bash_command(&self, command: &str) -> io::Result<CommandResult> {
-        // Find the bash executor
-        for executor in &self.executors {
-            if executor.name() == "bash" {
-                // Create a vector with the command as a single string
-                let args = vec![command];
-                // Convert to a slice of string references
-                let args_slice: Vec<&str> = args.iter().map(|s| &**s).collect();
-                return executor.execute(&args_slice);
+    pub fn set_alias(&mut self, name: &str, expansion: &str) {
+        self.aliases.insert(name.to_string(), expansion.to_string());
+    }
+    
+    pub fn remove_alias(&mut self, name: &str) -> Option<String> {
+        self.aliases.remove(name)
+    }
+    
+    pub fn aliases(&self) -> &HashMap<String, String> {
+        &self.aliases
+    }
+    
+    // The builtin that would run `word`, if any
+    pub fn find(&self, word: &str) -> Option<&dyn CommandExecutor> {
+        self.executors
+            .iter()
+            .rev()
+            .find(|executor| {
+                executor.names().contains(&word)
+                    || executor.prefixes().iter().any(|prefix| word.starts_with(prefix))
+            })
+            .map(|executor| executor.as_ref())
+    }
+    
+    // Ask the builtin the command under the cursor goes to for candidates
+    // for the word being typed. None when there's no such builtin or it
+    // has nothing to offer.
+    pub fn complete(&self, line: &str, cursor: usize) -> Option<Completion> {
+        let word = current_word(line, cursor);
+        let name = word.previous.first()?;
+        let executor = self.find(name)?;
+        
+        // The words after the cursor, if the rest of the line is still the
+        // same command
+        let whole = current_word(line, line.len());
+        let index = word.previous.len();
+        let following = match whole.previous.get(..=index) {
+            Some(through) if through[..index] == word.previous[..] => {
+                whole.previous[index + 1..].iter().chain(std::iter::once(&whole.value)).collect()
+            }
+            _ => Vec::new(),
+        };
+        
+        let mut args: Vec<&str> = word.previous.iter().map(String::as_str).collect();
+        args.push(&word.value);
+        args.extend(following.into_iter().map(String::as_str).filter(|arg| !arg.is_empty()));
+        
+        let candidates = executor.complete(&args, index);
+        if candidates.is_empty() {
+            return None;
+        }
+        Some(Completion::for_word(&word, candidates))
+    }
+    
+    pub fn execute(&self, command: &str) -> io::Result<CommandResult> {
+        self.dispatch(command, &mut Vec::new())
+    }
+    
+    // `expanded` holds the aliases already expanded, so an alias that
+    // refers to itself (`ls` -> `ls --color`) ends up at the next stage
+    fn dispatch(&self, command: &str, expanded: &mut Vec<String>) -> io::Result<CommandResult> {
+        let Some(first) = command.split_whitespace().next() else {
+            return Ok(CommandResult::Empty);
+        };
+        
+        // Builtins only take simple commands; pipes, redirections,
+        // expansions and the like need a real shell
+        if let Some(words) = split_words(command) {
+            if let Some(executor) = self.find(&words[0]) {
+                let args: Vec<&str> = words.iter().map(String::as_str).collect();
+                return executor.execute(&args);
             }
         }
         
-        // If we can't find bash executor, return an error
-        Ok(CommandResult::Error("Bash executor not found".to_string()))
+        if let Some(expansion) = self.aliases.get(first) {
+            if !expanded.iter().any(|name| name == first) {
+                expanded.push(first.to_string());
+                let rest = command.trim_start()[first.len()..].to_string();
+                return self.dispatch(&format!("{}{}", expansion, rest), expanded);
+            }
+        }
+        
+        self.execute_bash_command(command)
+    }
+    
+    pub fn execute_bash_command(&self, command: &str) -> io::Result<CommandResult> {
+        // Pass the whole command line as a single argument
+        self.shell.execute(&[command])
+    }
+}
+
+// Split a simple command line into words, removing quotes and expanding a
+// leading `~`. Returns None for anything that needs a shell to interpret.
+fn split_words(line: &str) -> Option<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut chars = line.chars().peekable();
+    
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next()? {
+                        '\'' => break,
+                        c => word.push(c),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => word.push(chars.next()?),
+                        '$' | '`' => return None,
+                        c => word.push(c),
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+                word.push(chars.next()?);
+            }
+            '~' if !in_word && matches!(chars.peek(), None | Some('/') | Some(' ') | Some('\t')) => {
+                in_word = true;
+                word.push_str(&dirs::home_dir()?.to_string_lossy());
+            }
+            '#' if !in_word => return None,
+            '|' | '&' | ';' | '<' | '>' | '(' | ')' | '$' | '`' | '*' | '?' | '[' | '{' | '\n' => {
+                return None;
+            }
+            c => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    
+    // `NAME=value command` sets a variable for the command
+    match words.first() {
+        Some(first) if !first.contains('=') => Some(words),
+        _ => None,
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Echo;
+
+    impl CommandExecutor for Echo {
+        fn execute(&self, args: &[&str]) -> io::Result<CommandResult> {
+            Ok(CommandResult::Output(args.join(",")))
+        }
+
+        fn name(&self) -> &str {
+            "say"
+        }
+
+        fn help(&self) -> &str {
+            "Echoes its arguments"
+        }
+
+        fn names(&self) -> Vec<&str> {
+            vec!["say", "tell"]
+        }
+
+        fn prefixes(&self) -> Vec<&str> {
+            vec!["say-"]
+        }
+
+        fn complete(&self, args: &[&str], cursor: usize) -> Vec<Candidate> {
+            ["loud words", "quietly"]
+                .iter()
+                .filter(|option| option.starts_with(args[cursor]))
+                .map(|option| Candidate {
+                    description: Some(format!("{} of {}", cursor, args.len())),
+                    ..Candidate::new(option)
+                })
+                .collect()
+        }
+    }
+
+    fn registry() -> CommandRegistry {
+        let mut registry = CommandRegistry::with_shell_mode(ShellMode::PerCommand);
+        registry.register(Box::new(Echo));
+        registry
+    }
+
+    fn output(result: io::Result<CommandResult>) -> Option<String> {
+        match result {
+            Ok(CommandResult::Output(output)) => Some(output),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn routes_by_name_and_prefix() {
+        let registry = registry();
+        assert_eq!(output(registry.execute("say hi  'a b'")), Some("say,hi,a b".to_string()));
+        assert_eq!(output(registry.execute("tell x")), Some("tell,x".to_string()));
+        assert_eq!(output(registry.execute("say-loud x")), Some("say-loud,x".to_string()));
+        assert!(registry.find("cd").is_some());
+        assert!(registry.find("ls").is_none());
+    }
+
+    #[test]
+    fn expands_aliases() {
+        let mut registry = registry();
+        registry.set_alias("greet", "say hello");
+        registry.set_alias("say", "say again");
+        assert_eq!(output(registry.execute("greet world")), Some("say,hello,world".to_string()));
+    }
+
+    #[test]
+    fn shell_syntax_is_left_to_the_shell() {
+        assert_eq!(split_words("say \"a b\" c\\ d"), Some(vec!["say".into(), "a b".into(), "c d".into()]));
+        assert_eq!(split_words("say a | grep b"), None);
+        assert_eq!(split_words("say $HOME"), None);
+        assert_eq!(split_words("FOO=1 say"), None);
+        assert_eq!(split_words("say 'unterminated"), None);
+        assert_eq!(split_words("say a#b"), Some(vec!["say".into(), "a#b".into()]));
+    }
+
+    #[test]
+    fn shell_session_starts_on_first_use() {
+        let executor = BashExecutor::new(ShellMode::Session);
+        assert!(executor.session.borrow().is_none());
+        let registry = CommandRegistry::default();
+        assert_eq!(registry.shell_mode(), ShellMode::Session);
+    }
+
+    #[test]
+    fn asks_builtins_for_completions() {
+        let registry = registry();
+        let completion = registry.complete("say lo", 6).unwrap();
+        assert_eq!(completion.start, 4);
+        assert_eq!(completion.candidates[0].replacement, "loud\\ words");
+        assert_eq!(completion.candidates[0].description.as_deref(), Some("1 of 2"));
+
+        let completion = registry.complete("tell 'q", 7).unwrap();
+        assert_eq!(completion.candidates[0].replacement, "'quietly'");
+
+        // Words after the cursor are passed along too
+        let completion = registry.complete("say q x y", 5).unwrap();
+        assert_eq!(completion.candidates[0].description.as_deref(), Some("1 of 4"));
+
+        assert!(registry.complete("say z", 5).is_none());
+        assert!(registry.complete("ls q", 4).is_none());
+    }
+}
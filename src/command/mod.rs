@@ -1 +1,2 @@
+#[allow(clippy::module_inception)]
 pub mod command;
\ No newline at end of file
@@ -0,0 +1,377 @@
+// Completions from the bash-completion specs that git, cargo, kubectl and
+// friends install, asked of a helper bash that has them loaded
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use super::completion::{current_word, escape, Candidate, Completion, Word};
+use crate::session::session::ansi_c_quote;
+
+// Loading a big spec like git's the first time takes a moment; a spec that
+// takes longer (kubectl asking an unreachable cluster) is given up on
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+// Ends each reply, after the NUL-terminated options and candidates
+const END_OF_REPLY: &[u8] = b"\0\x01\n";
+
+// Sources bash-completion where it's installed and falls back to loading
+// per-command specs by hand where it isn't. Completion functions are run
+// directly, so `compopt` is replaced by one that records the options.
+const HELPER_SCRIPT: &str = r#"
+shopt -s extglob progcomp
+for __rte_file in /usr/share/bash-completion/bash_completion /usr/local/share/bash-completion/bash_completion \
+        /opt/homebrew/share/bash-completion/bash_completion /etc/bash_completion; do
+    if [[ -r $__rte_file ]]; then . "$__rte_file"; break; fi
+done >/dev/null 2>&1
+
+compopt() {
+    while (($#)); do
+        case $1 in
+            -o) __rte_options+=" $2"; shift ;;
+            +o) __rte_options=${__rte_options// $2/}; shift ;;
+        esac
+        shift
+    done
+}
+
+__rte_load() {
+    if declare -F _comp_load >/dev/null; then
+        _comp_load -- "$1"
+    elif declare -F __load_completion >/dev/null; then
+        __load_completion "$1"
+    else
+        local dir
+        for dir in "${BASH_COMPLETION_USER_DIR:-${XDG_DATA_HOME:-$HOME/.local/share}/bash-completion}/completions" \
+                /usr/local/share/bash-completion/completions /usr/share/bash-completion/completions; do
+            if [[ -r $dir/$1 ]]; then . "$dir/$1"; return; fi
+        done
+    fi
+}
+
+__rte_complete() {
+    cd -- "$1" 2>/dev/null
+    COMP_CWORD=$2 COMP_LINE=$3 COMP_POINT=${#3}
+    COMP_WORDS=("${@:4}")
+    COMP_TYPE=9 COMP_KEY=9
+    COMPREPLY=()
+    __rte_options=
+    local cmd=${COMP_WORDS[0]##*/} cur=${COMP_WORDS[COMP_CWORD]} prev=${COMP_WORDS[COMP_CWORD-1]}
+    local spec
+    spec=$(complete -p -- "$cmd" 2>/dev/null) || {
+        __rte_load "$cmd" >/dev/null 2>&1
+        spec=$(complete -p -- "$cmd" 2>/dev/null)
+    }
+    if [[ $spec ]]; then
+        local -a words args=()
+        local func= i
+        eval "words=($spec)"
+        for ((i = 1; i < ${#words[@]} - 1; i++)); do
+            case ${words[i]} in
+                -F) func=${words[++i]} ;;
+                -o) __rte_options+=" ${words[++i]}" ;;
+                -[ACGWXPS]) args+=("${words[i]}" "${words[++i]}") ;;
+                *) args+=("${words[i]}") ;;
+            esac
+        done
+        if [[ $func ]]; then
+            "$func" "$cmd" "$cur" "$prev" </dev/null >/dev/null 2>&1
+        fi
+        if ((${#args[@]})); then
+            mapfile -t -O ${#COMPREPLY[@]} COMPREPLY < <(compgen "${args[@]}" -- "$cur" 2>/dev/null)
+        fi
+    fi
+    printf '%s\0' "$__rte_options" "${COMPREPLY[@]}"
+    printf '\1\n'
+}
+"#;
+
+#[derive(Default)]
+pub struct BashCompletion {
+    // Started on first use, and again after it fails or times out
+    helper: Option<Helper>,
+}
+
+struct Helper {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: ChildStdout,
+    buffer: Vec<u8>,
+}
+
+// What to send the helper for the word under the cursor
+#[derive(Debug, PartialEq)]
+struct Request {
+    // COMP_WORDS, split at `=` and `:` like bash does
+    words: Vec<String>,
+    // COMP_LINE, the command up to the cursor
+    line: String,
+    // The part of the word the candidates replace: what follows the
+    // last `=` or `:`
+    target: Word,
+}
+
+impl BashCompletion {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Candidates from the command's completion spec. None when it has no
+    // spec, the spec offers nothing, or the helper isn't working.
+    pub fn complete(&mut self, line: &str, cursor: usize, cwd: &Path) -> Option<Completion> {
+        let request = Request::new(line, cursor)?;
+        let mut command = format!(
+            "__rte_complete {} {} {}",
+            ansi_c_quote(&cwd.to_string_lossy()),
+            request.words.len() - 1,
+            ansi_c_quote(&request.line)
+        );
+        for word in &request.words {
+            command.push(' ');
+            command.push_str(&ansi_c_quote(word));
+        }
+        command.push('\n');
+
+        let reply = match self.ask(&command) {
+            Ok(reply) => reply,
+            Err(_) => {
+                self.helper = None;
+                return None;
+            }
+        };
+        let (options, replies) = reply.split_first()?;
+        let candidates = candidates(options, replies.to_vec(), cwd);
+        if candidates.is_empty() {
+            return None;
+        }
+        Some(Completion::for_word(&request.target, candidates))
+    }
+
+    fn ask(&mut self, command: &str) -> io::Result<Vec<String>> {
+        let helper = match &mut self.helper {
+            Some(helper) => helper,
+            None => self.helper.insert(Helper::start()?),
+        };
+        helper.stdin.write_all(command.as_bytes())?;
+        helper.read_reply()
+    }
+}
+
+impl Helper {
+    fn start() -> io::Result<Self> {
+        let mut child = Command::new("bash")
+            .args(["--norc", "--noprofile"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let (Some(mut stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+            return Err(io::Error::other("helper bash has no pipes"));
+        };
+        stdin.write_all(HELPER_SCRIPT.as_bytes())?;
+        Ok(Self {
+            child,
+            stdin,
+            stdout,
+            buffer: Vec::new(),
+        })
+    }
+
+    // Wait for the next reply and split it into its NUL-terminated fields
+    fn read_reply(&mut self) -> io::Result<Vec<String>> {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            if let Some(end) = self.buffer.windows(END_OF_REPLY.len()).position(|w| w == END_OF_REPLY) {
+                let reply: Vec<u8> = self.buffer.drain(..end + END_OF_REPLY.len()).collect();
+                let fields = &reply[..end + 1];
+                return Ok(fields
+                    .split(|&b| b == 0)
+                    .take(fields.iter().filter(|&&b| b == 0).count())
+                    .map(|field| String::from_utf8_lossy(field).into_owned())
+                    .collect());
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            let mut pollfd = libc::pollfd {
+                fd: self.stdout.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // SAFETY: polling one descriptor we own
+            let ready = unsafe { libc::poll(&mut pollfd, 1, remaining.as_millis() as libc::c_int) };
+            if ready < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+            if ready == 0 {
+                continue;
+            }
+
+            let mut chunk = [0u8; 4096];
+            let n = self.stdout.read(&mut chunk)?;
+            if n == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            self.buffer.extend_from_slice(&chunk[..n]);
+        }
+    }
+}
+
+impl Drop for Helper {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Request {
+    // Nothing to ask in command position; that's PATH completion
+    fn new(line: &str, cursor: usize) -> Option<Self> {
+        let word = current_word(line, cursor);
+        if word.command_position || word.previous.is_empty() {
+            return None;
+        }
+
+        let mut words = Vec::new();
+        let mut line = String::new();
+        for previous in &word.previous {
+            let previous = escape(previous);
+            words.extend(split_wordbreaks(&previous));
+            line.push_str(&previous);
+            line.push(' ');
+        }
+        line.push_str(&word.raw);
+
+        // Quoted words are left whole
+        let current = if word.raw == word.value {
+            split_wordbreaks(&word.raw)
+        } else {
+            vec![word.raw.clone()]
+        };
+        let last = current.last().map_or("", String::as_str);
+        let target = if word.raw != word.value {
+            word
+        } else {
+            // Right after a `=` the candidates go after it
+            let tail = if is_wordbreak_run(last) { "" } else { last };
+            Word {
+                start: cursor - tail.len(),
+                raw: tail.to_string(),
+                value: tail.to_string(),
+                quote: None,
+                command_position: false,
+                previous: Vec::new(),
+            }
+        };
+        words.extend(current);
+
+        Some(Self { words, line, target })
+    }
+}
+
+// Turn the helper's replies into candidates. A trailing space marks a
+// finished word, as git's specs do; with `nospace` others aren't.
+fn candidates(options: &str, mut replies: Vec<String>, cwd: &Path) -> Vec<Candidate> {
+    let options: Vec<&str> = options.split_whitespace().collect();
+    let filenames = options.contains(&"filenames");
+    let nospace = options.contains(&"nospace");
+    replies.sort();
+    replies.dedup();
+
+    replies
+        .iter()
+        .filter(|reply| !reply.trim().is_empty())
+        .map(|reply| {
+            let (text, complete) = match reply.strip_suffix(' ') {
+                Some(text) => (text, true),
+                None => (reply.as_str(), !nospace),
+            };
+            if filenames && cwd.join(text).is_dir() {
+                let text = format!("{}/", text.trim_end_matches('/'));
+                let name = text.trim_end_matches('/').rsplit('/').next().unwrap_or("");
+                return Candidate {
+                    display: format!("{}/", name),
+                    replacement: text,
+                    description: None,
+                    complete: false,
+                };
+            }
+            Candidate {
+                complete,
+                ..Candidate::new(text)
+            }
+        })
+        .collect()
+}
+
+fn is_wordbreak(c: char) -> bool {
+    c == '=' || c == ':'
+}
+
+fn is_wordbreak_run(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(is_wordbreak)
+}
+
+// `--pretty=one` becomes `--pretty`, `=`, `one`, each run of `=` and `:`
+// a word of its own
+fn split_wordbreaks(word: &str) -> Vec<String> {
+    let mut words: Vec<String> = Vec::new();
+    let mut previous_break = None;
+    for c in word.chars() {
+        let is_break = is_wordbreak(c);
+        if previous_break != Some(is_break) {
+            words.push(String::new());
+        }
+        previous_break = Some(is_break);
+        if let Some(last) = words.last_mut() {
+            last.push(c);
+        }
+    }
+    if words.is_empty() {
+        words.push(String::new());
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_words_like_bash() {
+        let request = Request::new("cd /tmp; git log --pretty=on", 28).unwrap();
+        assert_eq!(request.words, vec!["git", "log", "--pretty", "=", "on"]);
+        assert_eq!(request.line, "git log --pretty=on");
+        assert_eq!(request.target.start, 26);
+        assert_eq!(request.target.raw, "on");
+
+        let request = Request::new("scp host:", 9).unwrap();
+        assert_eq!(request.words, vec!["scp", "host", ":"]);
+        assert_eq!(request.target.start, 9);
+        assert_eq!(request.target.raw, "");
+
+        let request = Request::new("git add 'a b=c", 14).unwrap();
+        assert_eq!(request.words, vec!["git", "add", "'a b=c"]);
+        assert_eq!(request.target.start, 8);
+
+        assert!(Request::new("gi", 2).is_none());
+    }
+
+    #[test]
+    fn reads_reply_options() {
+        let replies = vec!["main ".to_string(), "--format=".to_string(), "main ".to_string()];
+        let candidates = candidates(" nospace", replies, Path::new("/"));
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(candidates[0].replacement, "--format=");
+        assert!(!candidates[0].complete);
+        assert_eq!(candidates[1].replacement, "main");
+        assert!(candidates[1].complete);
+    }
+}
@@ -0,0 +1,446 @@
+// Tab completion: executables from $PATH in command position, file and
+// directory paths everywhere else, and $VARIABLES from the environment
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    // Text that replaces the word being completed. Providers give the
+    // plain word; `Completion::for_word` quotes it for the line.
+    pub replacement: String,
+    // What the menu shows
+    pub display: String,
+    pub description: Option<String>,
+    // Whether the word is finished (a file rather than a directory), so a
+    // space can follow when it's the only candidate
+    pub complete: bool,
+}
+
+impl Candidate {
+    pub fn new(replacement: &str) -> Self {
+        Self {
+            replacement: replacement.to_string(),
+            display: replacement.to_string(),
+            description: None,
+            complete: true,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Completion {
+    // Byte offset in the line where the replaced text starts; it ends at
+    // the cursor
+    pub start: usize,
+    pub candidates: Vec<Candidate>,
+}
+
+impl Completion {
+    // Replace `word` with each candidate, quoted the way the word was
+    // started: inside an opening quote if it had one, backslashes if not
+    pub fn for_word(word: &Word, candidates: Vec<Candidate>) -> Self {
+        let quote = word.quote.filter(|q| word.raw.starts_with(*q));
+        let opening = quote.map(String::from).unwrap_or_default();
+        let candidates = candidates
+            .into_iter()
+            .map(|candidate| Candidate {
+                replacement: requote(&opening, &candidate.replacement, quote, candidate.complete),
+                ..candidate
+            })
+            .collect();
+        Self {
+            start: word.start,
+            candidates,
+        }
+    }
+
+    // The longest text all candidates start with
+    pub fn common_prefix(&self) -> &str {
+        let Some(first) = self.candidates.first() else {
+            return "";
+        };
+        let mut len = first.replacement.len();
+        for candidate in &self.candidates[1..] {
+            len = first
+                .replacement
+                .char_indices()
+                .zip(candidate.replacement.chars())
+                .take_while(|((_, a), b)| a == b)
+                .map(|((i, a), _)| i + a.len_utf8())
+                .last()
+                .unwrap_or(0)
+                .min(len);
+        }
+        &first.replacement[..len]
+    }
+}
+
+// The word under the cursor, as far as it's been typed
+#[derive(Debug, PartialEq)]
+pub struct Word {
+    // Byte offset of the word in the line
+    pub start: usize,
+    // The word as typed, quotes and backslashes included
+    pub raw: String,
+    // With quotes and escapes removed
+    pub value: String,
+    // Quote the word is still inside of
+    pub quote: Option<char>,
+    // First word of a command, where a program name goes
+    pub command_position: bool,
+    // Words of the current command before this one, unquoted
+    pub previous: Vec<String>,
+}
+
+// Split the line up to the cursor like the shell would, enough to find the
+// word being completed
+pub fn current_word(line: &str, cursor: usize) -> Word {
+    let mut word = Word {
+        start: 0,
+        raw: String::new(),
+        value: String::new(),
+        quote: None,
+        command_position: true,
+        previous: Vec::new(),
+    };
+    let mut in_word = false;
+    let mut escaped = false;
+
+    for (i, c) in line[..cursor].char_indices() {
+        if escaped {
+            escaped = false;
+            word.raw.push(c);
+            word.value.push(c);
+            continue;
+        }
+        match word.quote {
+            Some(q) if c == q => {
+                word.quote = None;
+                word.raw.push(c);
+            }
+            Some('"') if c == '\\' => {
+                escaped = true;
+                word.raw.push(c);
+            }
+            Some(_) => {
+                word.raw.push(c);
+                word.value.push(c);
+            }
+            None => {
+                let separator = c.is_whitespace();
+                let operator = matches!(c, '|' | '&' | ';' | '(' | ')' | '<' | '>');
+                if separator || operator {
+                    if in_word {
+                        word.previous.push(std::mem::take(&mut word.value));
+                        word.raw.clear();
+                        word.command_position = false;
+                        in_word = false;
+                    }
+                    // A new command starts after these
+                    if matches!(c, '|' | '&' | ';' | '(' | '\n') {
+                        word.command_position = true;
+                        word.previous.clear();
+                    }
+                    // Redirection targets are files, not commands
+                    if matches!(c, '<' | '>') {
+                        word.command_position = false;
+                    }
+                    word.start = i + c.len_utf8();
+                    continue;
+                }
+                if !in_word {
+                    word.start = i;
+                    in_word = true;
+                }
+                word.raw.push(c);
+                match c {
+                    '\\' => escaped = true,
+                    '\'' | '"' => word.quote = Some(c),
+                    c => word.value.push(c),
+                }
+            }
+        }
+    }
+    word
+}
+
+pub fn complete(line: &str, cursor: usize, cwd: &Path) -> Completion {
+    complete_with_variables(line, cursor, cwd, environment_names)
+}
+
+// `variables` gives the names $VARIABLES complete from, only asked for when
+// the word is one
+fn complete_with_variables(
+    line: &str,
+    cursor: usize,
+    cwd: &Path,
+    variables: impl FnOnce() -> Vec<String>,
+) -> Completion {
+    let word = current_word(line, cursor);
+
+    if let Some(completion) = complete_variable(&word, variables) {
+        return completion;
+    }
+
+    let candidates = if word.command_position && !word.value.contains('/') && !word.value.starts_with('~') {
+        executables(&word.value).iter().map(|name| Candidate::new(name)).collect()
+    } else {
+        complete_path(&word.value, cwd, false)
+    };
+    Completion::for_word(&word, candidates)
+}
+
+// `$NAME` at the end of the word, outside single quotes
+fn complete_variable(word: &Word, variables: impl FnOnce() -> Vec<String>) -> Option<Completion> {
+    if word.quote == Some('\'') {
+        return None;
+    }
+    let dollar = word.raw.rfind('$')?;
+    let name = &word.raw[dollar + 1..];
+    let (brace, name) = match name.strip_prefix('{') {
+        Some(name) => (true, name),
+        None => (false, name),
+    };
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return None;
+    }
+
+    let mut names: Vec<String> = variables().into_iter().filter(|key| key.starts_with(name)).collect();
+    names.sort();
+    let candidates = names
+        .into_iter()
+        .map(|key| Candidate {
+            replacement: if brace { format!("${{{}}}", key) } else { format!("${}", key) },
+            display: key,
+            description: None,
+            complete: true,
+        })
+        .collect();
+    Some(Completion {
+        start: word.start + dollar,
+        candidates,
+    })
+}
+
+// Names of the variables in the environment
+fn environment_names() -> Vec<String> {
+    env::vars_os().filter_map(|(key, _)| key.into_string().ok()).collect()
+}
+
+// Names of the programs in $PATH starting with `prefix`
+pub fn executables(prefix: &str) -> BTreeSet<String> {
+    let mut names = BTreeSet::new();
+    let Some(paths) = env::var_os("PATH") else {
+        return names;
+    };
+    for dir in env::split_paths(&paths) {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !name.starts_with(prefix) || names.contains(&name) {
+                continue;
+            }
+            // Follows symlinks, which most of /usr/bin is these days
+            let executable = entry
+                .path()
+                .metadata()
+                .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0);
+            if executable {
+                names.insert(name);
+            }
+        }
+    }
+    names
+}
+
+// Files and directories matching `value`, a path typed relative to `cwd`.
+// With `dirs_only`, as for `cd`, files are left out.
+pub fn complete_path(value: &str, cwd: &Path, dirs_only: bool) -> Vec<Candidate> {
+    // The directory part is kept as typed; only the name gets completed
+    let (dir_value, prefix) = match value.rfind('/') {
+        Some(slash) => (&value[..=slash], &value[slash + 1..]),
+        None => ("", value),
+    };
+
+    let dir = expand_tilde(dir_value);
+    let dir = if dir.as_os_str().is_empty() {
+        cwd.to_path_buf()
+    } else if dir.is_absolute() {
+        dir
+    } else {
+        cwd.join(dir)
+    };
+
+    // A lone `~` becomes the home directory itself
+    if value == "~" {
+        return vec![Candidate {
+            replacement: "~/".to_string(),
+            display: "~/".to_string(),
+            description: None,
+            complete: false,
+        }];
+    }
+
+    let Ok(entries) = fs::read_dir(&dir) else {
+        return Vec::new();
+    };
+    let mut candidates: Vec<Candidate> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            // Hidden files only when asked for
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            let is_dir = entry.path().is_dir();
+            if dirs_only && !is_dir {
+                return None;
+            }
+            let name = if is_dir { format!("{}/", name) } else { name };
+            Some(Candidate {
+                replacement: format!("{}{}", dir_value, name),
+                display: name,
+                description: None,
+                complete: !is_dir,
+            })
+        })
+        .collect();
+    candidates.sort_by(|a, b| a.display.cmp(&b.display));
+    candidates
+}
+
+// Backslash-escape a word for the command line
+pub fn escape(word: &str) -> String {
+    requote("", word, None, true)
+}
+
+// Append `name` to the raw text before it, quoted the way the word is
+fn requote(raw_before: &str, name: &str, quote: Option<char>, close: bool) -> String {
+    let mut text = raw_before.to_string();
+    match quote {
+        Some('\'') => {
+            text.push_str(&name.replace('\'', "'\\''"));
+            if close {
+                text.push('\'');
+            }
+        }
+        Some(q) => {
+            for c in name.chars() {
+                if matches!(c, '"' | '\\' | '$' | '`') {
+                    text.push('\\');
+                }
+                text.push(c);
+            }
+            if close {
+                text.push(q);
+            }
+        }
+        None => {
+            for c in name.chars() {
+                if c.is_whitespace() || "\"'\\$`&|;<>()*?[]{}!#".contains(c) {
+                    text.push('\\');
+                }
+                text.push(c);
+            }
+        }
+    }
+    text
+}
+
+fn expand_tilde(path: &str) -> PathBuf {
+    if path == "~" || path.starts_with("~/") {
+        if let Some(home) = dirs::home_dir() {
+            return home.join(path[1..].trim_start_matches('/'));
+        }
+    }
+    PathBuf::from(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("rte-completion-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("My Documents")).unwrap();
+        fs::create_dir_all(dir.join("music")).unwrap();
+        fs::write(dir.join("my file.txt"), "").unwrap();
+        fs::write(dir.join(".hidden"), "").unwrap();
+        dir
+    }
+
+    fn replacements(completion: &Completion) -> Vec<&str> {
+        completion.candidates.iter().map(|c| c.replacement.as_str()).collect()
+    }
+
+    #[test]
+    fn finds_the_current_word() {
+        let word = current_word("ls -l 'My Doc", 13);
+        assert_eq!(word.start, 6);
+        assert_eq!(word.value, "My Doc");
+        assert_eq!(word.quote, Some('\''));
+        assert!(!word.command_position);
+        assert_eq!(word.previous, vec!["ls", "-l"]);
+
+        let word = current_word("cat a | gr", 10);
+        assert!(word.command_position);
+        assert_eq!(word.value, "gr");
+
+        let word = current_word("echo my\\ f", 10);
+        assert_eq!(word.value, "my f");
+        assert_eq!(word.start, 5);
+    }
+
+    #[test]
+    fn completes_paths_with_quoting() {
+        let dir = temp_dir("paths");
+        let completion = complete("ls my", 5, &dir);
+        assert_eq!(replacements(&completion), vec!["my\\ file.txt"]);
+
+        let completion = complete("ls \"M", 5, &dir);
+        assert_eq!(replacements(&completion), vec!["\"My Documents/"]);
+
+        let completion = complete("ls 'my f", 8, &dir);
+        assert_eq!(replacements(&completion), vec!["'my file.txt'"]);
+
+        let completion = complete("ls m", 4, &dir);
+        assert_eq!(replacements(&completion), vec!["music/", "my\\ file.txt"]);
+        assert_eq!(completion.common_prefix(), "m");
+
+        let completion = complete("ls .h", 5, &dir);
+        assert_eq!(replacements(&completion), vec![".hidden"]);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn completes_from_a_directory_prefix() {
+        let dir = temp_dir("prefix");
+        let line = format!("cat {}/my", dir.display());
+        let completion = complete(&line, line.len(), &dir);
+        assert_eq!(completion.start, 4);
+        assert_eq!(replacements(&completion), vec![format!("{}/my\\ file.txt", dir.display())]);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn completes_variables() {
+        let variables = || vec!["RTE_COMPLETION_TEST".to_string(), "RTE_OTHER".to_string(), "HOME".to_string()];
+        let completion = complete_with_variables("echo \"$RTE_COMPL", 16, Path::new("/"), variables);
+        assert_eq!(completion.start, 6);
+        assert_eq!(replacements(&completion), vec!["$RTE_COMPLETION_TEST"]);
+        let completion = complete_with_variables("echo ${RTE_", 11, Path::new("/"), variables);
+        assert_eq!(replacements(&completion), vec!["${RTE_COMPLETION_TEST}", "${RTE_OTHER}"]);
+    }
+
+    #[test]
+    fn completes_commands_from_path() {
+        let completion = complete("sh", 2, Path::new("/"));
+        assert!(replacements(&completion).contains(&"sh"));
+    }
+}
@@ -0,0 +1,331 @@
+// Flag completion for commands without a completion spec: run
+// `<cmd> --help` once, pick the flags and their descriptions out of the
+// output and keep them in the cache dir until the program changes
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::completion::{current_word, Candidate, Completion};
+
+// A program that doesn't answer `--help` quickly is probably doing
+// something else with it
+const HELP_TIMEOUT: Duration = Duration::from_secs(1);
+const MAX_HELP_SIZE: u64 = 1 << 20;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Flag {
+    pub name: String,
+    pub description: String,
+    // Written as `--flag=VALUE`, so no space goes after it
+    pub takes_value: bool,
+}
+
+pub struct HelpFlags {
+    // None keeps what's learned in memory only
+    cache_dir: Option<PathBuf>,
+    flags: HashMap<String, Vec<Flag>>,
+}
+
+impl Default for HelpFlags {
+    fn default() -> Self {
+        Self::new(Self::default_cache_dir())
+    }
+}
+
+impl HelpFlags {
+    pub fn new(cache_dir: Option<PathBuf>) -> Self {
+        Self {
+            cache_dir,
+            flags: HashMap::new(),
+        }
+    }
+
+    // `~/.cache/rust-terminal-emulator/help` on Linux
+    pub fn default_cache_dir() -> Option<PathBuf> {
+        dirs::cache_dir().map(|dir| dir.join("rust-terminal-emulator").join("help"))
+    }
+
+    // Flags for the option being typed, for programs found in $PATH
+    pub fn complete(&mut self, line: &str, cursor: usize) -> Option<Completion> {
+        let word = current_word(line, cursor);
+        if word.command_position || !word.value.starts_with('-') {
+            return None;
+        }
+        let command = word.previous.first()?;
+        let flags = self.flags_for(command)?;
+
+        let candidates: Vec<Candidate> = flags
+            .iter()
+            .filter(|flag| flag.name.starts_with(&word.value))
+            .map(|flag| Candidate {
+                replacement: flag.name.clone(),
+                display: flag.name.clone(),
+                description: Some(flag.description.clone()).filter(|d| !d.is_empty()),
+                complete: !flag.takes_value,
+            })
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+        Some(Completion::for_word(&word, candidates))
+    }
+
+    fn flags_for(&mut self, command: &str) -> Option<&[Flag]> {
+        if !self.flags.contains_key(command) {
+            let program = find_program(command)?;
+            let flags = self
+                .load(command, &program)
+                .or_else(|| {
+                    let flags = parse_help(&run_help(&program)?);
+                    self.save(command, &flags);
+                    Some(flags)
+                })
+                .unwrap_or_default();
+            self.flags.insert(command.to_string(), flags);
+        }
+        self.flags.get(command).map(Vec::as_slice)
+    }
+
+    // The cached flags, unless the program is newer than the cache
+    fn load(&self, command: &str, program: &Path) -> Option<Vec<Flag>> {
+        let path = self.cache_dir.as_ref()?.join(command);
+        let cached = fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+        let installed = fs::metadata(program).and_then(|m| m.modified()).ok()?;
+        if installed > cached {
+            return None;
+        }
+        let contents = fs::read_to_string(&path).ok()?;
+        Some(contents.lines().filter_map(Flag::from_line).collect())
+    }
+
+    // One `name\tdescription` line per flag; an empty file still records
+    // that the program has none
+    fn save(&self, command: &str, flags: &[Flag]) {
+        let Some(dir) = &self.cache_dir else {
+            return;
+        };
+        let contents: String = flags.iter().map(Flag::to_line).collect();
+        let _ = fs::create_dir_all(dir).and_then(|_| fs::write(dir.join(command), contents));
+    }
+}
+
+impl Flag {
+    fn to_line(&self) -> String {
+        format!("{}\t{}\n", self.name, self.description.replace(['\t', '\n'], " "))
+    }
+
+    fn from_line(line: &str) -> Option<Self> {
+        let (name, description) = line.split_once('\t')?;
+        Some(Self {
+            name: name.to_string(),
+            description: description.to_string(),
+            takes_value: name.ends_with('='),
+        })
+    }
+}
+
+// Only bare names looked up in $PATH; running `./script --help` could do
+// anything
+fn find_program(command: &str) -> Option<PathBuf> {
+    if command.contains('/') || command.is_empty() {
+        return None;
+    }
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(command))
+        .find(|path| path.is_file())
+}
+
+// Help text from stdout, or stderr for programs that print it there
+fn run_help(program: &Path) -> Option<String> {
+    let mut child = Command::new(program)
+        .arg("--help")
+        .env("PAGER", "cat")
+        .env("MANPAGER", "cat")
+        .env("NO_COLOR", "1")
+        .env("TERM", "dumb")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .ok()?;
+
+    // Read both pipes on their own threads so a full one can't stall the
+    // program, and so a program that hangs can be given up on
+    let (sender, receiver) = mpsc::channel();
+    read_on_thread(child.stdout.take(), sender.clone());
+    read_on_thread(child.stderr.take(), sender);
+
+    let deadline = Instant::now() + HELP_TIMEOUT;
+    let mut outputs = Vec::new();
+    while outputs.len() < 2 {
+        match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(output) => outputs.push(output),
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return None;
+            }
+        }
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+    outputs.into_iter().max_by_key(|output| output.matches("\n  -").count())
+}
+
+fn read_on_thread<R: Read + Send + 'static>(pipe: Option<R>, sender: mpsc::Sender<String>) {
+    let Some(pipe) = pipe else {
+        return;
+    };
+    thread::spawn(move || {
+        let mut output = String::new();
+        let _ = pipe.take(MAX_HELP_SIZE).read_to_string(&mut output);
+        let _ = sender.send(output);
+    });
+}
+
+// Lines starting with a flag, followed by its description on the same
+// line after a wide gap, or on the next, further indented line
+pub fn parse_help(text: &str) -> Vec<Flag> {
+    let text = strip_escapes(text);
+    let lines: Vec<&str> = text.lines().collect();
+    let mut flags: Vec<Flag> = Vec::new();
+
+    for (i, line) in lines.iter().enumerate() {
+        let trimmed = line.trim_start();
+        if !trimmed.starts_with('-') || trimmed.starts_with("---") {
+            continue;
+        }
+        let indent = line.len() - trimmed.len();
+        let (spec, description) = match split_description(trimmed) {
+            Some((spec, description)) => (spec, description.to_string()),
+            None => {
+                let next = lines.get(i + 1).filter(|next| {
+                    let next_trimmed = next.trim_start();
+                    !next_trimmed.is_empty()
+                        && !next_trimmed.starts_with('-')
+                        && next.len() - next_trimmed.len() > indent
+                });
+                (trimmed, next.map_or(String::new(), |next| next.trim().to_string()))
+            }
+        };
+
+        for (name, takes_value) in flag_names(spec) {
+            if !flags.iter().any(|flag| flag.name == name) {
+                flags.push(Flag {
+                    name,
+                    description: description.clone(),
+                    takes_value,
+                });
+            }
+        }
+    }
+    flags
+}
+
+// `-a, --all   do not ignore entries` splits at the first run of two or
+// more spaces (or a tab)
+fn split_description(line: &str) -> Option<(&str, &str)> {
+    let gap = line.find("  ").into_iter().chain(line.find('\t')).min()?;
+    let description = line[gap..].trim();
+    if description.is_empty() {
+        return None;
+    }
+    Some((&line[..gap], description))
+}
+
+// `-b, --block-size=SIZE` gives `-b` and `--block-size=`, `--color[=WHEN]`
+// just `--color`
+fn flag_names(spec: &str) -> Vec<(String, bool)> {
+    spec.split([',', ' ', '|'])
+        .filter(|token| token.starts_with('-') && token.len() > 1 && token != &"--")
+        .filter_map(|token| {
+            let end = token.find(['=', '[', '<', '(']).unwrap_or(token.len());
+            let name = &token[..end];
+            let valid = name.len() > 1
+                && name.trim_start_matches('-').chars().next().is_some_and(|c| c.is_ascii_alphanumeric())
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+            if !valid {
+                return None;
+            }
+            let takes_value = name.starts_with("--") && token[end..].starts_with('=');
+            Some(if takes_value { (format!("{}=", name), true) } else { (name.to_string(), false) })
+        })
+        .collect()
+}
+
+// Drop color and other CSI sequences, and backspace overstriking
+fn strip_escapes(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => {
+                if chars.peek() == Some(&'[') {
+                    chars.next();
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+            }
+            '\x08' => {
+                stripped.pop();
+            }
+            c => stripped.push(c),
+        }
+    }
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(flags: &[Flag]) -> Vec<&str> {
+        flags.iter().map(|flag| flag.name.as_str()).collect()
+    }
+
+    #[test]
+    fn parses_gnu_style_help() {
+        let help = "Usage: ls [OPTION]... [FILE]...\n\
+            \n  -a, --all                  do not ignore entries starting with .\n      \
+            --block-size=SIZE      with -l, scale sizes by SIZE\n      \
+            --color[=WHEN]         color the output WHEN\n  \
+            -1                         list one file per line\n";
+        let flags = parse_help(help);
+        assert_eq!(names(&flags), vec!["-a", "--all", "--block-size=", "--color", "-1"]);
+        assert_eq!(flags[1].description, "do not ignore entries starting with .");
+        assert!(flags[2].takes_value);
+        assert!(!flags[3].takes_value);
+    }
+
+    #[test]
+    fn parses_descriptions_on_the_next_line() {
+        let help = "Options:\n  -V, --version\n          Print version info and exit\n  \
+            -q, --quiet <WHEN>\n          Do not print cargo log messages\n  -h\n";
+        let flags = parse_help(help);
+        assert_eq!(names(&flags), vec!["-V", "--version", "-q", "--quiet", "-h"]);
+        assert_eq!(flags[0].description, "Print version info and exit");
+        assert_eq!(flags[3].description, "Do not print cargo log messages");
+        assert_eq!(flags[4].description, "");
+    }
+
+    #[test]
+    fn cache_lines_round_trip() {
+        let flag = Flag {
+            name: "--block-size=".to_string(),
+            description: "scale sizes".to_string(),
+            takes_value: true,
+        };
+        assert_eq!(Flag::from_line(flag.to_line().trim_end()), Some(flag));
+        assert_eq!(strip_escapes("\x1b[1m-a\x1b[0m b\x08b"), "-a b");
+    }
+}
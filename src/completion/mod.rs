@@ -0,0 +1,4 @@
+#[allow(clippy::module_inception)]
+pub mod completion;
+pub mod bash;
+pub mod help;
@@ -0,0 +1,309 @@
+// Settings from `~/.config/rust-terminal-emulator/config.toml`. Every key
+// is optional; a missing file or section means the defaults, while unknown
+// keys and bad values are errors naming the line they're on.
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer};
+
+use crate::command::command::ShellMode;
+use crate::history::history::DEFAULT_HISTORY_SIZE;
+use crate::prompt::prompt::PromptConfig;
+use crate::screen::screen::DEFAULT_SCROLLBACK;
+use crate::screen::scrollback::ScrollbackStorage;
+use crate::terminal::keymap::{EditMode, KeyBindings};
+use crate::theme::theme::ThemeConfig;
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub shell: ShellConfig,
+    pub prompt: PromptConfig,
+    pub theme: ThemeConfig,
+    pub editor: EditorConfig,
+    // Key sequences and the actions they're bound to, on top of the
+    // default bindings
+    pub keybindings: KeyBindings,
+    pub scrollback: ScrollbackConfig,
+    pub history: HistoryConfig,
+    pub startup: StartupConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShellConfig {
+    pub mode: ShellMode,
+    // A bash, as a name looked up in $PATH or a path
+    pub program: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EditorConfig {
+    // `emacs` or `vi`
+    pub mode: EditMode,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScrollbackConfig {
+    // Lines kept above the screen
+    pub lines: usize,
+    // Memory the scrollback may use, in bytes or with a K, M or G suffix;
+    // the oldest lines go when it's reached
+    #[serde(deserialize_with = "size")]
+    pub max_bytes: Option<usize>,
+    // `memory`, `compressed` to compress old lines, or `disk` to write them
+    // compressed to a temp file, where they don't count towards `max_bytes`
+    pub storage: ScrollbackStorage,
+    // Lines PageUp/PageDown move by; half the window when unset
+    #[serde(deserialize_with = "positive")]
+    pub scroll_step: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    // Commands kept, oldest dropped first
+    pub size: usize,
+    // In place of `~/.local/share/rust-terminal-emulator/history`
+    #[serde(deserialize_with = "path")]
+    pub file: Option<PathBuf>,
+    pub autosuggest: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StartupConfig {
+    // The logo and hints shown at startup
+    pub banner: bool,
+    // Where to start instead of the directory we were run from
+    #[serde(deserialize_with = "path")]
+    pub directory: Option<PathBuf>,
+}
+
+impl Default for ShellConfig {
+    fn default() -> Self {
+        Self {
+            mode: ShellMode::Session,
+            program: "bash".to_string(),
+        }
+    }
+}
+
+impl Default for ScrollbackConfig {
+    fn default() -> Self {
+        Self {
+            lines: DEFAULT_SCROLLBACK,
+            max_bytes: None,
+            storage: ScrollbackStorage::Memory,
+            scroll_step: None,
+        }
+    }
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            size: DEFAULT_HISTORY_SIZE,
+            file: None,
+            autosuggest: true,
+        }
+    }
+}
+
+impl Default for StartupConfig {
+    fn default() -> Self {
+        Self {
+            banner: true,
+            directory: None,
+        }
+    }
+}
+
+impl Config {
+    // `~/.config/rust-terminal-emulator/config.toml` on Linux
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("rust-terminal-emulator").join("config.toml"))
+    }
+
+    // The defaults when the file doesn't exist
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text).map_err(|e| format!("{}: {}", path.display(), e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        parse_toml(text)
+    }
+}
+
+// Errors name the line they're on, as in `line 3: unknown field `siez``
+pub fn parse_toml<T: DeserializeOwned>(text: &str) -> Result<T, String> {
+    toml::from_str(text).map_err(|e| {
+        let message = e.message().trim_end();
+        match e.span() {
+            Some(span) => format!("line {}: {}", text[..span.start].matches('\n').count() + 1, message),
+            None => message.to_string(),
+        }
+    })
+}
+
+fn positive<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<usize>, D::Error> {
+    match usize::deserialize(deserializer)? {
+        0 => Err(serde::de::Error::custom("must be at least 1")),
+        n => Ok(Some(n)),
+    }
+}
+
+// A number of bytes, or a string like `64M` with a K, M or G suffix
+fn size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<usize>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Size {
+        Bytes(usize),
+        Text(String),
+    }
+    match Size::deserialize(deserializer)? {
+        Size::Bytes(bytes) => Ok(Some(bytes)),
+        Size::Text(text) => parse_size(&text)
+            .map(Some)
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid size `{}`, expected something like `64M`", text))),
+    }
+}
+
+fn parse_size(text: &str) -> Option<usize> {
+    let text = text.trim();
+    let digits = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    let (number, unit) = text.split_at(digits);
+    let shift = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 0,
+        "K" | "KB" | "KIB" => 10,
+        "M" | "MB" | "MIB" => 20,
+        "G" | "GB" | "GIB" => 30,
+        _ => return None,
+    };
+    number.parse::<usize>().ok()?.checked_mul(1 << shift)
+}
+
+// With a leading `~/` standing for the home directory
+fn path<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<PathBuf>, D::Error> {
+    let path = String::deserialize(deserializer)?;
+    let expanded = match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ if path == "~" => dirs::home_dir().unwrap_or_else(|| PathBuf::from(&path)),
+        _ => PathBuf::from(&path),
+    };
+    Ok(Some(expanded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::screen::cell::CellColor;
+    use crate::terminal::keymap::{Action, Binding, KeyContext, KeySequence};
+    use crate::theme::depth::ColorDepth;
+
+    #[test]
+    fn missing_sections_use_defaults() {
+        assert_eq!(Config::parse(""), Ok(Config::default()));
+        let config = Config::load(Path::new("/no/such/config.toml")).unwrap();
+        assert_eq!(config.scrollback.lines, DEFAULT_SCROLLBACK);
+        assert!(config.startup.banner);
+    }
+
+    #[test]
+    fn reads_every_section() {
+        let config = Config::parse(
+            r##"
+[shell]
+mode = "per-command"
+program = "/usr/local/bin/bash"
+
+[prompt]
+left = "{bold}{cwd_short}$ "
+right = "{time}"
+
+[prompt.git]
+ahead = "⇡"
+
+[theme]
+name = "nord"
+colors = "256"
+suggestion = "#606060"
+
+[editor]
+mode = "vi"
+
+[keybindings]
+"ctrl-l" = "clear-screen"
+"ctrl-x ctrl-e" = "none"
+
+[keybindings.menu]
+"ctrl-j" = "menu-next"
+
+[keybindings.running]
+"shift-up" = "scroll-up"
+
+[scrollback]
+lines = 500
+max_bytes = "64M"
+storage = "disk"
+scroll_step = 5
+
+[history]
+size = 100
+autosuggest = false
+
+[startup]
+banner = false
+directory = "/tmp"
+"##,
+        )
+        .unwrap();
+        assert_eq!(config.shell.mode, ShellMode::PerCommand);
+        assert!(config.prompt.right.is_some());
+        assert_eq!(config.prompt.git_symbols.ahead, "⇡");
+        assert_eq!(config.prompt.git_symbols.behind, "↓");
+        assert_eq!(config.theme.name.as_deref(), Some("nord"));
+        assert_eq!(config.theme.colors, Some(ColorDepth::Ansi256));
+        assert_eq!(config.theme.suggestion, Some(CellColor::Rgb(0x60, 0x60, 0x60)));
+        assert_eq!(config.theme.error, None);
+        assert_eq!(config.editor.mode, EditMode::Vi);
+        let binding = |context, keys| config.keybindings.get(context, &KeySequence::parse(keys).unwrap());
+        assert_eq!(binding(KeyContext::Prompt, "ctrl-l"), Some(Binding(Some(Action::ClearScreen))));
+        assert_eq!(binding(KeyContext::Prompt, "ctrl-x ctrl-e"), Some(Binding(None)));
+        assert_eq!(binding(KeyContext::Menu, "ctrl-j"), Some(Binding(Some(Action::MenuNext))));
+        assert_eq!(binding(KeyContext::Running, "shift-up"), Some(Binding(Some(Action::ScrollUp))));
+        assert_eq!(binding(KeyContext::Prompt, "shift-up"), None);
+        assert_eq!(config.scrollback.max_bytes, Some(64 << 20));
+        assert_eq!(config.scrollback.storage, ScrollbackStorage::Disk);
+        assert_eq!(config.scrollback.scroll_step, Some(5));
+        assert_eq!(config.history.size, 100);
+        assert!(!config.history.autosuggest);
+        assert_eq!(config.startup.directory, Some(PathBuf::from("/tmp")));
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let error = |text: &str| Config::parse(text).unwrap_err();
+        assert_eq!(error("[shell]\nmode = \"zsh\""), "line 2: unknown variant `zsh`, expected `session` or `per-command`");
+        assert!(error("[prompt]\n\nleft = \"{nope}\"").starts_with("line 3: unknown placeholder `nope`"));
+        assert!(error("[theme]\nsuggestion = \"grey\"").starts_with("line 2: invalid color"));
+        assert!(error("[scrollback]\nscroll_step = 0").starts_with("line 2: must be at least 1"));
+        assert!(error("[scrollback]\nmax_bytes = \"lots\"").starts_with("line 2: invalid size `lots`"));
+        assert!(error("[history]\nsize = 10\nsiez = 5").starts_with("line 3: unknown field `siez`"));
+        assert!(error("[startup\n").starts_with("line 1:"));
+        assert!(error("[editor]\nmode = \"ed\"").starts_with("line 2: unknown variant `ed`"));
+        assert!(error("[keybindings]\nctrl-a = \"fly\"").starts_with("line 2: unknown action `fly`"));
+        assert!(error("[keybindings]\n\n\"hyper-a\" = \"undo\"").starts_with("line 3: unknown key `hyper-a`"));
+        assert!(error("[keybindings.menu]\ntab = \"undo\"").starts_with("line 2: `undo` doesn't work in the completion menu"));
+        assert!(error("[keybindings]\nenter = \"menu-accept\"").starts_with("line 2: `menu-accept` doesn't work at the prompt"));
+        assert!(error("[keybindings.running]\n\"ctrl-x s\" = \"scroll-up\"").starts_with("line 2: key sequences only work"));
+    }
+}
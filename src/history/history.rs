@@ -0,0 +1,354 @@
+// Command history kept in a file under the XDG data dir, so it survives
+// restarts and is shared by every running emulator
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const DEFAULT_HISTORY_SIZE: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    pub command: String,
+    // Seconds since the epoch when the command was started
+    pub timestamp: u64,
+    pub cwd: PathBuf,
+    // Unknown for entries whose command never finished (or old files)
+    pub exit_status: Option<i32>,
+    pub duration: Option<Duration>,
+}
+
+impl HistoryEntry {
+    pub fn new(command: &str, cwd: &Path) -> Self {
+        Self {
+            command: command.to_string(),
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+            cwd: cwd.to_path_buf(),
+            exit_status: None,
+            duration: None,
+        }
+    }
+
+    // One line of the history file: timestamp, duration in milliseconds,
+    // exit status, cwd and command separated by tabs, `-` for unknowns
+    fn to_line(&self) -> String {
+        let duration = self.duration.map_or("-".to_string(), |d| d.as_millis().to_string());
+        let status = self.exit_status.map_or("-".to_string(), |s| s.to_string());
+        format!(
+            "{}\t{}\t{}\t{}\t{}\n",
+            self.timestamp,
+            duration,
+            status,
+            escape(&self.cwd.to_string_lossy()),
+            escape(&self.command)
+        )
+    }
+
+    fn from_line(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(5, '\t');
+        let timestamp = fields.next()?.parse().ok()?;
+        let duration = fields.next()?;
+        let status = fields.next()?;
+        let cwd = unescape(fields.next()?);
+        let command = unescape(fields.next()?);
+        if command.is_empty() {
+            return None;
+        }
+        Some(Self {
+            command,
+            timestamp,
+            cwd: PathBuf::from(cwd),
+            exit_status: status.parse().ok(),
+            duration: duration.parse().ok().map(Duration::from_millis),
+        })
+    }
+}
+
+pub struct History {
+    entries: Vec<HistoryEntry>,
+    // None keeps the history in memory only
+    path: Option<PathBuf>,
+    limit: usize,
+    // Lines in the file since it was last compacted, as far as we know
+    written: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTORY_SIZE)
+    }
+}
+
+impl History {
+    // A history that isn't saved anywhere
+    pub fn new(limit: usize) -> Self {
+        Self {
+            entries: Vec::new(),
+            path: None,
+            limit,
+            written: 0,
+        }
+    }
+
+    // `~/.local/share/rust-terminal-emulator/history` on Linux
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("rust-terminal-emulator").join("history"))
+    }
+
+    // Load the history file at `path`, creating its directory if needed
+    pub fn open(path: &Path, limit: usize) -> io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut history = Self {
+            entries: Vec::new(),
+            path: Some(path.to_path_buf()),
+            limit,
+            written: 0,
+        };
+
+        let lines = match fs::read_to_string(path) {
+            Ok(contents) => contents.lines().filter_map(HistoryEntry::from_line).collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        history.written = lines.len();
+        history.entries = dedup(lines, limit);
+        history.compact_if_grown()?;
+        Ok(history)
+    }
+
+    pub fn entries(&self) -> &[HistoryEntry] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&HistoryEntry> {
+        self.entries.get(index)
+    }
+
+    // Record a command. An earlier run of the same command is dropped so
+    // every command appears once, at its most recent position.
+    pub fn add(&mut self, entry: HistoryEntry) -> io::Result<()> {
+        if entry.command.trim().is_empty() {
+            return Ok(());
+        }
+        self.entries.retain(|old| old.command != entry.command);
+        self.entries.push(entry);
+        if self.entries.len() > self.limit {
+            let excess = self.entries.len() - self.limit;
+            self.entries.drain(..excess);
+        }
+
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        {
+            let _lock = lock(path)?;
+            // Commands can hold secrets, so only we get to read them
+            let mut file = OpenOptions::new().create(true).append(true).mode(0o600).open(path)?;
+            // A single write on an O_APPEND file, so lines from several
+            // instances never interleave
+            if let Some(entry) = self.entries.last() {
+                file.write_all(entry.to_line().as_bytes())?;
+            }
+        }
+        self.written += 1;
+        self.compact_if_grown()
+    }
+
+    // Appends from all instances make the file grow past the limit, so
+    // once it's half as big again it's compacted
+    fn compact_if_grown(&mut self) -> io::Result<()> {
+        if self.written > self.limit + self.limit / 2 {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    // Rewrite the file with just the deduplicated, size-limited entries.
+    // Entries other instances appended since we loaded are kept.
+    pub fn compact(&mut self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let _lock = lock(path)?;
+
+        let lines = match fs::read_to_string(path) {
+            Ok(contents) => contents.lines().filter_map(HistoryEntry::from_line).collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        let entries = dedup(lines, self.limit);
+
+        // Write a new file and move it into place, so a crash halfway
+        // doesn't lose the history
+        let temp = path.with_extension(format!("tmp.{}", std::process::id()));
+        let mut file = OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(&temp)?;
+        for entry in &entries {
+            file.write_all(entry.to_line().as_bytes())?;
+        }
+        file.sync_all()?;
+        fs::rename(&temp, path)?;
+
+        self.written = entries.len();
+        self.entries = entries;
+        Ok(())
+    }
+}
+
+// Keep the last occurrence of each command, and at most `limit` entries
+fn dedup(entries: Vec<HistoryEntry>, limit: usize) -> Vec<HistoryEntry> {
+    let mut seen = std::collections::HashSet::new();
+    let mut unique: Vec<HistoryEntry> = entries
+        .into_iter()
+        .rev()
+        .filter(|entry| seen.insert(entry.command.clone()))
+        .take(limit)
+        .collect();
+    unique.reverse();
+    unique
+}
+
+// Exclusive lock on a file next to the history, held until the returned
+// file is dropped. A separate file because compaction replaces the history.
+fn lock(path: &Path) -> io::Result<File> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .mode(0o600)
+        .open(path.with_extension("lock"))?;
+    // SAFETY: flock on a descriptor we own
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(file)
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rte-history-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join("history")
+    }
+
+    fn entry(command: &str) -> HistoryEntry {
+        HistoryEntry {
+            command: command.to_string(),
+            timestamp: 1_700_000_000,
+            cwd: PathBuf::from("/tmp"),
+            exit_status: Some(0),
+            duration: Some(Duration::from_millis(12)),
+        }
+    }
+
+    fn commands(history: &History) -> Vec<&str> {
+        history.entries().iter().map(|e| e.command.as_str()).collect()
+    }
+
+    #[test]
+    fn lines_round_trip() {
+        let mut e = entry("printf 'a\\tb'\necho\tdone");
+        e.cwd = PathBuf::from("/tmp/with\ttab");
+        assert_eq!(HistoryEntry::from_line(e.to_line().trim_end_matches('\n')), Some(e.clone()));
+
+        e.exit_status = None;
+        e.duration = None;
+        assert_eq!(HistoryEntry::from_line(e.to_line().trim_end_matches('\n')), Some(e));
+    }
+
+    #[test]
+    fn saves_and_loads_with_dedup() {
+        let path = temp_path("dedup");
+        let mut history = History::open(&path, 100).unwrap();
+        for command in ["ls", "make", "ls", "git status"] {
+            history.add(entry(command)).unwrap();
+        }
+        assert_eq!(commands(&history), vec!["make", "ls", "git status"]);
+
+        // Another instance appending to the same file
+        let mut other = History::open(&path, 100).unwrap();
+        other.add(entry("make")).unwrap();
+
+        let loaded = History::open(&path, 100).unwrap();
+        assert_eq!(commands(&loaded), vec!["ls", "git status", "make"]);
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn keeps_to_the_size_limit() {
+        let path = temp_path("limit");
+        let mut history = History::open(&path, 4).unwrap();
+        for i in 0..10 {
+            history.add(entry(&format!("echo {}", i))).unwrap();
+        }
+        assert_eq!(commands(&history), vec!["echo 6", "echo 7", "echo 8", "echo 9"]);
+        // Adding compacts the file as it goes
+        assert!(fs::read_to_string(&path).unwrap().lines().count() <= 6);
+
+        // Loading trims the file back down
+        let loaded = History::open(&path, 4).unwrap();
+        assert_eq!(loaded.len(), 4);
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 4);
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn file_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = temp_path("private");
+        let mut history = History::open(&path, 4).unwrap();
+        history.add(entry("export TOKEN=secret")).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        history.compact().unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+}
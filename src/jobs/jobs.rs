@@ -0,0 +1,274 @@
+// Job table for job control: every command we start on a pseudo-terminal is
+// a job that can run in the foreground, be stopped with Ctrl+Z and moved to
+// the background with bg/fg or a trailing `&`
+use std::borrow::Cow;
+
+use crate::pty::pty::{ProcessStatus, PtyProcess};
+
+pub struct Job {
+    pub id: usize,
+    pub command: String,
+    pub process: PtyProcess,
+    // Last status we told the user about
+    pub reported: ProcessStatus,
+}
+
+#[derive(Default)]
+pub struct JobTable {
+    jobs: Vec<Job>,
+    // Job ids from least to most recently stopped or backgrounded; the last
+    // one is the current job (%+), the one before it the previous job (%-)
+    recency: Vec<usize>,
+}
+
+impl JobTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, command: &str, process: PtyProcess) -> usize {
+        let id = self.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+        self.jobs.push(Job {
+            id,
+            command: command.to_string(),
+            reported: process.status(),
+            process,
+        });
+        self.touch(id);
+        id
+    }
+
+    pub fn remove(&mut self, id: usize) -> Option<Job> {
+        self.recency.retain(|&other| other != id);
+        let index = self.jobs.iter().position(|job| job.id == id)?;
+        Some(self.jobs.remove(index))
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Job> {
+        self.jobs.iter().find(|job| job.id == id)
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Job> {
+        self.jobs.iter_mut().find(|job| job.id == id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Job> {
+        self.jobs.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Job> {
+        self.jobs.iter_mut()
+    }
+
+    pub fn ids(&self) -> Vec<usize> {
+        self.jobs.iter().map(|job| job.id).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.jobs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    // Make a job the current one
+    pub fn touch(&mut self, id: usize) {
+        self.recency.retain(|&other| other != id);
+        self.recency.push(id);
+    }
+
+    pub fn current(&self) -> Option<usize> {
+        self.recency.last().copied()
+    }
+
+    pub fn previous(&self) -> Option<usize> {
+        self.recency.iter().rev().nth(1).copied()
+    }
+
+    // Resolve a job spec as accepted by fg/bg: nothing, `%+` or `%%` for the
+    // current job, `%-` for the previous one, `%N` or `N` for a job number
+    // and `%name` for the job whose command starts with `name`
+    pub fn resolve(&self, spec: Option<&str>) -> Result<usize, String> {
+        let spec = match spec {
+            None | Some("%") | Some("%+") | Some("%%") => {
+                return self.current().ok_or_else(|| "no current job".to_string());
+            }
+            Some("%-") => {
+                return self.previous().ok_or_else(|| "no previous job".to_string());
+            }
+            Some(spec) => spec,
+        };
+
+        let name = spec.strip_prefix('%').unwrap_or(spec);
+        let found = match name.parse::<usize>() {
+            Ok(id) => self.get(id).map(|job| job.id),
+            Err(_) => self
+                .jobs
+                .iter()
+                .rev()
+                .find(|job| job.command.starts_with(name))
+                .map(|job| job.id),
+        };
+        found.ok_or_else(|| format!("{}: no such job", spec))
+    }
+
+    // A status line in the style of bash's `jobs` output
+    pub fn describe(&self, job: &Job) -> String {
+        let marker = if Some(job.id) == self.current() {
+            '+'
+        } else if Some(job.id) == self.previous() {
+            '-'
+        } else {
+            ' '
+        };
+        let status = match job.process.status() {
+            ProcessStatus::Running => "Running".to_string(),
+            ProcessStatus::Stopped => "Stopped".to_string(),
+            ProcessStatus::Exited(0) => "Done".to_string(),
+            ProcessStatus::Exited(code) => format!("Exit {}", code),
+            ProcessStatus::Signaled(signal) => signal_name(signal).into_owned(),
+        };
+        let background = if job.process.status() == ProcessStatus::Running { " &" } else { "" };
+        format!("[{}]{}  {:<24}{}{}", job.id, marker, status, job.command, background)
+    }
+
+    // Hang up every job, like a shell does when its terminal goes away
+    pub fn hangup_all(&self) {
+        for job in &self.jobs {
+            let _ = job.process.signal_group(libc::SIGHUP);
+            let _ = job.process.signal_group(libc::SIGCONT);
+        }
+    }
+}
+
+// If a command line ends with a single `&`, the command without it
+pub fn strip_background(command: &str) -> Option<&str> {
+    let trimmed = command.trim_end();
+    let rest = trimmed.strip_suffix('&')?;
+    if rest.ends_with('&') || rest.ends_with('\\') || rest.ends_with('>') || rest.ends_with('|') {
+        return None;
+    }
+    let rest = rest.trim_end();
+    if rest.is_empty() {
+        None
+    } else {
+        Some(rest)
+    }
+}
+
+// What bash calls a job killed by `signal`
+fn signal_name(signal: i32) -> Cow<'static, str> {
+    let name = match signal {
+        libc::SIGHUP => "Hangup",
+        libc::SIGINT => "Interrupt",
+        libc::SIGQUIT => "Quit",
+        libc::SIGILL => "Illegal instruction",
+        libc::SIGTRAP => "Trace/breakpoint trap",
+        libc::SIGABRT => "Aborted",
+        libc::SIGBUS => "Bus error",
+        libc::SIGFPE => "Floating point exception",
+        libc::SIGKILL => "Killed",
+        libc::SIGUSR1 => "User defined signal 1",
+        libc::SIGSEGV => "Segmentation fault",
+        libc::SIGUSR2 => "User defined signal 2",
+        libc::SIGPIPE => "Broken pipe",
+        libc::SIGALRM => "Alarm clock",
+        libc::SIGTERM => "Terminated",
+        libc::SIGXCPU => "CPU time limit exceeded",
+        libc::SIGXFSZ => "File size limit exceeded",
+        libc::SIGVTALRM => "Virtual timer expired",
+        libc::SIGPROF => "Profiling timer expired",
+        libc::SIGSYS => "Bad system call",
+        _ => return Cow::Owned(format!("Signal {}", signal)),
+    };
+    Cow::Borrowed(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    // A process that ran `command` to its end
+    fn ended(command: &str) -> PtyProcess {
+        let mut process = PtyProcess::spawn(command, 80, 24).unwrap();
+        let started = Instant::now();
+        while !process.poll_status().unwrap().is_done() {
+            assert!(started.elapsed() < Duration::from_secs(10), "`{}` didn't end", command);
+            thread::sleep(Duration::from_millis(5));
+        }
+        process
+    }
+
+    fn table() -> JobTable {
+        let mut jobs = JobTable::new();
+        jobs.add("make", ended("exit 0"));
+        jobs.add("grep x", ended("exit 2"));
+        jobs.add("sleep 30", PtyProcess::spawn("sleep 30", 80, 24).unwrap());
+        jobs.add("vim", ended("kill -TERM $$"));
+        jobs
+    }
+
+    #[test]
+    fn trailing_ampersand() {
+        assert_eq!(strip_background("sleep 5 &"), Some("sleep 5"));
+        assert_eq!(strip_background("sleep 5&  "), Some("sleep 5"));
+        assert_eq!(strip_background("sleep 5"), None);
+        assert_eq!(strip_background("&"), None);
+        assert_eq!(strip_background("make &&"), None);
+        assert_eq!(strip_background("echo \\&"), None);
+        assert_eq!(strip_background("echo hi >&"), None);
+        assert_eq!(strip_background("make |&"), None);
+    }
+
+    #[test]
+    fn job_specs() {
+        let mut jobs = table();
+        assert_eq!(jobs.resolve(None), Ok(4));
+        assert_eq!(jobs.resolve(Some("%+")), Ok(4));
+        assert_eq!(jobs.resolve(Some("%%")), Ok(4));
+        assert_eq!(jobs.resolve(Some("%-")), Ok(3));
+        assert_eq!(jobs.resolve(Some("%1")), Ok(1));
+        assert_eq!(jobs.resolve(Some("2")), Ok(2));
+        assert_eq!(jobs.resolve(Some("%gr")), Ok(2));
+        assert_eq!(jobs.resolve(Some("%sleep")), Ok(3));
+        assert_eq!(jobs.resolve(Some("%9")), Err("%9: no such job".to_string()));
+        assert_eq!(jobs.resolve(Some("%top")), Err("%top: no such job".to_string()));
+
+        jobs.touch(1);
+        assert_eq!((jobs.resolve(None), jobs.resolve(Some("%-"))), (Ok(1), Ok(4)));
+        jobs.remove(1);
+        assert_eq!((jobs.resolve(None), jobs.resolve(Some("%-"))), (Ok(4), Ok(3)));
+        jobs.hangup_all();
+
+        let empty = JobTable::new();
+        assert_eq!(empty.resolve(None), Err("no current job".to_string()));
+        assert_eq!(empty.resolve(Some("%-")), Err("no previous job".to_string()));
+    }
+
+    #[test]
+    fn status_lines() {
+        let jobs = table();
+        let lines: Vec<String> = jobs.iter().map(|job| jobs.describe(job)).collect();
+        jobs.hangup_all();
+        assert_eq!(
+            lines,
+            [
+                "[1]   Done                    make",
+                "[2]   Exit 2                  grep x",
+                "[3]-  Running                 sleep 30 &",
+                "[4]+  Terminated              vim",
+            ]
+        );
+    }
+
+    #[test]
+    fn signal_names() {
+        assert_eq!(signal_name(libc::SIGKILL), "Killed");
+        assert_eq!(signal_name(libc::SIGUSR1), "User defined signal 1");
+        assert_eq!(signal_name(libc::SIGFPE), "Floating point exception");
+        assert_eq!(signal_name(libc::SIGRTMIN() + 1), format!("Signal {}", libc::SIGRTMIN() + 1));
+    }
+}
@@ -1,2 +1,12 @@
 pub mod terminal;
-pub mod command;
\ No newline at end of file
+pub mod command;
+pub mod pty;
+pub mod parser;
+pub mod screen;
+pub mod jobs;
+pub mod session;
+pub mod history;
+pub mod completion;
+pub mod prompt;
+pub mod config;
+pub mod theme;
@@ -1,7 +1,7 @@
 // filepath: /rust-terminal-emulator/rust-terminal-emulator/src/main.rs
 
 use rust_terminal_emulator::terminal::terminal::Terminal;
-use std::{io, process};
+use std::io;
 
 fn main() -> io::Result<()> {
     // Create and initialize the terminal
@@ -14,6 +14,9 @@ fn main() -> io::Result<()> {
         if !terminal.process_keyboard_input()? {
             break;
         }
+        
+        // Pick up output from whatever is running
+        terminal.process_output()?;
     }
     
     // Clean up terminal state
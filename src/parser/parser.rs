@@ -0,0 +1,833 @@
+// ANSI/VT escape sequence parser.
+//
+// This is a byte-oriented state machine modeled on the DEC VT500 parser
+// (https://vt100.net/emu/dec_ansi_parser). It doesn't know anything about
+// screens or colors: it turns the raw output of a child process into typed
+// actions that the screen model can apply.
+
+// Upper bounds so a misbehaving program can't make us buffer forever
+const MAX_PARAMS: usize = 32;
+const MAX_SUBPARAMS: usize = 16;
+const MAX_INTERMEDIATES: usize = 2;
+const MAX_STRING_LEN: usize = 64 * 1024;
+
+// Numeric parameters of a CSI or DCS sequence. Each parameter is a group of
+// colon separated sub-parameters (e.g. `38:2:255:0:0`), most only have one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Params {
+    groups: Vec<Vec<u16>>,
+}
+
+impl Params {
+    pub fn len(&self) -> usize {
+        self.groups.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    // First value of the parameter at `index`, if it was given
+    pub fn get(&self, index: usize) -> Option<u16> {
+        self.groups.get(index).and_then(|group| group.first().copied())
+    }
+
+    // Parameter at `index`, with missing or zero values replaced by `default`
+    // (the usual convention for counts and positions)
+    pub fn get_or(&self, index: usize, default: u16) -> u16 {
+        match self.get(index) {
+            Some(0) | None => default,
+            Some(value) => value,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &[u16]> {
+        self.groups.iter().map(|group| group.as_slice())
+    }
+}
+
+impl<const N: usize> From<[&[u16]; N]> for Params {
+    fn from(groups: [&[u16]; N]) -> Self {
+        Self {
+            groups: groups.iter().map(|group| group.to_vec()).collect(),
+        }
+    }
+}
+
+// Everything the parser can ask the terminal to do
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    // Display a character at the cursor
+    Print(char),
+    // Run a C0 control function (BEL, BS, HT, LF, CR...)
+    Execute(u8),
+    // A complete control sequence, e.g. `ESC [ 1 ; 31 m`
+    CsiDispatch {
+        params: Params,
+        intermediates: Vec<u8>,
+        action: char,
+    },
+    // A complete escape sequence, e.g. `ESC 7` or `ESC ( B`
+    EscDispatch {
+        intermediates: Vec<u8>,
+        byte: u8,
+    },
+    // An operating system command, split on `;` (e.g. `0`, `window title`)
+    OscDispatch {
+        params: Vec<Vec<u8>>,
+        bell_terminated: bool,
+    },
+    // A device control string with its payload
+    DcsDispatch {
+        params: Params,
+        intermediates: Vec<u8>,
+        action: char,
+        data: Vec<u8>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    EscapeIntermediate,
+    CsiEntry,
+    CsiParam,
+    CsiIntermediate,
+    CsiIgnore,
+    DcsEntry,
+    DcsParam,
+    DcsIntermediate,
+    DcsPassthrough,
+    DcsIgnore,
+    OscString,
+    SosPmApcString,
+}
+
+pub struct Parser {
+    state: State,
+    intermediates: Vec<u8>,
+    params: Vec<Vec<u16>>,
+    // Set when a sequence overflowed our limits and must not be dispatched
+    ignoring: bool,
+    string: Vec<u8>,
+    dcs_action: char,
+    // Partially received UTF-8 character
+    utf8: [u8; 4],
+    utf8_len: usize,
+    utf8_needed: usize,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Self {
+            state: State::Ground,
+            intermediates: Vec::new(),
+            params: Vec::new(),
+            ignoring: false,
+            string: Vec::new(),
+            dcs_action: '\0',
+            utf8: [0; 4],
+            utf8_len: 0,
+            utf8_needed: 0,
+        }
+    }
+
+    // Feed a chunk of output. State is kept between calls, so sequences
+    // (and UTF-8 characters) may be split across chunks.
+    pub fn parse(&mut self, bytes: &[u8]) -> Vec<Action> {
+        let mut actions = Vec::new();
+        for &byte in bytes {
+            self.advance(byte, &mut actions);
+        }
+        actions
+    }
+
+    fn advance(&mut self, byte: u8, actions: &mut Vec<Action>) {
+        // Finish (or abandon) a multi-byte character first
+        if self.utf8_needed > 0 {
+            if byte & 0xC0 == 0x80 {
+                self.utf8[self.utf8_len] = byte;
+                self.utf8_len += 1;
+                if self.utf8_len == self.utf8_needed {
+                    self.flush_utf8(actions);
+                }
+                return;
+            }
+            actions.push(Action::Print(char::REPLACEMENT_CHARACTER));
+            self.utf8_len = 0;
+            self.utf8_needed = 0;
+        }
+
+        // Transitions that apply from any state
+        match byte {
+            0x18 | 0x1A => {
+                actions.push(Action::Execute(byte));
+                self.state = State::Ground;
+                return;
+            }
+            0x1B => {
+                self.finish_string(false, actions);
+                self.enter_escape();
+                return;
+            }
+            _ => {}
+        }
+
+        match self.state {
+            State::Ground => self.ground(byte, actions),
+            State::Escape => self.escape(byte, actions),
+            State::EscapeIntermediate => self.escape_intermediate(byte, actions),
+            State::CsiEntry => self.csi_entry(byte, actions),
+            State::CsiParam => self.csi_param(byte, actions),
+            State::CsiIntermediate => self.csi_intermediate(byte, actions),
+            State::CsiIgnore => self.csi_ignore(byte, actions),
+            State::DcsEntry => self.dcs_entry(byte),
+            State::DcsParam => self.dcs_param(byte),
+            State::DcsIntermediate => self.dcs_intermediate(byte),
+            State::DcsPassthrough => self.put_string(byte),
+            State::DcsIgnore => {}
+            State::OscString => self.osc_string(byte, actions),
+            State::SosPmApcString => {}
+        }
+    }
+
+    fn ground(&mut self, byte: u8, actions: &mut Vec<Action>) {
+        match byte {
+            0x00..=0x1F => actions.push(Action::Execute(byte)),
+            0x20..=0x7E => actions.push(Action::Print(byte as char)),
+            0x7F => {}
+            _ => self.start_utf8(byte, actions),
+        }
+    }
+
+    fn escape(&mut self, byte: u8, actions: &mut Vec<Action>) {
+        match byte {
+            0x00..=0x1F => actions.push(Action::Execute(byte)),
+            0x20..=0x2F => {
+                self.collect(byte);
+                self.state = State::EscapeIntermediate;
+            }
+            b'[' => self.state = State::CsiEntry,
+            b']' => {
+                self.string.clear();
+                self.state = State::OscString;
+            }
+            b'P' => self.state = State::DcsEntry,
+            b'X' | b'^' | b'_' => self.state = State::SosPmApcString,
+            // A lone string terminator (the tail of `ESC \`) has nothing to do
+            b'\\' => self.state = State::Ground,
+            0x30..=0x7E => {
+                self.esc_dispatch(byte, actions);
+                self.state = State::Ground;
+            }
+            _ => {}
+        }
+    }
+
+    fn escape_intermediate(&mut self, byte: u8, actions: &mut Vec<Action>) {
+        match byte {
+            0x00..=0x1F => actions.push(Action::Execute(byte)),
+            0x20..=0x2F => self.collect(byte),
+            0x30..=0x7E => {
+                self.esc_dispatch(byte, actions);
+                self.state = State::Ground;
+            }
+            _ => {}
+        }
+    }
+
+    fn csi_entry(&mut self, byte: u8, actions: &mut Vec<Action>) {
+        match byte {
+            0x00..=0x1F => actions.push(Action::Execute(byte)),
+            0x20..=0x2F => {
+                self.collect(byte);
+                self.state = State::CsiIntermediate;
+            }
+            b'0'..=b'9' | b':' | b';' => {
+                self.param(byte);
+                self.state = State::CsiParam;
+            }
+            // Private markers like `?` in `CSI ? 25 h`
+            0x3C..=0x3F => {
+                self.collect(byte);
+                self.state = State::CsiParam;
+            }
+            0x40..=0x7E => {
+                self.csi_dispatch(byte, actions);
+                self.state = State::Ground;
+            }
+            _ => {}
+        }
+    }
+
+    fn csi_param(&mut self, byte: u8, actions: &mut Vec<Action>) {
+        match byte {
+            0x00..=0x1F => actions.push(Action::Execute(byte)),
+            b'0'..=b'9' | b':' | b';' => self.param(byte),
+            0x3C..=0x3F => self.state = State::CsiIgnore,
+            0x20..=0x2F => {
+                self.collect(byte);
+                self.state = State::CsiIntermediate;
+            }
+            0x40..=0x7E => {
+                self.csi_dispatch(byte, actions);
+                self.state = State::Ground;
+            }
+            _ => {}
+        }
+    }
+
+    fn csi_intermediate(&mut self, byte: u8, actions: &mut Vec<Action>) {
+        match byte {
+            0x00..=0x1F => actions.push(Action::Execute(byte)),
+            0x20..=0x2F => self.collect(byte),
+            0x30..=0x3F => self.state = State::CsiIgnore,
+            0x40..=0x7E => {
+                self.csi_dispatch(byte, actions);
+                self.state = State::Ground;
+            }
+            _ => {}
+        }
+    }
+
+    fn csi_ignore(&mut self, byte: u8, actions: &mut Vec<Action>) {
+        match byte {
+            0x00..=0x1F => actions.push(Action::Execute(byte)),
+            0x40..=0x7E => self.state = State::Ground,
+            _ => {}
+        }
+    }
+
+    fn dcs_entry(&mut self, byte: u8) {
+        match byte {
+            0x20..=0x2F => {
+                self.collect(byte);
+                self.state = State::DcsIntermediate;
+            }
+            b'0'..=b'9' | b':' | b';' => {
+                self.param(byte);
+                self.state = State::DcsParam;
+            }
+            0x3C..=0x3F => {
+                self.collect(byte);
+                self.state = State::DcsParam;
+            }
+            0x40..=0x7E => self.dcs_hook(byte),
+            _ => {}
+        }
+    }
+
+    fn dcs_param(&mut self, byte: u8) {
+        match byte {
+            b'0'..=b'9' | b':' | b';' => self.param(byte),
+            0x3C..=0x3F => self.state = State::DcsIgnore,
+            0x20..=0x2F => {
+                self.collect(byte);
+                self.state = State::DcsIntermediate;
+            }
+            0x40..=0x7E => self.dcs_hook(byte),
+            _ => {}
+        }
+    }
+
+    fn dcs_intermediate(&mut self, byte: u8) {
+        match byte {
+            0x20..=0x2F => self.collect(byte),
+            0x30..=0x3F => self.state = State::DcsIgnore,
+            0x40..=0x7E => self.dcs_hook(byte),
+            _ => {}
+        }
+    }
+
+    fn osc_string(&mut self, byte: u8, actions: &mut Vec<Action>) {
+        match byte {
+            // BEL is the xterm-style terminator
+            0x07 => {
+                self.finish_string(true, actions);
+                self.state = State::Ground;
+            }
+            0x00..=0x1F => {}
+            _ => self.put_string(byte),
+        }
+    }
+
+    fn enter_escape(&mut self) {
+        self.intermediates.clear();
+        self.params.clear();
+        self.ignoring = false;
+        self.state = State::Escape;
+    }
+
+    fn collect(&mut self, byte: u8) {
+        if self.intermediates.len() < MAX_INTERMEDIATES {
+            self.intermediates.push(byte);
+        } else {
+            self.ignoring = true;
+        }
+    }
+
+    fn param(&mut self, byte: u8) {
+        if self.params.is_empty() {
+            self.params.push(vec![0]);
+        }
+
+        match byte {
+            b';' => {
+                if self.params.len() < MAX_PARAMS {
+                    self.params.push(vec![0]);
+                } else {
+                    self.ignoring = true;
+                }
+            }
+            b':' => match self.params.last_mut() {
+                Some(group) if group.len() < MAX_SUBPARAMS => group.push(0),
+                _ => self.ignoring = true,
+            },
+            digit => {
+                if let Some(value) = self.params.last_mut().and_then(|group| group.last_mut()) {
+                    *value = value.saturating_mul(10).saturating_add((digit - b'0') as u16);
+                }
+            }
+        }
+    }
+
+    fn take_params(&mut self) -> Params {
+        Params {
+            groups: std::mem::take(&mut self.params),
+        }
+    }
+
+    fn csi_dispatch(&mut self, byte: u8, actions: &mut Vec<Action>) {
+        let params = self.take_params();
+        let intermediates = std::mem::take(&mut self.intermediates);
+        if self.ignoring {
+            return;
+        }
+        actions.push(Action::CsiDispatch {
+            params,
+            intermediates,
+            action: byte as char,
+        });
+    }
+
+    fn esc_dispatch(&mut self, byte: u8, actions: &mut Vec<Action>) {
+        let intermediates = std::mem::take(&mut self.intermediates);
+        if self.ignoring {
+            return;
+        }
+        actions.push(Action::EscDispatch { intermediates, byte });
+    }
+
+    fn dcs_hook(&mut self, byte: u8) {
+        self.dcs_action = byte as char;
+        self.string.clear();
+        self.state = State::DcsPassthrough;
+    }
+
+    fn put_string(&mut self, byte: u8) {
+        if self.string.len() < MAX_STRING_LEN {
+            self.string.push(byte);
+        }
+    }
+
+    // Called when a string state (OSC or DCS) is terminated by BEL or ESC
+    fn finish_string(&mut self, bell_terminated: bool, actions: &mut Vec<Action>) {
+        match self.state {
+            State::OscString => {
+                let data = std::mem::take(&mut self.string);
+                let params = data.split(|&b| b == b';').map(|p| p.to_vec()).collect();
+                actions.push(Action::OscDispatch {
+                    params,
+                    bell_terminated,
+                });
+            }
+            State::DcsPassthrough => {
+                let params = self.take_params();
+                let intermediates = std::mem::take(&mut self.intermediates);
+                let data = std::mem::take(&mut self.string);
+                if !self.ignoring {
+                    actions.push(Action::DcsDispatch {
+                        params,
+                        intermediates,
+                        action: self.dcs_action,
+                        data,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn start_utf8(&mut self, byte: u8, actions: &mut Vec<Action>) {
+        let needed = match byte {
+            0xC2..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF4 => 4,
+            // Stray continuation bytes and invalid lead bytes
+            _ => {
+                actions.push(Action::Print(char::REPLACEMENT_CHARACTER));
+                return;
+            }
+        };
+        self.utf8[0] = byte;
+        self.utf8_len = 1;
+        self.utf8_needed = needed;
+    }
+
+    fn flush_utf8(&mut self, actions: &mut Vec<Action>) {
+        let c = std::str::from_utf8(&self.utf8[..self.utf8_len])
+            .ok()
+            .and_then(|s| s.chars().next())
+            .unwrap_or(char::REPLACEMENT_CHARACTER);
+        actions.push(Action::Print(c));
+        self.utf8_len = 0;
+        self.utf8_needed = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(bytes: &[u8]) -> Vec<Action> {
+        Parser::new().parse(bytes)
+    }
+
+    fn csi(params: Params, intermediates: &[u8], action: char) -> Action {
+        Action::CsiDispatch {
+            params,
+            intermediates: intermediates.to_vec(),
+            action,
+        }
+    }
+
+    #[test]
+    fn prints_plain_text() {
+        assert_eq!(
+            parse(b"hi!"),
+            vec![Action::Print('h'), Action::Print('i'), Action::Print('!')]
+        );
+    }
+
+    #[test]
+    fn executes_c0_controls() {
+        assert_eq!(
+            parse(b"a\r\n\x07\x08\t"),
+            vec![
+                Action::Print('a'),
+                Action::Execute(b'\r'),
+                Action::Execute(b'\n'),
+                Action::Execute(0x07),
+                Action::Execute(0x08),
+                Action::Execute(b'\t'),
+            ]
+        );
+    }
+
+    #[test]
+    fn ignores_del_in_ground() {
+        assert_eq!(parse(b"a\x7fb"), vec![Action::Print('a'), Action::Print('b')]);
+    }
+
+    #[test]
+    fn decodes_utf8() {
+        assert_eq!(
+            parse("é█🦀".as_bytes()),
+            vec![Action::Print('é'), Action::Print('█'), Action::Print('🦀')]
+        );
+    }
+
+    #[test]
+    fn decodes_utf8_split_across_chunks() {
+        let mut parser = Parser::new();
+        let bytes = "🦀".as_bytes();
+        assert!(parser.parse(&bytes[..1]).is_empty());
+        assert!(parser.parse(&bytes[1..3]).is_empty());
+        assert_eq!(parser.parse(&bytes[3..]), vec![Action::Print('🦀')]);
+    }
+
+    #[test]
+    fn replaces_invalid_utf8() {
+        assert_eq!(
+            parse(b"\xffa\xc3(\x80"),
+            vec![
+                Action::Print(char::REPLACEMENT_CHARACTER),
+                Action::Print('a'),
+                Action::Print(char::REPLACEMENT_CHARACTER),
+                Action::Print('('),
+                Action::Print(char::REPLACEMENT_CHARACTER),
+            ]
+        );
+    }
+
+    #[test]
+    fn truncated_utf8_before_control() {
+        assert_eq!(
+            parse(b"\xe2\x94\n"),
+            vec![Action::Print(char::REPLACEMENT_CHARACTER), Action::Execute(b'\n')]
+        );
+    }
+
+    #[test]
+    fn csi_without_params() {
+        assert_eq!(parse(b"\x1b[H"), vec![csi(Params::default(), b"", 'H')]);
+    }
+
+    #[test]
+    fn csi_with_params() {
+        assert_eq!(
+            parse(b"\x1b[1;31m"),
+            vec![csi(Params::from([&[1][..], &[31]]), b"", 'm')]
+        );
+    }
+
+    #[test]
+    fn csi_empty_params_default_to_zero() {
+        let actions = parse(b"\x1b[;5H");
+        assert_eq!(actions, vec![csi(Params::from([&[0][..], &[5]]), b"", 'H')]);
+        if let Action::CsiDispatch { params, .. } = &actions[0] {
+            assert_eq!(params.get_or(0, 1), 1);
+            assert_eq!(params.get_or(1, 1), 5);
+            assert_eq!(params.get(2), None);
+        }
+    }
+
+    #[test]
+    fn csi_private_marker() {
+        assert_eq!(
+            parse(b"\x1b[?1049h"),
+            vec![csi(Params::from([&[1049][..]]), b"?", 'h')]
+        );
+    }
+
+    #[test]
+    fn csi_intermediates() {
+        assert_eq!(parse(b"\x1b[!p"), vec![csi(Params::default(), b"!", 'p')]);
+        assert_eq!(
+            parse(b"\x1b[2 q"),
+            vec![csi(Params::from([&[2][..]]), b" ", 'q')]
+        );
+    }
+
+    #[test]
+    fn csi_subparams() {
+        assert_eq!(
+            parse(b"\x1b[38:2:10:20:30;1m"),
+            vec![csi(Params::from([&[38, 2, 10, 20, 30][..], &[1]]), b"", 'm')]
+        );
+    }
+
+    #[test]
+    fn csi_param_overflow_saturates() {
+        assert_eq!(
+            parse(b"\x1b[999999A"),
+            vec![csi(Params::from([&[u16::MAX][..]]), b"", 'A')]
+        );
+    }
+
+    #[test]
+    fn csi_too_many_params_is_ignored() {
+        let mut bytes = b"\x1b[".to_vec();
+        bytes.extend(std::iter::repeat_n(b"1;", MAX_PARAMS + 1).flatten());
+        bytes.push(b'm');
+        bytes.push(b'x');
+        assert_eq!(parse(&bytes), vec![Action::Print('x')]);
+    }
+
+    #[test]
+    fn csi_too_many_subparams_is_ignored() {
+        let mut bytes = b"\x1b[38".to_vec();
+        bytes.extend(std::iter::repeat_n(b":1", MAX_SUBPARAMS).flatten());
+        bytes.extend(b"mx");
+        assert_eq!(parse(&bytes), vec![Action::Print('x')]);
+
+        // An endless run of colons doesn't grow the group
+        let mut parser = Parser::new();
+        parser.parse(b"\x1b[");
+        parser.parse(&[b':'; 100_000]);
+        assert_eq!(parser.params[0].len(), MAX_SUBPARAMS);
+    }
+
+    #[test]
+    fn csi_malformed_is_ignored() {
+        // A private marker after parameters puts the sequence in csi-ignore
+        assert_eq!(parse(b"\x1b[1?hX"), vec![Action::Print('X')]);
+    }
+
+    #[test]
+    fn c0_inside_csi_is_executed() {
+        assert_eq!(
+            parse(b"\x1b[1\n;2H"),
+            vec![
+                Action::Execute(b'\n'),
+                csi(Params::from([&[1][..], &[2]]), b"", 'H'),
+            ]
+        );
+    }
+
+    #[test]
+    fn csi_split_across_chunks() {
+        let mut parser = Parser::new();
+        assert!(parser.parse(b"\x1b[3").is_empty());
+        assert!(parser.parse(b"8;5;2").is_empty());
+        assert_eq!(
+            parser.parse(b"08m"),
+            vec![csi(Params::from([&[38][..], &[5], &[208]]), b"", 'm')]
+        );
+    }
+
+    #[test]
+    fn cancel_aborts_sequence() {
+        assert_eq!(
+            parse(b"\x1b[31\x18a"),
+            vec![Action::Execute(0x18), Action::Print('a')]
+        );
+    }
+
+    #[test]
+    fn escape_restarts_sequence() {
+        assert_eq!(
+            parse(b"\x1b[31\x1b[0m"),
+            vec![csi(Params::from([&[0][..]]), b"", 'm')]
+        );
+    }
+
+    #[test]
+    fn esc_dispatch() {
+        assert_eq!(
+            parse(b"\x1b7\x1b8\x1bM"),
+            vec![
+                Action::EscDispatch { intermediates: vec![], byte: b'7' },
+                Action::EscDispatch { intermediates: vec![], byte: b'8' },
+                Action::EscDispatch { intermediates: vec![], byte: b'M' },
+            ]
+        );
+    }
+
+    #[test]
+    fn esc_dispatch_with_intermediate() {
+        assert_eq!(
+            parse(b"\x1b(B\x1b#8"),
+            vec![
+                Action::EscDispatch { intermediates: vec![b'('], byte: b'B' },
+                Action::EscDispatch { intermediates: vec![b'#'], byte: b'8' },
+            ]
+        );
+    }
+
+    #[test]
+    fn osc_terminated_by_bell() {
+        assert_eq!(
+            parse(b"\x1b]0;my title\x07x"),
+            vec![
+                Action::OscDispatch {
+                    params: vec![b"0".to_vec(), b"my title".to_vec()],
+                    bell_terminated: true,
+                },
+                Action::Print('x'),
+            ]
+        );
+    }
+
+    #[test]
+    fn osc_terminated_by_st() {
+        assert_eq!(
+            parse(b"\x1b]7;file://host/tmp\x1b\\x"),
+            vec![
+                Action::OscDispatch {
+                    params: vec![b"7".to_vec(), b"file://host/tmp".to_vec()],
+                    bell_terminated: false,
+                },
+                Action::Print('x'),
+            ]
+        );
+    }
+
+    #[test]
+    fn osc_keeps_utf8_bytes() {
+        assert_eq!(
+            parse("\x1b]2;café\x07".as_bytes()),
+            vec![Action::OscDispatch {
+                params: vec![b"2".to_vec(), "café".as_bytes().to_vec()],
+                bell_terminated: true,
+            }]
+        );
+    }
+
+    #[test]
+    fn osc_with_empty_params() {
+        assert_eq!(
+            parse(b"\x1b]133;D;\x07"),
+            vec![Action::OscDispatch {
+                params: vec![b"133".to_vec(), b"D".to_vec(), b"".to_vec()],
+                bell_terminated: true,
+            }]
+        );
+    }
+
+    #[test]
+    fn osc_cancelled() {
+        assert_eq!(parse(b"\x1b]0;abc\x18d"), vec![Action::Execute(0x18), Action::Print('d')]);
+    }
+
+    #[test]
+    fn dcs_with_payload() {
+        assert_eq!(
+            parse(b"\x1bP1$qm\x1b\\"),
+            vec![Action::DcsDispatch {
+                params: Params::from([&[1][..]]),
+                intermediates: vec![b'$'],
+                action: 'q',
+                data: b"m".to_vec(),
+            }]
+        );
+    }
+
+    #[test]
+    fn dcs_payload_keeps_controls() {
+        assert_eq!(
+            parse(b"\x1bPqab\ncd\x1b\\"),
+            vec![Action::DcsDispatch {
+                params: Params::default(),
+                intermediates: vec![],
+                action: 'q',
+                data: b"ab\ncd".to_vec(),
+            }]
+        );
+    }
+
+    #[test]
+    fn dcs_invalid_is_ignored() {
+        assert_eq!(parse(b"\x1bP1?x\x1b\\y"), vec![Action::Print('y')]);
+    }
+
+    #[test]
+    fn sos_pm_apc_strings_are_ignored() {
+        assert_eq!(
+            parse(b"\x1bXsos\x1b\\\x1b^pm\x1b\\\x1b_apc\x07\x1b\\z"),
+            vec![Action::Print('z')]
+        );
+    }
+
+    #[test]
+    fn mixed_stream() {
+        assert_eq!(
+            parse(b"\x1b[32mok\x1b[0m\r\n"),
+            vec![
+                csi(Params::from([&[32][..]]), b"", 'm'),
+                Action::Print('o'),
+                Action::Print('k'),
+                csi(Params::from([&[0][..]]), b"", 'm'),
+                Action::Execute(b'\r'),
+                Action::Execute(b'\n'),
+            ]
+        );
+    }
+}
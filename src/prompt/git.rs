@@ -0,0 +1,328 @@
+// The `{git}` prompt segment: branch, ahead/behind counts and the state of
+// the work tree. `git status` can take a while in a big repository, so it
+// runs on a worker thread and the prompt keeps the last result until a
+// new one arrives.
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GitStatus {
+    // The branch name, or the abbreviated commit when detached
+    pub branch: String,
+    pub detached: bool,
+    pub ahead: usize,
+    pub behind: usize,
+    pub staged: usize,
+    // Changed in the work tree but not staged
+    pub modified: usize,
+    pub untracked: usize,
+    pub conflicted: usize,
+}
+
+// What goes before each part of the segment
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GitSymbols {
+    pub branch: String,
+    pub detached: String,
+    pub ahead: String,
+    pub behind: String,
+    pub staged: String,
+    pub modified: String,
+    pub untracked: String,
+    pub conflicted: String,
+    // Shown instead of the counts when nothing changed
+    pub clean: String,
+}
+
+impl Default for GitSymbols {
+    fn default() -> Self {
+        Self {
+            branch: String::new(),
+            detached: "@".to_string(),
+            ahead: "↑".to_string(),
+            behind: "↓".to_string(),
+            staged: "+".to_string(),
+            modified: "!".to_string(),
+            untracked: "?".to_string(),
+            conflicted: "=".to_string(),
+            clean: String::new(),
+        }
+    }
+}
+
+impl GitSymbols {
+    // The defaults with `name=symbol` pairs separated by commas applied,
+    // as in `ahead=⇡,behind=⇣,clean=✓`
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut symbols = Self::default();
+        for pair in spec.split(',').filter(|pair| !pair.trim().is_empty()) {
+            let (name, symbol) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("expected `name=symbol`, found `{}`", pair))?;
+            symbols.set(name.trim(), symbol)?;
+        }
+        Ok(symbols)
+    }
+
+    pub fn set(&mut self, name: &str, symbol: &str) -> Result<(), String> {
+        let slot = match name {
+            "branch" => &mut self.branch,
+            "detached" => &mut self.detached,
+            "ahead" => &mut self.ahead,
+            "behind" => &mut self.behind,
+            "staged" => &mut self.staged,
+            "modified" => &mut self.modified,
+            "untracked" => &mut self.untracked,
+            "conflicted" => &mut self.conflicted,
+            "clean" => &mut self.clean,
+            _ => return Err(format!("unknown git symbol `{}`", name)),
+        };
+        *slot = symbol.to_string();
+        Ok(())
+    }
+}
+
+impl GitStatus {
+    // `main ↑1↓2 +3!1?4`
+    pub fn format(&self, symbols: &GitSymbols) -> String {
+        let prefix = if self.detached { &symbols.detached } else { &symbols.branch };
+        let mut segment = format!("{}{}", prefix, self.branch);
+
+        if self.ahead > 0 || self.behind > 0 {
+            segment.push(' ');
+            for (count, symbol) in [(self.ahead, &symbols.ahead), (self.behind, &symbols.behind)] {
+                if count > 0 {
+                    segment.push_str(&format!("{}{}", symbol, count));
+                }
+            }
+        }
+
+        let changes: String = [
+            (self.conflicted, &symbols.conflicted),
+            (self.staged, &symbols.staged),
+            (self.modified, &symbols.modified),
+            (self.untracked, &symbols.untracked),
+        ]
+        .iter()
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, symbol)| format!("{}{}", symbol, count))
+        .collect();
+        let changes = if changes.is_empty() { &symbols.clean } else { &changes };
+        if !changes.is_empty() {
+            segment.push(' ');
+            segment.push_str(changes);
+        }
+        segment
+    }
+}
+
+// Output of `git status --porcelain=v2 --branch`
+pub fn parse_status(output: &str) -> GitStatus {
+    let mut status = GitStatus::default();
+    let mut oid = "";
+    for line in output.lines() {
+        if let Some(header) = line.strip_prefix("# ") {
+            let (key, value) = header.split_once(' ').unwrap_or((header, ""));
+            match key {
+                "branch.oid" => oid = value,
+                "branch.head" => {
+                    status.detached = value == "(detached)";
+                    status.branch = value.to_string();
+                }
+                "branch.ab" => {
+                    for count in value.split_whitespace() {
+                        if let Some(ahead) = count.strip_prefix('+') {
+                            status.ahead = ahead.parse().unwrap_or(0);
+                        } else if let Some(behind) = count.strip_prefix('-') {
+                            status.behind = behind.parse().unwrap_or(0);
+                        }
+                    }
+                }
+                _ => {}
+            }
+            continue;
+        }
+        let mut fields = line.splitn(3, ' ');
+        match (fields.next(), fields.next()) {
+            // Ordinary and renamed entries, with the index and work tree
+            // states in `XY`
+            (Some("1" | "2"), Some(xy)) => {
+                let mut states = xy.chars();
+                if states.next().is_some_and(|x| x != '.') {
+                    status.staged += 1;
+                }
+                if states.next().is_some_and(|y| y != '.') {
+                    status.modified += 1;
+                }
+            }
+            (Some("u"), _) => status.conflicted += 1,
+            (Some("?"), _) => status.untracked += 1,
+            _ => {}
+        }
+    }
+    if status.detached {
+        status.branch = oid.chars().take(7).collect();
+    }
+    status
+}
+
+// The status of the work tree `dir` is in, or None outside of one
+fn query(dir: &Path) -> Option<GitStatus> {
+    let output = Command::new("git")
+        .args(["status", "--porcelain=v2", "--branch"])
+        .current_dir(dir)
+        // Don't take locks a command run meanwhile could trip over
+        .env("GIT_OPTIONAL_LOCKS", "0")
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(parse_status(&String::from_utf8_lossy(&output.stdout)))
+}
+
+pub struct GitPrompt {
+    requests: mpsc::Sender<PathBuf>,
+    results: mpsc::Receiver<(PathBuf, Option<GitStatus>)>,
+    // The last directory asked about
+    requested: Option<PathBuf>,
+    // The last result and the directory it's for
+    status: Option<(PathBuf, GitStatus)>,
+}
+
+impl Default for GitPrompt {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GitPrompt {
+    pub fn new() -> Self {
+        let (requests, worker_requests) = mpsc::channel::<PathBuf>();
+        let (worker_results, results) = mpsc::channel();
+        thread::spawn(move || {
+            while let Ok(mut dir) = worker_requests.recv() {
+                // Only the newest of the requests that piled up matters
+                while let Ok(newer) = worker_requests.try_recv() {
+                    dir = newer;
+                }
+                let status = query(&dir);
+                if worker_results.send((dir, status)).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            requests,
+            results,
+            requested: None,
+            status: None,
+        }
+    }
+
+    // Ask for a fresh status of `dir`, say after a command ran in it
+    pub fn refresh(&mut self, dir: &Path) {
+        self.requested = Some(dir.to_path_buf());
+        let _ = self.requests.send(dir.to_path_buf());
+    }
+
+    // Take the results that arrived, asking about `cwd` first if it's new.
+    // Returns whether the status shown for `cwd` changed.
+    pub fn update(&mut self, cwd: &Path) -> bool {
+        if self.requested.as_deref() != Some(cwd) {
+            self.refresh(cwd);
+        }
+        let before = self.status(cwd).cloned();
+        while let Ok((dir, status)) = self.results.try_recv() {
+            self.status = status.map(|status| (dir, status));
+        }
+        self.status(cwd) != before.as_ref()
+    }
+
+    // The last known status for `cwd`, if it's in a work tree
+    pub fn status(&self, cwd: &Path) -> Option<&GitStatus> {
+        self.status
+            .as_ref()
+            .filter(|(dir, _)| dir == cwd)
+            .map(|(_, status)| status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn parses_porcelain_status() {
+        let output = "# branch.oid 1234567890abcdef\n# branch.head main\n# branch.upstream origin/main\n\
+            # branch.ab +2 -1\n1 M. N... 100644 100644 100644 a b src/a.rs\n1 .M N... 100644 100644 100644 a b b.rs\n\
+            1 MM N... 100644 100644 100644 a b c.rs\n2 R. N... 100644 100644 100644 a b R100 new\told\n\
+            u UU N... 100644 100644 100644 100644 a b c d.rs\n? notes.txt\n? tmp/\n";
+        let status = parse_status(output);
+        assert_eq!(
+            status,
+            GitStatus {
+                branch: "main".to_string(),
+                detached: false,
+                ahead: 2,
+                behind: 1,
+                staged: 3,
+                modified: 2,
+                untracked: 2,
+                conflicted: 1,
+            }
+        );
+        assert_eq!(status.format(&GitSymbols::default()), "main ↑2↓1 =1+3!2?2");
+
+        let detached = parse_status("# branch.oid 1234567890abcdef\n# branch.head (detached)\n");
+        assert_eq!(detached.format(&GitSymbols::default()), "@1234567");
+    }
+
+    #[test]
+    fn symbols_are_configurable() {
+        let symbols = GitSymbols::parse("ahead=⇡, clean=✓,branch= ").unwrap();
+        let status = GitStatus {
+            branch: "dev".to_string(),
+            ahead: 1,
+            ..GitStatus::default()
+        };
+        assert_eq!(status.format(&symbols), " dev ⇡1 ✓");
+        assert!(GitSymbols::parse("dirty=*").is_err());
+        assert!(GitSymbols::parse("ahead").is_err());
+    }
+
+    #[test]
+    fn status_arrives_from_the_worker() {
+        let dir = std::env::temp_dir().join(format!("rte-git-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let initialized = Command::new("git")
+            .args(["init", "-q", "-b", "trunk"])
+            .current_dir(&dir)
+            .status()
+            .is_ok_and(|status| status.success());
+        if !initialized {
+            let _ = std::fs::remove_dir_all(&dir);
+            return;
+        }
+        std::fs::write(dir.join("file"), "x").unwrap();
+
+        let mut git = GitPrompt::new();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !git.update(&dir) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let status = git.status(&dir).cloned();
+        let _ = std::fs::remove_dir_all(&dir);
+        let status = status.expect("no status from the worker");
+        assert_eq!(status.branch, "trunk");
+        assert_eq!(status.untracked, 1);
+    }
+}
@@ -0,0 +1,585 @@
+// Prompts written as templates such as `{green}{user}@{host}({cwd}): `.
+// `{name}` is replaced by a value, `{red}`, `{bold}` or `{fg:208}` change
+// the style of what follows, `{?name:...}` shows its contents only when
+// `name` is set (a failed command, any jobs, a slow command) and
+// `{!name:...}` only when it isn't. `{{` and `}}` are literal braces.
+use std::env;
+use std::path::{Component, Path};
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use super::git::GitSymbols;
+use crate::screen::cell::{Attributes, CellColor};
+
+pub const DEFAULT_PROMPT: &str = "{user}@{host}({cwd}){?git: {magenta}{git}{reset}}: ";
+pub const DEFAULT_CONTINUATION_PROMPT: &str = "> ";
+
+// Commands that ran at least this long set `{?duration:...}`
+const SLOW_COMMAND: Duration = Duration::from_secs(2);
+
+const COLOR_NAMES: &[&str] = &["black", "red", "green", "yellow", "blue", "magenta", "cyan", "white"];
+
+pub type StyledText = Vec<(char, Attributes)>;
+
+// What the placeholders are filled in from
+#[derive(Debug, Clone)]
+pub struct PromptContext<'a> {
+    pub user: &'a str,
+    pub host: &'a str,
+    pub cwd: &'a Path,
+    pub home: Option<&'a Path>,
+    // Exit status of the last command
+    pub status: i32,
+    // How long the last command ran
+    pub duration: Option<Duration>,
+    pub jobs: usize,
+    // Local time as hours, minutes and seconds
+    pub time: (u32, u32, u32),
+    // The git segment, inside a work tree
+    pub git: Option<String>,
+    // `insert`, `normal` or `visual` in vi mode, empty otherwise
+    pub vi_mode: &'a str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Variable {
+    User,
+    Host,
+    // The full path
+    Cwd,
+    // With the home directory as `~`
+    CwdHome,
+    // `~/s/rust-terminal-emulator`: every directory but the last cut to
+    // its first letter
+    CwdShort,
+    // Just the last directory
+    Dir,
+    Status,
+    Duration,
+    Time,
+    Jobs,
+    Git,
+    ViMode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Style {
+    Reset,
+    Bold,
+    Dim,
+    Italic,
+    Underline,
+    Inverse,
+    Foreground(CellColor),
+    Background(CellColor),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Text(String),
+    Variable(Variable),
+    Style(Style),
+    // The body is shown when the variable is set, or unset when negated
+    Conditional {
+        variable: Variable,
+        negated: bool,
+        body: Vec<Segment>,
+    },
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct PromptTemplate {
+    segments: Vec<Segment>,
+}
+
+impl TryFrom<String> for PromptTemplate {
+    type Error = String;
+
+    fn try_from(template: String) -> Result<Self, String> {
+        Self::parse(&template)
+    }
+}
+
+impl PromptTemplate {
+    // Errors name the column they were found at
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let chars: Vec<char> = template.chars().collect();
+        let mut pos = 0;
+        let segments = parse_segments(&chars, &mut pos, None)?;
+        Ok(Self { segments })
+    }
+
+    // Text starts out in `base`, the theme's prompt color, and `{reset}`
+    // goes back to it
+    pub fn render(&self, context: &PromptContext, base: Attributes) -> StyledText {
+        let mut out = Vec::new();
+        let mut attrs = base;
+        render_segments(&self.segments, context, base, &mut attrs, &mut out);
+        out
+    }
+}
+
+// The left prompt, an optional one drawn at the right edge of the first
+// input line, the one for continuation lines and the symbols `{git}` uses;
+// the `[prompt]` section of the config file
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PromptConfig {
+    pub left: PromptTemplate,
+    pub right: Option<PromptTemplate>,
+    pub continuation: PromptTemplate,
+    #[serde(rename = "git")]
+    pub git_symbols: GitSymbols,
+}
+
+impl Default for PromptConfig {
+    fn default() -> Self {
+        Self {
+            left: PromptTemplate::parse(DEFAULT_PROMPT).unwrap_or_default(),
+            right: None,
+            continuation: PromptTemplate::parse(DEFAULT_CONTINUATION_PROMPT).unwrap_or_default(),
+            git_symbols: GitSymbols::default(),
+        }
+    }
+}
+
+impl PromptConfig {
+    pub fn new(left: &str, right: Option<&str>, continuation: &str) -> Result<Self, String> {
+        let parse = |name: &str, template: &str| PromptTemplate::parse(template).map_err(|e| format!("{}: {}", name, e));
+        Ok(Self {
+            left: parse("prompt", left)?,
+            right: right.filter(|r| !r.is_empty()).map(|r| parse("right prompt", r)).transpose()?,
+            continuation: parse("continuation prompt", continuation)?,
+            git_symbols: GitSymbols::default(),
+        })
+    }
+
+    // Templates from $RTE_PROMPT, $RTE_RPROMPT and $RTE_PS2, and git
+    // symbols from $RTE_GIT_SYMBOLS, where set, in place of the configured
+    // ones
+    pub fn apply_env(&mut self) -> Result<(), String> {
+        let template = |var: &str, name: &str| {
+            env::var(var)
+                .ok()
+                .map(|template| PromptTemplate::parse(&template).map_err(|e| format!("${} ({}): {}", var, name, e)))
+                .transpose()
+        };
+        if let Some(left) = template("RTE_PROMPT", "prompt")? {
+            self.left = left;
+        }
+        if let Some(right) = template("RTE_RPROMPT", "right prompt")? {
+            self.right = Some(right).filter(|right| right != &PromptTemplate::default());
+        }
+        if let Some(continuation) = template("RTE_PS2", "continuation prompt")? {
+            self.continuation = continuation;
+        }
+        if let Ok(spec) = env::var("RTE_GIT_SYMBOLS") {
+            self.git_symbols = GitSymbols::parse(&spec).map_err(|e| format!("$RTE_GIT_SYMBOLS: {}", e))?;
+        }
+        Ok(())
+    }
+}
+
+// Segments up to the end of the template, or up to the `}` closing the
+// conditional opened at column `open`
+fn parse_segments(chars: &[char], pos: &mut usize, open: Option<usize>) -> Result<Vec<Segment>, String> {
+    let mut segments = Vec::new();
+    let mut text = String::new();
+
+    while *pos < chars.len() {
+        let c = chars[*pos];
+        match c {
+            '{' if chars.get(*pos + 1) == Some(&'{') => {
+                text.push('{');
+                *pos += 2;
+            }
+            // Inside a conditional a `}` always closes it
+            '}' if open.is_some() => {
+                *pos += 1;
+                if !text.is_empty() {
+                    segments.push(Segment::Text(text));
+                }
+                return Ok(segments);
+            }
+            '}' if chars.get(*pos + 1) == Some(&'}') => {
+                text.push('}');
+                *pos += 2;
+            }
+            '}' => return Err(format!("unmatched `}}` at column {}", *pos + 1)),
+            '{' => {
+                if !text.is_empty() {
+                    segments.push(Segment::Text(std::mem::take(&mut text)));
+                }
+                let start = *pos;
+                *pos += 1;
+                if let Some(&marker @ ('?' | '!')) = chars.get(*pos) {
+                    *pos += 1;
+                    let name_end = chars[*pos..].iter().position(|&c| c == ':' || c == '}').map(|p| *pos + p);
+                    let Some(name_end) = name_end.filter(|&end| chars[end] == ':') else {
+                        return Err(format!("expected `:` in the condition at column {}", start + 1));
+                    };
+                    let name: String = chars[*pos..name_end].iter().collect();
+                    let variable = Variable::from_name(&name)
+                        .ok_or_else(|| format!("unknown placeholder `{}` at column {}", name, start + 1))?;
+                    *pos = name_end + 1;
+                    let body = parse_segments(chars, pos, Some(start))?;
+                    segments.push(Segment::Conditional {
+                        variable,
+                        negated: marker == '!',
+                        body,
+                    });
+                    continue;
+                }
+                let Some(end) = chars[*pos..].iter().position(|&c| c == '}').map(|p| *pos + p) else {
+                    return Err(format!("unclosed `{{` at column {}", start + 1));
+                };
+                let name: String = chars[*pos..end].iter().collect();
+                *pos = end + 1;
+                segments.push(directive(name.trim()).map_err(|e| format!("{} at column {}", e, start + 1))?);
+            }
+            c => {
+                text.push(c);
+                *pos += 1;
+            }
+        }
+    }
+
+    if let Some(open) = open {
+        return Err(format!("unclosed condition at column {}", open + 1));
+    }
+    if !text.is_empty() {
+        segments.push(Segment::Text(text));
+    }
+    Ok(segments)
+}
+
+fn directive(name: &str) -> Result<Segment, String> {
+    if let Some(variable) = Variable::from_name(name) {
+        return Ok(Segment::Variable(variable));
+    }
+    let style = match name {
+        "reset" => Style::Reset,
+        "bold" => Style::Bold,
+        "dim" => Style::Dim,
+        "italic" => Style::Italic,
+        "underline" => Style::Underline,
+        "inverse" => Style::Inverse,
+        _ => {
+            if let Some(color) = name.strip_prefix("fg:") {
+                Style::Foreground(parse_color(color)?)
+            } else if let Some(color) = name.strip_prefix("bg:") {
+                Style::Background(parse_color(color)?)
+            } else if let Ok(color) = parse_color(name) {
+                Style::Foreground(color)
+            } else {
+                return Err(format!("unknown placeholder `{}`", name));
+            }
+        }
+    };
+    Ok(Segment::Style(style))
+}
+
+// A color name (`red`, `bright_blue`, `default`), a palette index or
+// `#rrggbb`
+pub fn parse_color(name: &str) -> Result<CellColor, String> {
+    let name = name.trim();
+    if name == "default" {
+        return Ok(CellColor::Default);
+    }
+    if let Some(hex) = name.strip_prefix('#') {
+        let channel = |i: usize| hex.get(i..i + 2).and_then(|c| u8::from_str_radix(c, 16).ok());
+        return match (hex.len(), channel(0), channel(2), channel(4)) {
+            (6, Some(r), Some(g), Some(b)) => Ok(CellColor::Rgb(r, g, b)),
+            _ => Err(format!("invalid color `{}`", name)),
+        };
+    }
+    if let Ok(index) = name.parse::<u8>() {
+        return Ok(CellColor::Indexed(index));
+    }
+    let (base, bright) = match name.strip_prefix("bright_") {
+        Some(base) => (base, true),
+        None => (name, false),
+    };
+    COLOR_NAMES
+        .iter()
+        .position(|&color| color == base)
+        .map(|index| CellColor::Indexed(index as u8 + if bright { 8 } else { 0 }))
+        .ok_or_else(|| format!("invalid color `{}`", name))
+}
+
+impl Variable {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "user" => Variable::User,
+            "host" => Variable::Host,
+            "cwd" => Variable::Cwd,
+            "cwd_home" => Variable::CwdHome,
+            "cwd_short" => Variable::CwdShort,
+            "dir" => Variable::Dir,
+            "status" => Variable::Status,
+            "duration" => Variable::Duration,
+            "time" => Variable::Time,
+            "jobs" => Variable::Jobs,
+            "git" => Variable::Git,
+            "vi_mode" => Variable::ViMode,
+            _ => return None,
+        })
+    }
+
+    fn value(self, context: &PromptContext) -> String {
+        match self {
+            Variable::User => context.user.to_string(),
+            Variable::Host => context.host.to_string(),
+            Variable::Cwd => context.cwd.display().to_string(),
+            Variable::CwdHome => home_relative(context.cwd, context.home),
+            Variable::CwdShort => shorten(&home_relative(context.cwd, context.home)),
+            Variable::Dir => {
+                let path = home_relative(context.cwd, context.home);
+                match path.rsplit_once('/') {
+                    Some((_, last)) if !last.is_empty() => last.to_string(),
+                    _ => path,
+                }
+            }
+            Variable::Status => context.status.to_string(),
+            Variable::Duration => context.duration.map(format_duration).unwrap_or_default(),
+            Variable::Time => {
+                let (hours, minutes, seconds) = context.time;
+                format!("{:02}:{:02}:{:02}", hours, minutes, seconds)
+            }
+            Variable::Jobs => context.jobs.to_string(),
+            Variable::Git => context.git.clone().unwrap_or_default(),
+            Variable::ViMode => context.vi_mode.to_string(),
+        }
+    }
+
+    fn is_set(self, context: &PromptContext) -> bool {
+        match self {
+            Variable::Status => context.status != 0,
+            Variable::Duration => context.duration.is_some_and(|d| d >= SLOW_COMMAND),
+            Variable::Jobs => context.jobs > 0,
+            _ => !self.value(context).is_empty(),
+        }
+    }
+}
+
+fn render_segments(segments: &[Segment], context: &PromptContext, base: Attributes, attrs: &mut Attributes, out: &mut StyledText) {
+    for segment in segments {
+        match segment {
+            Segment::Text(text) => out.extend(text.chars().map(|c| (c, *attrs))),
+            Segment::Variable(variable) => out.extend(
+                variable
+                    .value(context)
+                    .chars()
+                    .filter(|c| !c.is_control())
+                    .map(|c| (c, *attrs)),
+            ),
+            Segment::Style(style) => match *style {
+                Style::Reset => *attrs = base,
+                Style::Bold => attrs.bold = true,
+                Style::Dim => attrs.dim = true,
+                Style::Italic => attrs.italic = true,
+                Style::Underline => attrs.underline = true,
+                Style::Inverse => attrs.inverse = true,
+                Style::Foreground(color) => attrs.fg = color,
+                Style::Background(color) => attrs.bg = color,
+            },
+            Segment::Conditional { variable, negated, body } => {
+                if variable.is_set(context) != *negated {
+                    render_segments(body, context, base, attrs, out);
+                }
+            }
+        }
+    }
+}
+
+fn home_relative(cwd: &Path, home: Option<&Path>) -> String {
+    match home.and_then(|home| cwd.strip_prefix(home).ok()) {
+        Some(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+        Some(rest) => format!("~/{}", rest.display()),
+        None => cwd.display().to_string(),
+    }
+}
+
+// Every directory but the last cut to its first letter, keeping the dot
+// of hidden ones
+fn shorten(path: &str) -> String {
+    let components: Vec<Component> = Path::new(path).components().collect();
+    let last = components.len().saturating_sub(1);
+    let mut short = String::new();
+    for (i, component) in components.iter().enumerate() {
+        match component {
+            Component::RootDir => short.push('/'),
+            Component::Normal(name) => {
+                let name = name.to_string_lossy();
+                if i == last || name == "~" {
+                    short.push_str(&name);
+                } else {
+                    let len = if name.starts_with('.') { 2 } else { 1 };
+                    short.extend(name.chars().take(len));
+                }
+                if i != last {
+                    short.push('/');
+                }
+            }
+            other => {
+                short.push_str(&other.as_os_str().to_string_lossy());
+                if i != last {
+                    short.push('/');
+                }
+            }
+        }
+    }
+    short
+}
+
+// `850ms`, `4.2s`, `3m12s`, `1h05m`
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs < 1 {
+        format!("{}ms", duration.as_millis())
+    } else if secs < 60 {
+        format!("{:.1}s", duration.as_secs_f64())
+    } else if secs < 3600 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h{:02}m", secs / 3600, secs % 3600 / 60)
+    }
+}
+
+pub fn local_time() -> (u32, u32, u32) {
+    // SAFETY: time accepts a null pointer, and localtime_r only writes to
+    // the tm it's given
+    unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&now, &mut tm).is_null() {
+            return (0, 0, 0);
+        }
+        (tm.tm_hour as u32, tm.tm_min as u32, tm.tm_sec as u32)
+    }
+}
+
+// The prompt as text with SGR sequences, for echoing into the screen
+pub fn to_ansi(text: &[(char, Attributes)]) -> String {
+    let mut ansi = String::new();
+    let mut current = Attributes::default();
+    for &(c, attrs) in text {
+        if attrs != current {
+            ansi.push_str(&sgr(&attrs));
+            current = attrs;
+        }
+        ansi.push(c);
+    }
+    if current != Attributes::default() {
+        ansi.push_str("\x1b[0m");
+    }
+    ansi
+}
+
+fn sgr(attrs: &Attributes) -> String {
+    let mut codes = vec!["0".to_string()];
+    for (on, code) in [
+        (attrs.bold, "1"),
+        (attrs.dim, "2"),
+        (attrs.italic, "3"),
+        (attrs.underline, "4"),
+        (attrs.inverse, "7"),
+    ] {
+        if on {
+            codes.push(code.to_string());
+        }
+    }
+    for (color, base) in [(attrs.fg, 30), (attrs.bg, 40)] {
+        match color {
+            CellColor::Default => {}
+            CellColor::Indexed(n) if n < 8 => codes.push((base + n as u32).to_string()),
+            CellColor::Indexed(n) if n < 16 => codes.push((base + 60 + n as u32 - 8).to_string()),
+            CellColor::Indexed(n) => codes.push(format!("{};5;{}", base + 8, n)),
+            CellColor::Rgb(r, g, b) => codes.push(format!("{};2;{};{};{}", base + 8, r, g, b)),
+        }
+    }
+    format!("\x1b[{}m", codes.join(";"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(status: i32, jobs: usize) -> PromptContext<'static> {
+        PromptContext {
+            user: "ann",
+            host: "box",
+            cwd: Path::new("/home/ann/src/rust-terminal-emulator"),
+            home: Some(Path::new("/home/ann")),
+            status,
+            duration: Some(Duration::from_millis(4200)),
+            jobs,
+            time: (9, 5, 0),
+            git: None,
+            vi_mode: "",
+        }
+    }
+
+    fn text(template: &str, context: &PromptContext) -> String {
+        let styled = PromptTemplate::parse(template).unwrap().render(context, Attributes::default());
+        styled.into_iter().map(|(c, _)| c).collect()
+    }
+
+    #[test]
+    fn fills_in_placeholders() {
+        let context = context(0, 0);
+        assert_eq!(text(DEFAULT_PROMPT, &context), "ann@box(/home/ann/src/rust-terminal-emulator): ");
+        assert_eq!(text("{cwd_home} {cwd_short} {dir}", &context), "~/src/rust-terminal-emulator ~/s/rust-terminal-emulator rust-terminal-emulator");
+        assert_eq!(text("[{time}] {duration} {{x}}", &context), "[09:05:00] 4.2s {x}");
+        assert_eq!(shorten("/usr/.local/share"), "/u/.l/share");
+        assert_eq!(home_relative(Path::new("/home/ann"), Some(Path::new("/home/ann"))), "~");
+    }
+
+    #[test]
+    fn conditional_segments() {
+        let template = "{?status:[{status}] }{?jobs:{jobs}& }{!status:ok }{?duration:took {duration}}";
+        assert_eq!(text(template, &context(0, 0)), "ok took 4.2s");
+        assert_eq!(text(template, &context(127, 2)), "[127] 2& took 4.2s");
+        let in_repo = PromptContext {
+            git: Some("main +1".to_string()),
+            ..context(0, 0)
+        };
+        assert_eq!(text(DEFAULT_PROMPT, &in_repo), "ann@box(/home/ann/src/rust-terminal-emulator) main +1: ");
+        let vi = PromptContext {
+            vi_mode: "normal",
+            ..context(0, 0)
+        };
+        assert_eq!(text("{?vi_mode:[{vi_mode}] }$ ", &vi), "[normal] $ ");
+        assert_eq!(text("{?vi_mode:[{vi_mode}] }$ ", &context(0, 0)), "$ ");
+    }
+
+    #[test]
+    fn styles_apply_to_what_follows() {
+        let base = Attributes {
+            fg: CellColor::Indexed(2),
+            ..Attributes::default()
+        };
+        let styled = PromptTemplate::parse("{bold}{fg:#ff8000}a{reset}{bright_blue}{bg:236}b{reset}c")
+            .unwrap()
+            .render(&context(0, 0), base);
+        assert!(styled[0].1.bold);
+        assert_eq!(styled[0].1.fg, CellColor::Rgb(255, 128, 0));
+        assert!(!styled[1].1.bold);
+        assert_eq!(styled[2].1, base);
+        assert_eq!(styled[1].1.fg, CellColor::Indexed(12));
+        assert_eq!(styled[1].1.bg, CellColor::Indexed(236));
+        assert_eq!(to_ansi(&styled), "\x1b[0;1;38;2;255;128;0ma\x1b[0;94;48;5;236mb\x1b[0;32mc\x1b[0m");
+    }
+
+    #[test]
+    fn reports_errors_with_columns() {
+        assert_eq!(PromptTemplate::parse("ab{nope}"), Err("unknown placeholder `nope` at column 3".to_string()));
+        assert_eq!(PromptTemplate::parse("{user"), Err("unclosed `{` at column 1".to_string()));
+        assert_eq!(PromptTemplate::parse("x{?status:y"), Err("unclosed condition at column 2".to_string()));
+        assert_eq!(PromptTemplate::parse("a}"), Err("unmatched `}` at column 2".to_string()));
+        assert!(PromptTemplate::parse("{fg:#12}").is_err());
+        assert!(PromptConfig::new(DEFAULT_PROMPT, Some("{time}"), DEFAULT_CONTINUATION_PROMPT).is_ok());
+    }
+}
@@ -0,0 +1,540 @@
+// Pseudo-terminal support: allocates a master/slave pair and runs child
+// processes on the slave side so they see a real tty (colors, pagers, editors)
+use std::env;
+use std::ffi::{CString, OsStr};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How long to keep collecting output after the child exited while something
+// it left behind (a background process) still holds the terminal open
+const EXIT_GRACE_PERIOD: Duration = Duration::from_millis(100);
+
+// Linux numbers its signals, real-time ones included, from 1 to 64
+const LAST_SIGNAL: i32 = 64;
+
+// A freshly allocated pseudo-terminal pair
+struct PtyPair {
+    master: File,
+    slave: File,
+}
+
+impl PtyPair {
+    fn open() -> io::Result<Self> {
+        // SAFETY: plain libc calls, every returned descriptor is checked and
+        // immediately wrapped in a File so it gets closed on error paths
+        unsafe {
+            let master_fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC);
+            if master_fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let master = File::from_raw_fd(master_fd);
+
+            if libc::grantpt(master_fd) != 0 || libc::unlockpt(master_fd) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            // Look up the path of the slave device (/dev/pts/N)
+            let mut name = [0 as libc::c_char; 128];
+            let err = libc::ptsname_r(master_fd, name.as_mut_ptr(), name.len());
+            if err != 0 {
+                return Err(io::Error::from_raw_os_error(err));
+            }
+
+            let slave_fd = libc::open(name.as_ptr(), libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC);
+            if slave_fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let slave = File::from_raw_fd(slave_fd);
+
+            Ok(Self { master, slave })
+        }
+    }
+}
+
+// Tell the kernel (and therefore the child) how big the terminal is
+fn set_window_size(fd: RawFd, cols: u16, rows: u16) -> io::Result<()> {
+    let size = libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: TIOCSWINSZ only reads the winsize struct we pass in
+    if unsafe { libc::ioctl(fd, libc::TIOCSWINSZ, &size) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Pipe whose ends are closed on exec
+fn pipe() -> io::Result<(File, File)> {
+    let mut fds = [0; 2];
+    // SAFETY: pipe2 fills in the two descriptors, which we take ownership of
+    unsafe {
+        if libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])))
+    }
+}
+
+fn set_nonblocking(fd: RawFd) -> io::Result<()> {
+    // SAFETY: F_GETFL/F_SETFL on a descriptor we own
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        if flags == -1 || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+fn find_in_path(program: &str) -> PathBuf {
+    env::var_os("PATH")
+        .and_then(|paths| {
+            env::split_paths(&paths)
+                .map(|dir| dir.join(program))
+                .find(|path| path.metadata().is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0))
+        })
+        .unwrap_or_else(|| PathBuf::from("/bin").join(program))
+}
+
+fn c_string(bytes: &[u8]) -> io::Result<CString> {
+    CString::new(bytes).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "command contains a NUL byte"))
+}
+
+// Runs in the forked child and never returns. Only async-signal-safe calls
+// are allowed here, so everything it needs is prepared before the fork.
+//
+// The child becomes the session leader of the new terminal and starts the
+// job in a process group of its own, then relays the job's wait statuses
+// (exited, stopped, continued) back to us over the status pipe. Running the
+// job directly as the session leader would leave it in an orphaned process
+// group, and the kernel silently drops Ctrl+Z (SIGTSTP) for those.
+unsafe fn run_session_leader(
+    slave: RawFd,
+    status: RawFd,
+    program: &CString,
+    argv: &[*const libc::c_char],
+    envp: &[*const libc::c_char],
+    no_signals: &libc::sigset_t,
+) -> ! {
+    if libc::setsid() == -1 || libc::ioctl(slave, libc::TIOCSCTTY, 0) == -1 {
+        libc::_exit(1);
+    }
+
+    let job = libc::fork();
+    if job == 0 {
+        // Take over the terminal's foreground before running the command
+        libc::setpgid(0, 0);
+        libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+        libc::tcsetpgrp(slave, libc::getpid());
+        libc::signal(libc::SIGTTOU, libc::SIG_DFL);
+
+        libc::dup2(slave, 0);
+        libc::dup2(slave, 1);
+        libc::dup2(slave, 2);
+
+        // Ignored signals stay ignored across exec, and Rust ignores
+        // SIGPIPE, which would turn `yes | head -1` into a write error.
+        // Start the command with every signal at its default and none
+        // blocked, as a shell would. glibc won't touch the signals it
+        // reserves for itself, so this goes to the kernel directly; a
+        // zeroed kernel sigaction is SIG_DFL with no flags or mask.
+        let default = [0u64; 4];
+        for signal in 1..=LAST_SIGNAL {
+            if signal != libc::SIGKILL && signal != libc::SIGSTOP {
+                libc::syscall(libc::SYS_rt_sigaction, signal, default.as_ptr(), std::ptr::null_mut::<u64>(), 8);
+            }
+        }
+        libc::sigprocmask(libc::SIG_SETMASK, no_signals, std::ptr::null_mut());
+        libc::execve(program.as_ptr(), argv.as_ptr(), envp.as_ptr());
+        libc::_exit(127);
+    }
+
+    // Tell our parent which process the job is (-1 if it couldn't start)
+    let pid = if job > 0 { job } else { -1 };
+    libc::setpgid(job, job);
+    libc::write(status, pid.to_ne_bytes().as_ptr().cast(), 4);
+    if job < 0 {
+        libc::_exit(1);
+    }
+
+    // Let go of everything else, the terminal included, so reads on the
+    // master report EOF once the job and its children are gone
+    for fd in 0..1024 {
+        if fd != status {
+            libc::close(fd);
+        }
+    }
+
+    loop {
+        let mut raw = 0;
+        if libc::waitpid(job, &mut raw, libc::WUNTRACED | libc::WCONTINUED) == -1 {
+            if *libc::__errno_location() == libc::EINTR {
+                continue;
+            }
+            libc::_exit(0);
+        }
+        libc::write(status, raw.to_ne_bytes().as_ptr().cast(), 4);
+        if libc::WIFEXITED(raw) || libc::WIFSIGNALED(raw) {
+            libc::_exit(0);
+        }
+    }
+}
+
+// Read the master side on a background thread so a slow or chatty child
+// never blocks the UI. The channel disconnects once the slave side closes.
+fn spawn_reader(mut master: File) -> Receiver<Vec<u8>> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buf = [0u8; 8192];
+        loop {
+            match master.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    if tx.send(buf[..n].to_vec()).is_err() {
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                // Linux reports EIO on the master after the last slave fd is closed
+                Err(_) => break,
+            }
+        }
+    });
+    rx
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessStatus {
+    Running,
+    // Suspended by a job control signal (Ctrl+Z)
+    Stopped,
+    Exited(i32),
+    // Killed by the given signal
+    Signaled(i32),
+}
+
+impl ProcessStatus {
+    pub fn is_done(&self) -> bool {
+        matches!(self, ProcessStatus::Exited(_) | ProcessStatus::Signaled(_))
+    }
+
+    // Exit code the way shells report it ($?)
+    pub fn code(&self) -> Option<i32> {
+        match self {
+            ProcessStatus::Exited(code) => Some(*code),
+            ProcessStatus::Signaled(signal) => Some(128 + signal),
+            _ => None,
+        }
+    }
+}
+
+// A child process running on the slave side of a pseudo-terminal.
+// We keep the master side to feed it input; its output arrives through
+// the reader thread. The child runs in a process group of its own, so its
+// pid is also the id of the group job control signals go to.
+pub struct PtyProcess {
+    master: File,
+    pid: libc::pid_t,
+    // Session leader between us and the child, see run_session_leader
+    leader: libc::pid_t,
+    leader_reaped: bool,
+    status_pipe: File,
+    status: ProcessStatus,
+    output: Receiver<Vec<u8>>,
+    closed: bool,
+    exited_at: Option<Instant>,
+}
+
+impl PtyProcess {
+    // Run a command line through `bash -c` on a new pseudo-terminal
+    pub fn spawn(command: &str, cols: u16, rows: u16) -> io::Result<Self> {
+        Self::spawn_in("bash", command, cols, rows)
+    }
+
+    // Run a command line through `<shell> -c`, with the shell a name looked
+    // up in $PATH or a path
+    pub fn spawn_in(shell: &str, command: &str, cols: u16, rows: u16) -> io::Result<Self> {
+        let pair = PtyPair::open()?;
+        set_window_size(pair.master.as_raw_fd(), cols, rows)?;
+
+        // Prepare everything the forked child needs
+        let program = if shell.contains('/') { PathBuf::from(shell) } else { find_in_path(shell) };
+        let name = program.file_name().map_or(shell.as_bytes(), |name| name.as_bytes());
+        let args = [c_string(name)?, c_string(b"-c")?, c_string(command.as_bytes())?];
+        let program = c_string(program.as_os_str().as_bytes())?;
+        let mut vars = Vec::new();
+        for (key, value) in env::vars_os() {
+            // Advertise what the screen model understands
+            if key != OsStr::new("TERM") {
+                vars.push(c_string(&[key.as_bytes(), b"=", value.as_bytes()].concat())?);
+            }
+        }
+        vars.push(c_string(b"TERM=xterm-256color")?);
+        let argv: Vec<*const libc::c_char> =
+            args.iter().map(|arg| arg.as_ptr()).chain(std::iter::once(std::ptr::null())).collect();
+        let envp: Vec<*const libc::c_char> =
+            vars.iter().map(|var| var.as_ptr()).chain(std::iter::once(std::ptr::null())).collect();
+
+        let (mut status_pipe, status_write) = pipe()?;
+        // SAFETY: sigemptyset initializes the set it's given
+        let no_signals = unsafe {
+            let mut set = std::mem::zeroed();
+            libc::sigemptyset(&mut set);
+            set
+        };
+
+        // SAFETY: the child only makes async-signal-safe calls, see above
+        let leader = unsafe { libc::fork() };
+        if leader == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        if leader == 0 {
+            unsafe {
+                run_session_leader(
+                    pair.slave.as_raw_fd(),
+                    status_write.as_raw_fd(),
+                    &program,
+                    &argv,
+                    &envp,
+                    &no_signals,
+                )
+            }
+        }
+
+        // Drop our copies of the slave so reads on the master report EOF
+        // once the child (and anything it started) is gone
+        drop(pair.slave);
+        drop(status_write);
+
+        // The first message on the status pipe is the job's pid
+        let mut pid = [0u8; 4];
+        status_pipe.read_exact(&mut pid)?;
+        let pid = libc::pid_t::from_ne_bytes(pid);
+        if pid <= 0 {
+            return Err(io::Error::other("failed to start the command"));
+        }
+        set_nonblocking(status_pipe.as_raw_fd())?;
+
+        let output = spawn_reader(pair.master.try_clone()?);
+
+        Ok(Self {
+            master: pair.master,
+            pid,
+            leader,
+            leader_reaped: false,
+            status_pipe,
+            status: ProcessStatus::Running,
+            output,
+            closed: false,
+            exited_at: None,
+        })
+    }
+
+    // Everything the child has written since the last call, without waiting
+    pub fn take_output(&mut self) -> Vec<u8> {
+        let mut output = Vec::new();
+        loop {
+            match self.output.try_recv() {
+                Ok(chunk) => output.extend_from_slice(&chunk),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.closed = true;
+                    break;
+                }
+            }
+        }
+        output
+    }
+
+    pub fn pid(&self) -> i32 {
+        self.pid
+    }
+
+    // Status as of the last poll
+    pub fn status(&self) -> ProcessStatus {
+        self.status
+    }
+
+    // Check (without blocking) whether the child exited, stopped or continued
+    pub fn poll_status(&mut self) -> io::Result<ProcessStatus> {
+        // Statuses arrive from the session leader as raw waitpid values
+        let mut buf = [0u8; 64];
+        while !self.status.is_done() {
+            let n = match self.status_pipe.read(&mut buf) {
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if n == 0 {
+                // The session leader went away without telling us how the
+                // job ended, so it must have been killed along with it
+                self.status = ProcessStatus::Signaled(libc::SIGKILL);
+                break;
+            }
+            for raw in buf[..n].chunks_exact(4) {
+                self.status = decode_status(i32::from_ne_bytes([raw[0], raw[1], raw[2], raw[3]]));
+            }
+        }
+
+        if self.status.is_done() {
+            if self.exited_at.is_none() {
+                self.exited_at = Some(Instant::now());
+            }
+            self.reap_leader();
+        }
+        Ok(self.status)
+    }
+
+    fn reap_leader(&mut self) {
+        if self.leader_reaped {
+            return;
+        }
+        let mut raw = 0;
+        // SAFETY: waitpid on our own child with a valid status pointer
+        let result = unsafe { libc::waitpid(self.leader, &mut raw, libc::WNOHANG) };
+        self.leader_reaped = result == self.leader || result == -1;
+    }
+
+    // Whether the child is gone and all of its output has been taken
+    pub fn is_finished(&mut self) -> io::Result<bool> {
+        self.poll_status()?;
+        Ok(match self.exited_at {
+            Some(exited_at) => self.closed || exited_at.elapsed() >= EXIT_GRACE_PERIOD,
+            None => false,
+        })
+    }
+
+    // Send a signal to the child's whole process group (used by fg/bg)
+    pub fn signal_group(&self, signal: i32) -> io::Result<()> {
+        // SAFETY: killpg has no memory safety requirements
+        if unsafe { libc::killpg(self.pid, signal) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    // Continue a stopped child. The session leader confirms this later,
+    // but until then it must not keep looking stopped to our callers.
+    pub fn resume(&mut self) -> io::Result<()> {
+        self.signal_group(libc::SIGCONT)?;
+        if self.status == ProcessStatus::Stopped {
+            self.status = ProcessStatus::Running;
+        }
+        Ok(())
+    }
+
+    // Send a signal to whichever process group is in the foreground of the
+    // child's terminal, the way the line discipline does for Ctrl+C
+    pub fn signal_foreground(&self, signal: i32) -> io::Result<()> {
+        // SAFETY: tcgetpgrp just queries the descriptor we own
+        let pgrp = unsafe { libc::tcgetpgrp(self.master.as_raw_fd()) };
+        let pgrp = if pgrp > 0 { pgrp } else { self.pid };
+        // SAFETY: killpg has no memory safety requirements
+        if unsafe { libc::killpg(pgrp, signal) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    // Whether the child's terminal turns Ctrl+C and friends into signals.
+    // Programs that put it in raw mode (editors) want the keys themselves.
+    pub fn signals_enabled(&self) -> bool {
+        // SAFETY: tcgetattr fills in the zeroed termios struct we pass in
+        unsafe {
+            let mut termios: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(self.master.as_raw_fd(), &mut termios) == -1 {
+                return true;
+            }
+            termios.c_lflag & libc::ISIG != 0
+        }
+    }
+
+    // Send input (keystrokes) to the child
+    pub fn write_all(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.master.write_all(bytes)
+    }
+
+    pub fn resize(&self, cols: u16, rows: u16) -> io::Result<()> {
+        set_window_size(self.master.as_raw_fd(), cols, rows)
+    }
+
+}
+
+impl Drop for PtyProcess {
+    fn drop(&mut self) {
+        self.reap_leader();
+    }
+}
+
+fn decode_status(raw: i32) -> ProcessStatus {
+    if libc::WIFEXITED(raw) {
+        ProcessStatus::Exited(libc::WEXITSTATUS(raw))
+    } else if libc::WIFSIGNALED(raw) {
+        ProcessStatus::Signaled(libc::WTERMSIG(raw))
+    } else if libc::WIFSTOPPED(raw) {
+        ProcessStatus::Stopped
+    } else {
+        ProcessStatus::Running
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Run a command to the end, returning how it ended and its output
+    fn run(command: &str) -> (ProcessStatus, String) {
+        let mut process = PtyProcess::spawn(command, 80, 24).unwrap();
+        let started = Instant::now();
+        let mut output = Vec::new();
+        while !process.is_finished().unwrap() {
+            assert!(started.elapsed() < Duration::from_secs(10), "`{}` didn't finish", command);
+            output.extend(process.take_output());
+            thread::sleep(Duration::from_millis(5));
+        }
+        output.extend(process.take_output());
+        (process.status(), String::from_utf8_lossy(&output).into_owned())
+    }
+
+    #[test]
+    fn commands_start_with_default_signals() {
+        let (status, output) = run("yes | head -1");
+        assert_eq!(status, ProcessStatus::Exited(0));
+        assert_eq!(output.trim(), "y");
+        let (_, output) = run("grep -E '^Sig(Ign|Blk)' /proc/self/status");
+        assert!(output.contains("SigBlk:\t0000000000000000"), "{}", output);
+        assert!(output.contains("SigIgn:\t0000000000000000"), "{}", output);
+    }
+
+    #[test]
+    fn statuses_come_through_the_session_leader() {
+        assert_eq!(run("exit 3").0, ProcessStatus::Exited(3));
+        assert_eq!(run("kill -TERM $$").0, ProcessStatus::Signaled(libc::SIGTERM));
+        assert_eq!(run("kill -TERM $$").0.code(), Some(143));
+
+        // A stopped job is reported as such and carries on once resumed
+        let mut process = PtyProcess::spawn("kill -STOP $$; exit 4", 80, 24).unwrap();
+        let started = Instant::now();
+        while process.poll_status().unwrap() != ProcessStatus::Stopped {
+            assert!(started.elapsed() < Duration::from_secs(10), "never stopped");
+            thread::sleep(Duration::from_millis(5));
+        }
+        process.resume().unwrap();
+        while !process.poll_status().unwrap().is_done() {
+            assert!(started.elapsed() < Duration::from_secs(10), "never finished");
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(process.status(), ProcessStatus::Exited(4));
+    }
+}
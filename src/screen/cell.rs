@@ -0,0 +1,124 @@
+// A single character cell of the screen grid and its display attributes
+use unicode_width::UnicodeWidthChar;
+
+// Stands in the cell after a wide character, which covers both
+pub const WIDE_CONTINUATION: char = '\0';
+
+// Columns a character takes up: 2 for wide (mostly CJK) characters, 0 for
+// combining marks and other zero width characters, 1 for the rest
+pub fn char_width(c: char) -> usize {
+    c.width().unwrap_or(1)
+}
+
+// Zero width characters are kept with the cell before them
+pub fn is_combining(c: char) -> bool {
+    c != WIDE_CONTINUATION && char_width(c) == 0
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CellColor {
+    // Whatever the real terminal uses by default
+    #[default]
+    Default,
+    // One of the 256 palette colors (0-15 are the standard ANSI colors)
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Attributes {
+    pub fg: CellColor,
+    pub bg: CellColor,
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub inverse: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub c: char,
+    // A combining mark drawn over `c`; any after the first are dropped
+    pub combining: Option<char>,
+    pub attrs: Attributes,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self {
+            c: ' ',
+            combining: None,
+            attrs: Attributes::default(),
+        }
+    }
+}
+
+impl Cell {
+    pub fn new(c: char, attrs: Attributes) -> Self {
+        Self { c, combining: None, attrs }
+    }
+
+    // A blank cell keeps the background color of the pen that erased it
+    pub fn blank(attrs: Attributes) -> Self {
+        Self {
+            c: ' ',
+            combining: None,
+            attrs: Attributes {
+                bg: attrs.bg,
+                ..Attributes::default()
+            },
+        }
+    }
+
+    pub fn is_wide(&self) -> bool {
+        char_width(self.c) == 2
+    }
+
+    pub fn is_continuation(&self) -> bool {
+        self.c == WIDE_CONTINUATION
+    }
+
+    // The cell's text: nothing for the second half of a wide character
+    pub fn push_text(&self, text: &mut String) {
+        if !self.is_continuation() {
+            text.push(self.c);
+            text.extend(self.combining);
+        }
+    }
+}
+
+// One line of the grid
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Row {
+    pub cells: Vec<Cell>,
+    // Set when the text continues on the next row because of autowrap
+    pub wrapped: bool,
+}
+
+impl Row {
+    pub fn new(cols: usize) -> Self {
+        Self {
+            cells: vec![Cell::default(); cols],
+            wrapped: false,
+        }
+    }
+
+    pub fn resize(&mut self, cols: usize) {
+        self.cells.resize(cols, Cell::default());
+    }
+
+    pub fn clear(&mut self, attrs: Attributes) {
+        self.cells.fill(Cell::blank(attrs));
+        self.wrapped = false;
+    }
+
+    // Plain text of the row without trailing blanks
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for cell in &self.cells {
+            cell.push_text(&mut text);
+        }
+        text.trim_end().to_string()
+    }
+}
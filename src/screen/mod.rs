@@ -0,0 +1,4 @@
+pub mod cell;
+#[allow(clippy::module_inception)]
+pub mod screen;
+pub mod scrollback;
\ No newline at end of file
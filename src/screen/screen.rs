@@ -0,0 +1,1095 @@
+// Screen model: a grid of cells with a cursor, scroll region, alternate
+// screen and scrollback. Child output is fed through the escape sequence
+// parser and applied here; the terminal then draws the cells.
+use std::borrow::Cow;
+
+use crate::parser::parser::{Action, Params, Parser};
+use super::cell::{char_width, Attributes, Cell, CellColor, Row, WIDE_CONTINUATION};
+use super::scrollback::{Scrollback, ScrollbackStorage};
+
+// How many lines scrolled off the top we keep around
+pub const DEFAULT_SCROLLBACK: usize = 10_000;
+
+const TAB_WIDTH: usize = 8;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Cursor {
+    pub row: usize,
+    pub col: usize,
+}
+
+// State stored by DECSC (ESC 7) and restored by DECRC (ESC 8)
+#[derive(Debug, Clone, Copy, Default)]
+struct SavedCursor {
+    cursor: Cursor,
+    attrs: Attributes,
+    origin_mode: bool,
+    line_drawing: bool,
+}
+
+pub struct Screen {
+    cols: usize,
+    rows: usize,
+    grid: Vec<Row>,
+    // The buffer that isn't shown: the primary screen while the alternate
+    // one is active and vice versa
+    inactive_grid: Vec<Row>,
+    alternate_active: bool,
+    cursor: Cursor,
+    // The cursor sits past the last column; the next character wraps
+    pending_wrap: bool,
+    attrs: Attributes,
+    saved: SavedCursor,
+    // Scroll region, both ends inclusive
+    scroll_top: usize,
+    scroll_bottom: usize,
+    tab_stops: Vec<bool>,
+    scrollback: Scrollback,
+    parser: Parser,
+    last_char: char,
+    // G0 is mapped to the DEC special graphics (line drawing) set
+    line_drawing: bool,
+    application_cursor: bool,
+    autowrap: bool,
+    origin_mode: bool,
+    insert_mode: bool,
+    cursor_visible: bool,
+    bracketed_paste: bool,
+    title: String,
+    // Answers to queries (cursor position, device attributes) that have to
+    // be written back to the child
+    replies: Vec<u8>,
+}
+
+impl Screen {
+    // Keeps up to `scrollback_limit` lines in memory
+    pub fn new(cols: u16, rows: u16, scrollback_limit: usize) -> Self {
+        Self::with_scrollback(cols, rows, Scrollback::new(scrollback_limit, None, ScrollbackStorage::Memory))
+    }
+
+    pub fn with_scrollback(cols: u16, rows: u16, scrollback: Scrollback) -> Self {
+        let cols = (cols as usize).max(1);
+        let rows = (rows as usize).max(1);
+        Self {
+            cols,
+            rows,
+            grid: vec![Row::new(cols); rows],
+            inactive_grid: vec![Row::new(cols); rows],
+            alternate_active: false,
+            cursor: Cursor::default(),
+            pending_wrap: false,
+            attrs: Attributes::default(),
+            saved: SavedCursor::default(),
+            scroll_top: 0,
+            scroll_bottom: rows - 1,
+            tab_stops: default_tab_stops(cols),
+            scrollback,
+            parser: Parser::new(),
+            last_char: ' ',
+            line_drawing: false,
+            application_cursor: false,
+            autowrap: true,
+            origin_mode: false,
+            insert_mode: false,
+            cursor_visible: true,
+            bracketed_paste: false,
+            title: String::new(),
+            replies: Vec::new(),
+        }
+    }
+
+    // Feed raw output from a child process
+    pub fn feed(&mut self, bytes: &[u8]) {
+        for action in self.parser.parse(bytes) {
+            self.apply(action);
+        }
+    }
+
+    // Move to the start of a fresh line unless we're already on one, so
+    // output that didn't end with a newline doesn't run into what follows
+    pub fn finish_line(&mut self) {
+        if self.cursor.col != 0 || self.pending_wrap {
+            self.feed(b"\r\n");
+        }
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cursor(&self) -> Cursor {
+        self.cursor
+    }
+
+    pub fn cursor_visible(&self) -> bool {
+        self.cursor_visible
+    }
+
+    pub fn application_cursor(&self) -> bool {
+        self.application_cursor
+    }
+
+    pub fn bracketed_paste(&self) -> bool {
+        self.bracketed_paste
+    }
+
+    pub fn is_alternate(&self) -> bool {
+        self.alternate_active
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn take_replies(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.replies)
+    }
+
+    // Row of the visible grid
+    pub fn row(&self, index: usize) -> &Row {
+        &self.grid[index]
+    }
+
+    pub fn scrollback_len(&self) -> usize {
+        self.scrollback.len()
+    }
+
+    // Scrollback and grid seen as one list of lines, oldest first
+    pub fn total_lines(&self) -> usize {
+        self.scrollback.len() + self.rows
+    }
+
+    // Lines of the scrollback that are compressed or on disk come back as
+    // copies
+    pub fn line(&self, index: usize) -> Option<Cow<'_, Row>> {
+        if index < self.scrollback.len() {
+            self.scrollback.get(index)
+        } else {
+            self.grid.get(index - self.scrollback.len()).map(Cow::Borrowed)
+        }
+    }
+
+    // The text of every line with its index, much faster than going
+    // through them one by one with `line`
+    pub fn for_each_text(&self, mut f: impl FnMut(usize, &str)) {
+        self.scrollback.for_each_text(&mut f);
+        let start = self.scrollback.len();
+        for (i, row) in self.grid.iter().enumerate() {
+            f(start + i, &row.text());
+        }
+    }
+
+    pub fn clear_scrollback(&mut self) {
+        self.scrollback.clear();
+    }
+
+    pub fn resize(&mut self, cols: u16, rows: u16) {
+        let cols = (cols as usize).max(1);
+        let rows = (rows as usize).max(1);
+
+        if cols != self.cols {
+            for row in self.grid.iter_mut().chain(self.inactive_grid.iter_mut()) {
+                row.resize(cols);
+            }
+            self.tab_stops = default_tab_stops(cols);
+            self.cols = cols;
+        }
+
+        if rows < self.rows {
+            // Drop blank lines below the cursor first, then push lines off
+            // the top so the cursor stays on screen
+            let excess = self.rows - rows;
+            let below = (self.rows - 1 - self.cursor.row).min(excess);
+            self.grid.truncate(self.rows - below);
+            for _ in below..excess {
+                let row = self.grid.remove(0);
+                if !self.alternate_active {
+                    self.push_scrollback(row);
+                }
+                self.cursor.row = self.cursor.row.saturating_sub(1);
+            }
+            self.inactive_grid.truncate(rows);
+        } else {
+            self.grid.resize(rows, Row::new(cols));
+            self.inactive_grid.resize(rows, Row::new(cols));
+        }
+        self.rows = rows;
+
+        self.scroll_top = 0;
+        self.scroll_bottom = rows - 1;
+        self.cursor.row = self.cursor.row.min(rows - 1);
+        self.cursor.col = self.cursor.col.min(cols - 1);
+        self.pending_wrap = false;
+    }
+
+    fn apply(&mut self, action: Action) {
+        match action {
+            Action::Print(c) => self.print(c),
+            Action::Execute(byte) => self.execute(byte),
+            Action::CsiDispatch { params, intermediates, action } => {
+                self.csi_dispatch(&params, &intermediates, action)
+            }
+            Action::EscDispatch { intermediates, byte } => self.esc_dispatch(&intermediates, byte),
+            Action::OscDispatch { params, .. } => self.osc_dispatch(&params),
+            // No device control strings are supported yet
+            Action::DcsDispatch { .. } => {}
+        }
+    }
+
+    fn print(&mut self, c: char) {
+        let c = if self.line_drawing { dec_special_graphics(c) } else { c };
+        let width = char_width(c);
+        if width == 0 {
+            self.combine(c);
+            return;
+        }
+        // A wide character can't be shown at all on a single column
+        if width > self.cols {
+            return;
+        }
+
+        // A wide character that doesn't fit in the last column wraps early
+        let no_room = self.cursor.col + width > self.cols;
+        if self.pending_wrap || (no_room && self.autowrap) {
+            self.grid[self.cursor.row].wrapped = true;
+            self.cursor.col = 0;
+            self.linefeed();
+        } else if no_room {
+            self.cursor.col = self.cols - width;
+        }
+        self.pending_wrap = false;
+
+        let col = self.cursor.col;
+        let cells = &mut self.grid[self.cursor.row].cells;
+        if self.insert_mode {
+            cells.truncate(self.cols - width);
+            cells.splice(col..col, std::iter::repeat_n(Cell::blank(self.attrs), width));
+        }
+        // Overwriting half of a wide character leaves the other half blank
+        if col > 0 && cells[col].is_continuation() {
+            cells[col - 1] = Cell::blank(cells[col - 1].attrs);
+        }
+        if cells.get(col + width).is_some_and(Cell::is_continuation) {
+            cells[col + width] = Cell::blank(cells[col + width].attrs);
+        }
+        cells[col] = Cell::new(c, self.attrs);
+        if width == 2 {
+            cells[col + 1] = Cell::new(WIDE_CONTINUATION, self.attrs);
+        }
+        self.last_char = c;
+
+        if col + width < self.cols {
+            self.cursor.col += width;
+        } else {
+            self.cursor.col = self.cols - 1;
+            self.pending_wrap = self.autowrap;
+        }
+    }
+
+    // Combining marks go onto the character before the cursor, or are
+    // dropped at the start of a line
+    fn combine(&mut self, c: char) {
+        let col = if self.pending_wrap { Some(self.cursor.col) } else { self.cursor.col.checked_sub(1) };
+        let Some(mut col) = col else {
+            return;
+        };
+        let cells = &mut self.grid[self.cursor.row].cells;
+        if col > 0 && cells[col].is_continuation() {
+            col -= 1;
+        }
+        cells[col].combining.get_or_insert(c);
+    }
+
+    fn execute(&mut self, byte: u8) {
+        match byte {
+            // Backspace
+            0x08 => {
+                self.cursor.col = self.cursor.col.saturating_sub(1);
+                self.pending_wrap = false;
+            }
+            // Horizontal tab
+            0x09 => self.tab_forward(1),
+            // Line feed, vertical tab, form feed
+            0x0A..=0x0C => self.linefeed(),
+            // Carriage return
+            0x0D => {
+                self.cursor.col = 0;
+                self.pending_wrap = false;
+            }
+            // Shift out/in (G1/G0) and the bell aren't supported
+            _ => {}
+        }
+    }
+
+    fn esc_dispatch(&mut self, intermediates: &[u8], byte: u8) {
+        match (intermediates, byte) {
+            ([], b'7') => self.save_cursor(),
+            ([], b'8') => self.restore_cursor(),
+            // Index
+            ([], b'D') => self.linefeed(),
+            // Next line
+            ([], b'E') => {
+                self.cursor.col = 0;
+                self.linefeed();
+            }
+            // Horizontal tab set
+            ([], b'H') => self.tab_stops[self.cursor.col] = true,
+            // Reverse index
+            ([], b'M') => self.reverse_index(),
+            // Full reset
+            ([], b'c') => self.reset(),
+            // Application / normal keypad: arrows behave the same for us
+            ([], b'=') | ([], b'>') => {}
+            ([b'('], b'0') => self.line_drawing = true,
+            ([b'('], _) => self.line_drawing = false,
+            _ => {}
+        }
+    }
+
+    fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], action: char) {
+        let private = intermediates.first() == Some(&b'?');
+        if private {
+            match action {
+                'h' => self.set_private_modes(params, true),
+                'l' => self.set_private_modes(params, false),
+                _ => {}
+            }
+            return;
+        }
+
+        // Sequences with intermediates (DECSCUSR, DECSTR...) are mostly
+        // cosmetic; the only one we honour is the soft reset
+        if !intermediates.is_empty() {
+            if intermediates == b"!" && action == 'p' {
+                self.soft_reset();
+            }
+            if intermediates == b">" && action == 'c' {
+                self.replies.extend_from_slice(b"\x1b[>0;10;1c");
+            }
+            return;
+        }
+
+        let n = params.get_or(0, 1) as usize;
+        match action {
+            '@' => self.insert_chars(n),
+            'A' => self.move_cursor_up(n),
+            'B' | 'e' => self.move_cursor_down(n),
+            'C' | 'a' => self.move_cursor_to(self.cursor.row, self.cursor.col + n),
+            'D' => self.move_cursor_to(self.cursor.row, self.cursor.col.saturating_sub(n)),
+            'E' => {
+                self.move_cursor_down(n);
+                self.cursor.col = 0;
+            }
+            'F' => {
+                self.move_cursor_up(n);
+                self.cursor.col = 0;
+            }
+            'G' | '`' => self.move_cursor_to(self.cursor.row, n - 1),
+            'H' | 'f' => {
+                let row = params.get_or(0, 1) as usize - 1;
+                let col = params.get_or(1, 1) as usize - 1;
+                self.move_cursor_origin(row, col);
+            }
+            'I' => self.tab_forward(n),
+            'J' => self.erase_display(params.get(0).unwrap_or(0)),
+            'K' => self.erase_line(params.get(0).unwrap_or(0)),
+            'L' => self.insert_lines(n),
+            'M' => self.delete_lines(n),
+            'P' => self.delete_chars(n),
+            'S' => self.scroll_up(n),
+            'T' => self.scroll_down(n),
+            'X' => self.erase_chars(n),
+            'Z' => self.tab_backward(n),
+            'b' => {
+                for _ in 0..n.min(self.cols * self.rows) {
+                    self.print(self.last_char);
+                }
+            }
+            'c' => self.replies.extend_from_slice(b"\x1b[?62;22c"),
+            'd' => {
+                let col = self.cursor.col;
+                self.move_cursor_origin(n - 1, col);
+            }
+            'g' => match params.get(0).unwrap_or(0) {
+                0 => self.tab_stops[self.cursor.col] = false,
+                3 => self.tab_stops.fill(false),
+                _ => {}
+            },
+            'h' => self.set_modes(params, true),
+            'l' => self.set_modes(params, false),
+            'm' => self.select_graphic_rendition(params),
+            'n' => self.device_status_report(params.get(0).unwrap_or(0)),
+            'r' => {
+                let top = params.get_or(0, 1) as usize - 1;
+                let bottom = (params.get_or(1, self.rows as u16) as usize).min(self.rows) - 1;
+                if top < bottom {
+                    self.scroll_top = top;
+                    self.scroll_bottom = bottom;
+                    self.move_cursor_origin(0, 0);
+                }
+            }
+            's' => self.save_cursor(),
+            'u' => self.restore_cursor(),
+            _ => {}
+        }
+    }
+
+    fn osc_dispatch(&mut self, params: &[Vec<u8>]) {
+        match params.first().map(|p| p.as_slice()) {
+            // Icon name and window title
+            Some(b"0") | Some(b"2") => {
+                let title = params[1..].join(&b';');
+                self.title = String::from_utf8_lossy(&title).to_string();
+            }
+            _ => {}
+        }
+    }
+
+    fn set_modes(&mut self, params: &Params, enable: bool) {
+        for param in params.iter() {
+            if param.first() == Some(&4) {
+                self.insert_mode = enable;
+            }
+        }
+    }
+
+    fn set_private_modes(&mut self, params: &Params, enable: bool) {
+        for param in params.iter() {
+            match param.first().copied().unwrap_or(0) {
+                1 => self.application_cursor = enable,
+                6 => {
+                    self.origin_mode = enable;
+                    self.move_cursor_origin(0, 0);
+                }
+                7 => self.autowrap = enable,
+                25 => self.cursor_visible = enable,
+                47 | 1047 => self.set_alternate_screen(enable),
+                1049 => {
+                    if enable {
+                        self.save_cursor();
+                        self.set_alternate_screen(true);
+                    } else {
+                        self.set_alternate_screen(false);
+                        self.restore_cursor();
+                    }
+                }
+                2004 => self.bracketed_paste = enable,
+                _ => {}
+            }
+        }
+    }
+
+    fn set_alternate_screen(&mut self, enable: bool) {
+        if enable == self.alternate_active {
+            return;
+        }
+        std::mem::swap(&mut self.grid, &mut self.inactive_grid);
+        self.alternate_active = enable;
+        if enable {
+            // The alternate screen always starts out blank
+            for row in &mut self.grid {
+                row.clear(Attributes::default());
+            }
+        }
+        self.pending_wrap = false;
+    }
+
+    fn select_graphic_rendition(&mut self, params: &Params) {
+        if params.is_empty() {
+            self.attrs = Attributes::default();
+            return;
+        }
+
+        let groups: Vec<&[u16]> = params.iter().collect();
+        let mut i = 0;
+        while i < groups.len() {
+            let group = groups[i];
+            match group[0] {
+                0 => self.attrs = Attributes::default(),
+                1 => self.attrs.bold = true,
+                2 => self.attrs.dim = true,
+                3 => self.attrs.italic = true,
+                // `4:0` turns underline off, other styles just underline
+                4 => self.attrs.underline = group.get(1) != Some(&0),
+                7 => self.attrs.inverse = true,
+                21 => self.attrs.underline = true,
+                22 => {
+                    self.attrs.bold = false;
+                    self.attrs.dim = false;
+                }
+                23 => self.attrs.italic = false,
+                24 => self.attrs.underline = false,
+                27 => self.attrs.inverse = false,
+                n @ 30..=37 => self.attrs.fg = CellColor::Indexed((n - 30) as u8),
+                38 => {
+                    let (color, used) = extended_color(group, &groups[i + 1..]);
+                    if let Some(color) = color {
+                        self.attrs.fg = color;
+                    }
+                    i += used;
+                }
+                39 => self.attrs.fg = CellColor::Default,
+                n @ 40..=47 => self.attrs.bg = CellColor::Indexed((n - 40) as u8),
+                48 => {
+                    let (color, used) = extended_color(group, &groups[i + 1..]);
+                    if let Some(color) = color {
+                        self.attrs.bg = color;
+                    }
+                    i += used;
+                }
+                49 => self.attrs.bg = CellColor::Default,
+                n @ 90..=97 => self.attrs.fg = CellColor::Indexed((n - 90 + 8) as u8),
+                n @ 100..=107 => self.attrs.bg = CellColor::Indexed((n - 100 + 8) as u8),
+                _ => {}
+            }
+            i += 1;
+        }
+    }
+
+    fn device_status_report(&mut self, kind: u16) {
+        match kind {
+            5 => self.replies.extend_from_slice(b"\x1b[0n"),
+            6 => {
+                let row = if self.origin_mode {
+                    self.cursor.row.saturating_sub(self.scroll_top)
+                } else {
+                    self.cursor.row
+                };
+                let reply = format!("\x1b[{};{}R", row + 1, self.cursor.col + 1);
+                self.replies.extend_from_slice(reply.as_bytes());
+            }
+            _ => {}
+        }
+    }
+
+    fn linefeed(&mut self) {
+        if self.cursor.row == self.scroll_bottom {
+            self.scroll_up(1);
+        } else if self.cursor.row + 1 < self.rows {
+            self.cursor.row += 1;
+        }
+        self.pending_wrap = false;
+    }
+
+    fn reverse_index(&mut self) {
+        if self.cursor.row == self.scroll_top {
+            self.scroll_down(1);
+        } else {
+            self.cursor.row = self.cursor.row.saturating_sub(1);
+        }
+        self.pending_wrap = false;
+    }
+
+    // Scroll the contents of the scroll region up by `n` lines
+    fn scroll_up(&mut self, n: usize) {
+        let n = n.min(self.scroll_bottom - self.scroll_top + 1);
+        for _ in 0..n {
+            let row = self.grid.remove(self.scroll_top);
+            // Only lines leaving the top of the whole primary screen are kept
+            if self.scroll_top == 0 && !self.alternate_active {
+                self.push_scrollback(row);
+            }
+            let mut blank = Row::new(self.cols);
+            blank.clear(self.attrs);
+            self.grid.insert(self.scroll_bottom, blank);
+        }
+    }
+
+    fn scroll_down(&mut self, n: usize) {
+        let n = n.min(self.scroll_bottom - self.scroll_top + 1);
+        for _ in 0..n {
+            self.grid.remove(self.scroll_bottom);
+            let mut blank = Row::new(self.cols);
+            blank.clear(self.attrs);
+            self.grid.insert(self.scroll_top, blank);
+        }
+    }
+
+    fn push_scrollback(&mut self, row: Row) {
+        self.scrollback.push(row);
+    }
+
+    fn move_cursor_to(&mut self, row: usize, col: usize) {
+        self.cursor.row = row.min(self.rows - 1);
+        self.cursor.col = col.min(self.cols - 1);
+        self.pending_wrap = false;
+    }
+
+    // Absolute positioning, relative to the scroll region in origin mode
+    fn move_cursor_origin(&mut self, row: usize, col: usize) {
+        if self.origin_mode {
+            let row = (self.scroll_top + row).min(self.scroll_bottom);
+            self.move_cursor_to(row, col);
+        } else {
+            self.move_cursor_to(row, col);
+        }
+    }
+
+    // Relative moves stop at the scroll region margins when inside it
+    fn move_cursor_up(&mut self, n: usize) {
+        let top = if self.cursor.row >= self.scroll_top { self.scroll_top } else { 0 };
+        let row = self.cursor.row.saturating_sub(n).max(top);
+        self.move_cursor_to(row, self.cursor.col);
+    }
+
+    fn move_cursor_down(&mut self, n: usize) {
+        let bottom = if self.cursor.row <= self.scroll_bottom {
+            self.scroll_bottom
+        } else {
+            self.rows - 1
+        };
+        let row = (self.cursor.row + n).min(bottom);
+        self.move_cursor_to(row, self.cursor.col);
+    }
+
+    fn tab_forward(&mut self, n: usize) {
+        for _ in 0..n {
+            let next = (self.cursor.col + 1..self.cols).find(|&col| self.tab_stops[col]);
+            self.cursor.col = next.unwrap_or(self.cols - 1);
+        }
+        self.pending_wrap = false;
+    }
+
+    fn tab_backward(&mut self, n: usize) {
+        for _ in 0..n {
+            let prev = (0..self.cursor.col).rev().find(|&col| self.tab_stops[col]);
+            self.cursor.col = prev.unwrap_or(0);
+        }
+        self.pending_wrap = false;
+    }
+
+    fn erase_display(&mut self, mode: u16) {
+        let attrs = self.attrs;
+        let row = self.cursor.row;
+        match mode {
+            0 => {
+                self.erase_line(0);
+                for r in &mut self.grid[row + 1..] {
+                    r.clear(attrs);
+                }
+            }
+            1 => {
+                self.erase_line(1);
+                for r in &mut self.grid[..row] {
+                    r.clear(attrs);
+                }
+            }
+            2 => {
+                for r in &mut self.grid {
+                    r.clear(attrs);
+                }
+            }
+            3 => self.clear_scrollback(),
+            _ => {}
+        }
+    }
+
+    fn erase_line(&mut self, mode: u16) {
+        let blank = Cell::blank(self.attrs);
+        let col = self.cursor.col;
+        let row = &mut self.grid[self.cursor.row];
+        match mode {
+            0 => {
+                row.cells[col..].fill(blank);
+                row.wrapped = false;
+            }
+            1 => row.cells[..=col].fill(blank),
+            2 => row.clear(self.attrs),
+            _ => {}
+        }
+        self.pending_wrap = false;
+    }
+
+    fn erase_chars(&mut self, n: usize) {
+        let blank = Cell::blank(self.attrs);
+        let col = self.cursor.col;
+        let end = (col + n).min(self.cols);
+        self.grid[self.cursor.row].cells[col..end].fill(blank);
+        self.pending_wrap = false;
+    }
+
+    fn insert_chars(&mut self, n: usize) {
+        let blank = Cell::blank(self.attrs);
+        let col = self.cursor.col;
+        let n = n.min(self.cols - col);
+        let cells = &mut self.grid[self.cursor.row].cells;
+        cells.truncate(self.cols - n);
+        cells.splice(col..col, std::iter::repeat_n(blank, n));
+        self.pending_wrap = false;
+    }
+
+    fn delete_chars(&mut self, n: usize) {
+        let blank = Cell::blank(self.attrs);
+        let col = self.cursor.col;
+        let n = n.min(self.cols - col);
+        let cells = &mut self.grid[self.cursor.row].cells;
+        cells.drain(col..col + n);
+        cells.extend(std::iter::repeat_n(blank, n));
+        self.pending_wrap = false;
+    }
+
+    fn insert_lines(&mut self, n: usize) {
+        if self.cursor.row < self.scroll_top || self.cursor.row > self.scroll_bottom {
+            return;
+        }
+        let n = n.min(self.scroll_bottom - self.cursor.row + 1);
+        for _ in 0..n {
+            self.grid.remove(self.scroll_bottom);
+            let mut blank = Row::new(self.cols);
+            blank.clear(self.attrs);
+            self.grid.insert(self.cursor.row, blank);
+        }
+        self.cursor.col = 0;
+        self.pending_wrap = false;
+    }
+
+    fn delete_lines(&mut self, n: usize) {
+        if self.cursor.row < self.scroll_top || self.cursor.row > self.scroll_bottom {
+            return;
+        }
+        let n = n.min(self.scroll_bottom - self.cursor.row + 1);
+        for _ in 0..n {
+            self.grid.remove(self.cursor.row);
+            let mut blank = Row::new(self.cols);
+            blank.clear(self.attrs);
+            self.grid.insert(self.scroll_bottom, blank);
+        }
+        self.cursor.col = 0;
+        self.pending_wrap = false;
+    }
+
+    fn save_cursor(&mut self) {
+        self.saved = SavedCursor {
+            cursor: self.cursor,
+            attrs: self.attrs,
+            origin_mode: self.origin_mode,
+            line_drawing: self.line_drawing,
+        };
+    }
+
+    fn restore_cursor(&mut self) {
+        let saved = self.saved;
+        self.attrs = saved.attrs;
+        self.origin_mode = saved.origin_mode;
+        self.line_drawing = saved.line_drawing;
+        // The scroll region may have changed since; in origin mode the
+        // cursor can't leave it
+        let row = if self.origin_mode {
+            saved.cursor.row.clamp(self.scroll_top, self.scroll_bottom)
+        } else {
+            saved.cursor.row
+        };
+        self.move_cursor_to(row, saved.cursor.col);
+    }
+
+    // DECSTR: reset modes and pen but keep the screen contents
+    fn soft_reset(&mut self) {
+        self.attrs = Attributes::default();
+        self.saved = SavedCursor::default();
+        self.scroll_top = 0;
+        self.scroll_bottom = self.rows - 1;
+        self.line_drawing = false;
+        self.application_cursor = false;
+        self.autowrap = true;
+        self.origin_mode = false;
+        self.insert_mode = false;
+        self.cursor_visible = true;
+        self.pending_wrap = false;
+    }
+
+    // RIS: back to a blank primary screen (scrollback is kept)
+    fn reset(&mut self) {
+        self.set_alternate_screen(false);
+        self.soft_reset();
+        self.bracketed_paste = false;
+        self.tab_stops = default_tab_stops(self.cols);
+        for row in &mut self.grid {
+            row.clear(Attributes::default());
+        }
+        self.cursor = Cursor::default();
+    }
+}
+
+fn default_tab_stops(cols: usize) -> Vec<bool> {
+    (0..cols).map(|col| col % TAB_WIDTH == 0).collect()
+}
+
+// Parse the color that follows SGR 38/48, either as separate parameters
+// (`38;5;n`, `38;2;r;g;b`) or as sub-parameters (`38:5:n`, `38:2::r:g:b`).
+// Returns the color and how many of the following parameters it used up.
+fn extended_color(group: &[u16], rest: &[&[u16]]) -> (Option<CellColor>, usize) {
+    let byte = |value: u16| value.min(255) as u8;
+
+    if group.len() > 1 {
+        let color = match group[1] {
+            5 => group.get(2).map(|&n| CellColor::Indexed(byte(n))),
+            2 => {
+                // An optional color space id may precede the components
+                let rgb = if group.len() >= 6 { &group[3..6] } else { &group[2..] };
+                (rgb.len() >= 3).then(|| CellColor::Rgb(byte(rgb[0]), byte(rgb[1]), byte(rgb[2])))
+            }
+            _ => None,
+        };
+        return (color, 0);
+    }
+
+    match rest.first().map(|g| g[0]) {
+        Some(5) => match rest.get(1) {
+            Some(n) => (Some(CellColor::Indexed(byte(n[0]))), 2),
+            None => (None, 1),
+        },
+        Some(2) if rest.len() >= 4 => (
+            Some(CellColor::Rgb(byte(rest[1][0]), byte(rest[2][0]), byte(rest[3][0]))),
+            4,
+        ),
+        Some(2) => (None, rest.len()),
+        _ => (None, 0),
+    }
+}
+
+// DEC special graphics, used by curses programs to draw boxes
+fn dec_special_graphics(c: char) -> char {
+    match c {
+        '`' => '◆',
+        'a' => '▒',
+        'f' => '°',
+        'g' => '±',
+        'j' => '┘',
+        'k' => '┐',
+        'l' => '┌',
+        'm' => '└',
+        'n' => '┼',
+        'o' => '⎺',
+        'p' => '⎻',
+        'q' => '─',
+        'r' => '⎼',
+        's' => '⎽',
+        't' => '├',
+        'u' => '┤',
+        'v' => '┴',
+        'w' => '┬',
+        'x' => '│',
+        'y' => '≤',
+        'z' => '≥',
+        '{' => 'π',
+        '|' => '≠',
+        '}' => '£',
+        '~' => '·',
+        _ => c,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn screen(cols: u16, rows: u16, input: &str) -> Screen {
+        let mut screen = Screen::new(cols, rows, 100);
+        screen.feed(input.as_bytes());
+        screen
+    }
+
+    fn lines(screen: &Screen) -> Vec<String> {
+        (0..screen.rows()).map(|row| screen.row(row).text()).collect()
+    }
+
+    fn cursor(screen: &Screen) -> (usize, usize) {
+        (screen.cursor().row, screen.cursor().col)
+    }
+
+    #[test]
+    fn autowrap_waits_for_the_next_character() {
+        let mut screen = screen(5, 3, "abcde");
+        // The cursor stays on the last column until something is printed
+        assert_eq!(cursor(&screen), (0, 4));
+        assert_eq!(lines(&screen), ["abcde", "", ""]);
+        screen.feed(b"f");
+        assert_eq!(lines(&screen), ["abcde", "f", ""]);
+        assert!(screen.row(0).wrapped);
+        assert_eq!(cursor(&screen), (1, 1));
+
+        // A carriage return cancels the pending wrap
+        let screen = self::screen(5, 3, "abcde\rX");
+        assert_eq!(lines(&screen), ["Xbcde", "", ""]);
+        assert!(!screen.row(0).wrapped);
+
+        // Without autowrap the last column is overwritten
+        let screen = self::screen(5, 3, "\x1b[?7labcdefg");
+        assert_eq!(lines(&screen), ["abcdg", "", ""]);
+    }
+
+    #[test]
+    fn scroll_region_with_index_and_reverse_index() {
+        let mut screen = screen(5, 5, "1\r\n2\r\n3\r\n4\r\n5");
+        screen.feed(b"\x1b[2;4r");
+        assert_eq!(cursor(&screen), (0, 0));
+
+        // IND at the bottom margin scrolls only the region
+        screen.feed(b"\x1b[4H\x1bD");
+        assert_eq!(lines(&screen), ["1", "3", "4", "", "5"]);
+        assert_eq!(cursor(&screen), (3, 0));
+
+        // RI at the top margin scrolls it the other way
+        screen.feed(b"\x1b[2H\x1bM");
+        assert_eq!(lines(&screen), ["1", "", "3", "4", "5"]);
+
+        // Lines scrolled out of a region that isn't at the top are lost
+        assert_eq!(screen.scrollback_len(), 0);
+    }
+
+    #[test]
+    fn insert_and_delete_lines_and_characters() {
+        let mut screen = screen(8, 4, "abcdef");
+        screen.feed(b"\x1b[1;3H\x1b[2@");
+        assert_eq!(screen.row(0).text(), "ab  cdef");
+        screen.feed(b"\x1b[3P");
+        assert_eq!(screen.row(0).text(), "abdef");
+        // Characters pushed past the right edge are gone
+        screen.feed(b"\x1b[1;1H\x1b[5@");
+        assert_eq!(screen.row(0).text(), "     abd");
+
+        let mut screen = self::screen(4, 4, "a\r\nb\r\nc\r\nd");
+        screen.feed(b"\x1b[2;3H\x1b[2L");
+        assert_eq!(lines(&screen), ["a", "", "", "b"]);
+        assert_eq!(cursor(&screen), (1, 0));
+        screen.feed(b"\x1b[M");
+        assert_eq!(lines(&screen), ["a", "", "b", ""]);
+        // Outside the scroll region IL and DL do nothing
+        screen.feed(b"\x1b[2;3r\x1b[4H\x1b[L");
+        assert_eq!(lines(&screen), ["a", "", "b", ""]);
+    }
+
+    #[test]
+    fn alternate_screen_saves_the_cursor() {
+        let mut screen = screen(10, 3, "main\x1b[2;3H\x1b[1m");
+        screen.feed(b"\x1b[?1049h");
+        assert!(screen.is_alternate());
+        assert_eq!(lines(&screen), ["", "", ""]);
+        screen.feed(b"\x1b[0m\x1b[Halt\r\n\r\n\r\nmore");
+        // Nothing scrolled off the alternate screen is kept
+        assert_eq!(screen.scrollback_len(), 0);
+
+        screen.feed(b"\x1b[?1049l");
+        assert!(!screen.is_alternate());
+        assert_eq!(lines(&screen), ["main", "", ""]);
+        assert_eq!(cursor(&screen), (1, 2));
+        screen.feed(b"x");
+        assert!(screen.row(1).cells[2].attrs.bold);
+
+        // DECSC/DECRC bring back the position and the pen
+        let mut screen = self::screen(10, 3, "\x1b[3;4H\x1b7\x1b[4m\x1b[H");
+        screen.feed(b"\x1b8y");
+        assert_eq!(screen.row(2).text(), "   y");
+        assert!(!screen.row(2).cells[3].attrs.underline);
+    }
+
+    #[test]
+    fn cursor_report_in_origin_mode() {
+        let mut screen = screen(10, 6, "\x1b[3;5r\x1b[?6h\x1b[2;4H\x1b[6n");
+        assert_eq!(screen.take_replies(), b"\x1b[2;4R");
+        assert_eq!(cursor(&screen), (3, 3));
+
+        // Saved above a scroll region set afterwards, the cursor comes back
+        // inside it
+        screen.feed(b"\x1b[r\x1b[H\x1b7\x1b[3;5r\x1b8\x1b[6n");
+        assert_eq!(screen.take_replies(), b"\x1b[1;1R");
+        assert_eq!(cursor(&screen), (2, 0));
+    }
+
+    #[test]
+    fn extended_colors_in_both_forms() {
+        let screen = screen(
+            10,
+            1,
+            "\x1b[38;5;196mA\x1b[48;2;1;2;3mB\x1b[0;38:5:17mC\x1b[48:2::4:5:6mD\x1b[38:2:7:8:9;1mE",
+        );
+        let cells = &screen.row(0).cells;
+        assert_eq!(cells[0].attrs.fg, CellColor::Indexed(196));
+        assert_eq!(cells[1].attrs.bg, CellColor::Rgb(1, 2, 3));
+        assert_eq!(cells[2].attrs.fg, CellColor::Indexed(17));
+        assert_eq!(cells[2].attrs.bg, CellColor::Default);
+        assert_eq!(cells[3].attrs.bg, CellColor::Rgb(4, 5, 6));
+        // Sub-parameters don't swallow the parameters that follow them
+        assert_eq!(cells[4].attrs.fg, CellColor::Rgb(7, 8, 9));
+        assert!(cells[4].attrs.bold);
+    }
+
+    #[test]
+    fn resize_keeps_the_cursor_line() {
+        let mut screen = screen(6, 4, "one\r\ntwo\r\nthree");
+        screen.resize(6, 2);
+        assert_eq!(lines(&screen), ["two", "three"]);
+        assert_eq!(cursor(&screen), (1, 5));
+        assert_eq!(screen.scrollback_len(), 1);
+        assert_eq!(screen.line(0).unwrap().text(), "one");
+
+        // Blank lines below the cursor go before anything is scrolled off
+        let mut screen = self::screen(6, 4, "one");
+        screen.resize(6, 2);
+        assert_eq!(lines(&screen), ["one", ""]);
+        assert_eq!(screen.scrollback_len(), 0);
+
+        screen.resize(3, 3);
+        assert_eq!(lines(&screen), ["one", "", ""]);
+        assert_eq!(cursor(&screen), (0, 2));
+        screen.resize(8, 3);
+        screen.feed(b"\x1b[1;8Hx");
+        assert_eq!(screen.row(0).text(), "one    x");
+    }
+
+    #[test]
+    fn wide_and_combining_characters() {
+        let screen = screen(6, 2, "a中b");
+        let cells = &screen.row(0).cells;
+        assert_eq!(cells[1].c, '中');
+        assert!(cells[2].is_continuation());
+        assert_eq!(cells[3].c, 'b');
+        assert_eq!(screen.row(0).text(), "a中b");
+        assert_eq!(cursor(&screen), (0, 4));
+
+        // Combining marks land on the cell before, or go at a line start
+        let screen = self::screen(6, 2, "\u{301}e\u{301}中\u{302}x");
+        let cells = &screen.row(0).cells;
+        assert_eq!((cells[0].c, cells[0].combining), ('e', Some('\u{301}')));
+        assert_eq!((cells[1].c, cells[1].combining), ('中', Some('\u{302}')));
+        assert_eq!(screen.row(0).text(), "e\u{301}中\u{302}x");
+        assert_eq!(cursor(&screen), (0, 4));
+
+        // A wide character that doesn't fit wraps to the next line
+        let screen = self::screen(5, 2, "abcd中");
+        assert_eq!(lines(&screen), ["abcd", "中"]);
+        assert!(screen.row(0).wrapped);
+        assert_eq!(cursor(&screen), (1, 2));
+
+        // Writing over either half blanks the other one
+        let screen = self::screen(5, 1, "中中\x1b[2Gx\x1b[4Gy");
+        assert_eq!(screen.row(0).text(), " x y");
+        assert!(!screen.row(0).cells.iter().any(Cell::is_continuation));
+    }
+
+    #[test]
+    fn lines_scrolled_off_go_to_the_scrollback() {
+        let mut screen = screen(5, 2, "1\r\n2\r\n3\r\n4");
+        assert_eq!(screen.scrollback_len(), 2);
+        assert_eq!(screen.total_lines(), 4);
+        let all: Vec<String> = (0..4).map(|index| screen.line(index).unwrap().text()).collect();
+        assert_eq!(all, ["1", "2", "3", "4"]);
+
+        // SU scrolls the whole screen into it too, ED 3 clears it
+        screen.feed(b"\x1b[2S");
+        assert_eq!(screen.scrollback_len(), 4);
+        assert_eq!(lines(&screen), ["", ""]);
+        screen.feed(b"\x1b[3J");
+        assert_eq!(screen.scrollback_len(), 0);
+
+        // The limit drops the oldest lines
+        let mut screen = Screen::new(5, 1, 3);
+        screen.feed(b"a\r\nb\r\nc\r\nd\r\ne");
+        assert_eq!(screen.scrollback_len(), 3);
+        assert_eq!(screen.line(0).unwrap().text(), "b");
+    }
+}
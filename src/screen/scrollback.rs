@@ -0,0 +1,582 @@
+// Lines that scrolled off the top of the screen, oldest first. Rows are kept
+// in pages of PAGE_ROWS: the newest pages stay as rows, while older ones can
+// be compressed, or compressed and written to a temp file. Once the line or
+// byte limit is reached the oldest lines are dropped.
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::mem;
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use serde::Deserialize;
+
+use super::cell::{is_combining, Attributes, Cell, CellColor, Row, WIDE_CONTINUATION};
+
+const PAGE_ROWS: usize = 256;
+
+// Pages left as rows whatever the storage, so the lines that just scrolled
+// off are quick to get at
+const HOT_PAGES: usize = 4;
+
+// The spill file is rewritten without dropped pages once it's at least this
+// big and more than half of it is dropped pages
+const COMPACT_MIN: u64 = 16 << 20;
+
+// Keeps spill files of several screens (and tests) apart
+static SPILL_FILES: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScrollbackStorage {
+    // Every line as it is
+    #[default]
+    Memory,
+    // Old pages compressed in memory
+    Compressed,
+    // Old pages compressed into a temp file, which the byte limit doesn't
+    // count
+    Disk,
+}
+
+enum Page {
+    Rows(Vec<Row>),
+    Compressed(Vec<u8>),
+    Spilled { offset: u64, len: usize },
+}
+
+struct SpillFile {
+    file: File,
+    len: u64,
+    // Bytes of the file still belonging to a page
+    live: u64,
+}
+
+pub struct Scrollback {
+    // Full pages, oldest first
+    pages: VecDeque<Page>,
+    // The newest rows, fewer than a page
+    recent: Vec<Row>,
+    // Rows at the start of the first page that were already dropped
+    dropped: usize,
+    len: usize,
+    // Roughly the memory the pages and recent rows take up
+    bytes: usize,
+    max_lines: usize,
+    max_bytes: Option<usize>,
+    storage: ScrollbackStorage,
+    // How many pages were dropped, so a page keeps its number as the ones
+    // before it go
+    first_page: usize,
+    spill: Option<SpillFile>,
+    // The last page decoded and its number, so drawing a screenful of
+    // lines decodes it once
+    cache: RefCell<Option<(usize, Vec<Row>)>>,
+}
+
+impl Scrollback {
+    pub fn new(max_lines: usize, max_bytes: Option<usize>, storage: ScrollbackStorage) -> Self {
+        Self {
+            pages: VecDeque::new(),
+            recent: Vec::new(),
+            dropped: 0,
+            len: 0,
+            bytes: 0,
+            max_lines,
+            max_bytes,
+            storage,
+            first_page: 0,
+            spill: None,
+            cache: RefCell::new(None),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Memory in use, which the byte limit applies to
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn get(&self, index: usize) -> Option<Cow<'_, Row>> {
+        if index >= self.len {
+            return None;
+        }
+        let position = index + self.dropped;
+        let (page, offset) = (position / PAGE_ROWS, position % PAGE_ROWS);
+        match self.pages.get(page) {
+            None => self.recent.get(position - self.pages.len() * PAGE_ROWS).map(Cow::Borrowed),
+            Some(Page::Rows(rows)) => rows.get(offset).map(Cow::Borrowed),
+            Some(stored) => {
+                let number = self.first_page + page;
+                let mut cache = self.cache.borrow_mut();
+                if cache.as_ref().map(|(cached, _)| *cached) != Some(number) {
+                    *cache = Some((number, self.decode(stored)));
+                }
+                cache.as_ref().and_then(|(_, rows)| rows.get(offset)).cloned().map(Cow::Owned)
+            }
+        }
+    }
+
+    // The text of every line, as `Row::text` has it, with its index. This
+    // is what searching goes through, so stored pages are only decoded as
+    // far as their text and no line is copied.
+    pub fn for_each_text(&self, mut f: impl FnMut(usize, &str)) {
+        let mut text = String::new();
+        for (n, page) in self.pages.iter().enumerate() {
+            let skip = if n == 0 { self.dropped } else { 0 };
+            let mut line = |i: usize, text: &str| {
+                if i >= skip {
+                    f(n * PAGE_ROWS + i - self.dropped, text);
+                }
+            };
+            match page {
+                Page::Rows(rows) => {
+                    for (i, row) in rows.iter().enumerate() {
+                        line(i, row_text(row, &mut text));
+                    }
+                }
+                Page::Compressed(data) => decode_text(data, &mut text, line),
+                Page::Spilled { offset, len } => {
+                    let mut data = vec![0; *len];
+                    if let Some(spill) = &self.spill {
+                        if spill.file.read_exact_at(&mut data, *offset).is_ok() {
+                            decode_text(&data, &mut text, line);
+                        }
+                    }
+                }
+            }
+        }
+        let first = self.pages.len() * PAGE_ROWS - self.dropped;
+        for (i, row) in self.recent.iter().enumerate() {
+            f(first + i, row_text(row, &mut text));
+        }
+    }
+
+    pub fn push(&mut self, row: Row) {
+        if self.max_lines == 0 {
+            return;
+        }
+        self.bytes += row_bytes(&row);
+        self.recent.push(row);
+        self.len += 1;
+        if self.recent.len() == PAGE_ROWS {
+            let rows = mem::take(&mut self.recent);
+            self.pages.push_back(Page::Rows(rows));
+            self.store_old_page();
+        }
+        while self.len > self.max_lines || (self.len > 0 && self.max_bytes.is_some_and(|max| self.bytes > max)) {
+            self.drop_oldest();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.first_page += self.pages.len();
+        self.pages.clear();
+        self.recent.clear();
+        self.dropped = 0;
+        self.len = 0;
+        self.bytes = 0;
+        if let Some(spill) = &mut self.spill {
+            if spill.file.set_len(0).is_ok() {
+                spill.len = 0;
+            }
+            spill.live = 0;
+        }
+        *self.cache.get_mut() = None;
+    }
+
+    // The page that just stopped being one of the newest is compressed,
+    // and maybe written out
+    fn store_old_page(&mut self) {
+        if self.storage == ScrollbackStorage::Memory || self.pages.len() <= HOT_PAGES {
+            return;
+        }
+        let index = self.pages.len() - HOT_PAGES - 1;
+        let Some(Page::Rows(rows)) = self.pages.get(index) else {
+            return;
+        };
+        let data = encode(rows);
+        let page = match self.storage {
+            ScrollbackStorage::Disk => match self.spill(&data) {
+                Ok(offset) => Page::Spilled { offset, len: data.len() },
+                // Keep it in memory, and the rest from now on
+                Err(_) => {
+                    self.storage = ScrollbackStorage::Compressed;
+                    Page::Compressed(data)
+                }
+            },
+            _ => Page::Compressed(data),
+        };
+        let old = mem::replace(&mut self.pages[index], page);
+        self.bytes = self.bytes - page_bytes(&old) + page_bytes(&self.pages[index]);
+    }
+
+    fn spill(&mut self, data: &[u8]) -> io::Result<u64> {
+        let spill = match &mut self.spill {
+            Some(spill) => spill,
+            None => self.spill.insert(SpillFile::create()?),
+        };
+        let offset = spill.len;
+        spill.file.write_all_at(data, offset)?;
+        spill.len += data.len() as u64;
+        spill.live += data.len() as u64;
+        Ok(offset)
+    }
+
+    fn drop_oldest(&mut self) {
+        self.len -= 1;
+        if self.pages.is_empty() {
+            let row = self.recent.remove(0);
+            self.bytes -= row_bytes(&row);
+            return;
+        }
+        // Rows of a page only go when all of them have
+        self.dropped += 1;
+        if self.dropped < PAGE_ROWS {
+            return;
+        }
+        self.dropped = 0;
+        self.first_page += 1;
+        if let Some(page) = self.pages.pop_front() {
+            self.bytes -= page_bytes(&page);
+            if let (Page::Spilled { len, .. }, Some(spill)) = (page, &mut self.spill) {
+                spill.live -= len as u64;
+                if spill.len >= COMPACT_MIN && spill.live * 2 < spill.len {
+                    self.compact_spill();
+                }
+            }
+        }
+    }
+
+    // Copy the pages still in the spill file to a new one, leaving the
+    // dropped ones behind
+    fn compact_spill(&mut self) {
+        let Some(old) = &self.spill else {
+            return;
+        };
+        let Ok(mut new) = SpillFile::create() else {
+            return;
+        };
+        let mut moved = Vec::new();
+        for (index, page) in self.pages.iter().enumerate() {
+            if let Page::Spilled { offset, len } = *page {
+                let mut data = vec![0; len];
+                if old.file.read_exact_at(&mut data, offset).is_err()
+                    || new.file.write_all_at(&data, new.len).is_err()
+                {
+                    return;
+                }
+                moved.push((index, new.len));
+                new.len += len as u64;
+                new.live += len as u64;
+            }
+        }
+        for (index, new_offset) in moved {
+            if let Page::Spilled { offset, .. } = &mut self.pages[index] {
+                *offset = new_offset;
+            }
+        }
+        self.spill = Some(new);
+    }
+
+    // The rows of a compressed or spilled page; none if it can't be read
+    fn decode(&self, page: &Page) -> Vec<Row> {
+        match page {
+            Page::Rows(rows) => rows.clone(),
+            Page::Compressed(data) => decode(data).unwrap_or_default(),
+            Page::Spilled { offset, len } => {
+                let mut data = vec![0; *len];
+                match &self.spill {
+                    Some(spill) if spill.file.read_exact_at(&mut data, *offset).is_ok() => {
+                        decode(&data).unwrap_or_default()
+                    }
+                    _ => Vec::new(),
+                }
+            }
+        }
+    }
+}
+
+impl SpillFile {
+    fn create() -> io::Result<Self> {
+        let number = SPILL_FILES.fetch_add(1, Ordering::Relaxed);
+        let path = env::temp_dir().join(format!("rte-scrollback-{}-{}", process::id(), number));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)?;
+        // Nothing needs to find it by name, and unlinked it goes away with
+        // us however we exit
+        fs::remove_file(&path)?;
+        Ok(Self { file, len: 0, live: 0 })
+    }
+}
+
+fn row_bytes(row: &Row) -> usize {
+    mem::size_of::<Row>() + row.cells.capacity() * mem::size_of::<Cell>()
+}
+
+fn page_bytes(page: &Page) -> usize {
+    match page {
+        Page::Rows(rows) => rows.iter().map(row_bytes).sum(),
+        Page::Compressed(data) => data.capacity(),
+        Page::Spilled { .. } => 0,
+    }
+}
+
+// Each row is its wrapped flag, its cell count and runs of cells with the
+// same attributes: the attributes, then the run's text, which has each
+// cell's character followed by its combining mark. The whole page is then
+// compressed with LZ4.
+fn encode(rows: &[Row]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for row in rows {
+        out.push(row.wrapped as u8);
+        out.extend_from_slice(&(row.cells.len() as u32).to_le_bytes());
+        let mut cells = row.cells.as_slice();
+        while let Some(first) = cells.first() {
+            let run = cells.iter().take_while(|cell| cell.attrs == first.attrs).count();
+            encode_attrs(&mut out, &first.attrs);
+            let mut text = String::new();
+            for cell in &cells[..run] {
+                text.push(cell.c);
+                text.extend(cell.combining);
+            }
+            out.extend_from_slice(&(text.len() as u32).to_le_bytes());
+            out.extend_from_slice(text.as_bytes());
+            cells = &cells[run..];
+        }
+    }
+    let mut compressed = lz4_flex::compress_prepend_size(&out);
+    compressed.shrink_to_fit();
+    compressed
+}
+
+fn encode_attrs(out: &mut Vec<u8>, attrs: &Attributes) {
+    for color in [attrs.fg, attrs.bg] {
+        match color {
+            CellColor::Default => out.push(0),
+            CellColor::Indexed(n) => out.extend_from_slice(&[1, n]),
+            CellColor::Rgb(r, g, b) => out.extend_from_slice(&[2, r, g, b]),
+        }
+    }
+    let flags = [attrs.bold, attrs.dim, attrs.italic, attrs.underline, attrs.inverse];
+    out.push(flags.iter().enumerate().fold(0, |byte, (bit, &set)| byte | (set as u8) << bit));
+}
+
+fn decode(data: &[u8]) -> Option<Vec<Row>> {
+    let bytes = lz4_flex::decompress_size_prepended(data).ok()?;
+    let mut reader = Reader(&bytes);
+    let mut rows = Vec::with_capacity(PAGE_ROWS);
+    while !reader.0.is_empty() {
+        let wrapped = reader.u8()? != 0;
+        let count = reader.u32()? as usize;
+        let mut cells: Vec<Cell> = Vec::with_capacity(count);
+        while cells.len() < count {
+            let attrs = reader.attrs()?;
+            let len = reader.u32()? as usize;
+            let text = std::str::from_utf8(reader.take(len)?).ok().filter(|text| !text.is_empty())?;
+            for c in text.chars() {
+                match cells.last_mut() {
+                    Some(cell) if is_combining(c) => cell.combining = Some(c),
+                    _ => cells.push(Cell::new(c, attrs)),
+                }
+            }
+        }
+        rows.push(Row { cells, wrapped });
+    }
+    Some(rows)
+}
+
+// Like `decode`, but only each row's text, without trailing blanks, into
+// `text`; a page that doesn't decode stops early
+fn decode_text(data: &[u8], text: &mut String, mut f: impl FnMut(usize, &str)) {
+    let Ok(bytes) = lz4_flex::decompress_size_prepended(data) else {
+        return;
+    };
+    let mut reader = Reader(&bytes);
+    let mut index = 0;
+    while !reader.0.is_empty() {
+        text.clear();
+        let Some(count) = reader.u8().and(reader.u32()) else {
+            return;
+        };
+        let mut cells = 0;
+        while cells < (count as usize) {
+            let run = reader.attrs().and(reader.u32()).and_then(|len| reader.take(len as usize));
+            let Some(run) = run.and_then(|run| std::str::from_utf8(run).ok()).filter(|run| !run.is_empty()) else {
+                return;
+            };
+            text.extend(run.chars().filter(|&c| c != WIDE_CONTINUATION));
+            cells += run.chars().filter(|&c| !is_combining(c)).count();
+        }
+        f(index, text.trim_end());
+        index += 1;
+    }
+}
+
+fn row_text<'a>(row: &Row, text: &'a mut String) -> &'a str {
+    text.clear();
+    for cell in &row.cells {
+        cell.push_text(text);
+    }
+    text.trim_end()
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (taken, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|bytes| bytes[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4)?.try_into().ok().map(u32::from_le_bytes)
+    }
+
+    fn color(&mut self) -> Option<CellColor> {
+        match self.u8()? {
+            0 => Some(CellColor::Default),
+            1 => self.u8().map(CellColor::Indexed),
+            2 => Some(CellColor::Rgb(self.u8()?, self.u8()?, self.u8()?)),
+            _ => None,
+        }
+    }
+
+    fn attrs(&mut self) -> Option<Attributes> {
+        let fg = self.color()?;
+        let bg = self.color()?;
+        let flags = self.u8()?;
+        let flag = |bit: u8| flags & (1 << bit) != 0;
+        Some(Attributes {
+            fg,
+            bg,
+            bold: flag(0),
+            dim: flag(1),
+            italic: flag(2),
+            underline: flag(3),
+            inverse: flag(4),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(n: usize) -> Row {
+        let mut row = Row::new(40);
+        for (cell, c) in row.cells.iter_mut().zip(format!("line {}", n).chars()) {
+            cell.c = c;
+        }
+        row.cells[0].attrs.fg = CellColor::Rgb(1, 2, 3);
+        row.cells[1].attrs.bold = true;
+        row.wrapped = n % 2 == 1;
+        row
+    }
+
+    fn filled(storage: ScrollbackStorage, max_lines: usize, max_bytes: Option<usize>, lines: usize) -> Scrollback {
+        let mut scrollback = Scrollback::new(max_lines, max_bytes, storage);
+        for n in 0..lines {
+            scrollback.push(row(n));
+        }
+        scrollback
+    }
+
+    #[test]
+    fn line_limit_drops_the_oldest() {
+        let scrollback = filled(ScrollbackStorage::Memory, 1000, None, 2500);
+        assert_eq!(scrollback.len(), 1000);
+        assert_eq!(scrollback.get(0).unwrap().text(), "line 1500");
+        assert_eq!(scrollback.get(999).unwrap().text(), "line 2499");
+        assert!(scrollback.get(1000).is_none());
+        assert!(filled(ScrollbackStorage::Memory, 0, None, 10).is_empty());
+    }
+
+    #[test]
+    fn stored_pages_read_back_the_same() {
+        let memory = filled(ScrollbackStorage::Memory, 20_000, None, 10_000);
+        for storage in [ScrollbackStorage::Compressed, ScrollbackStorage::Disk] {
+            let stored = filled(storage, 20_000, None, 10_000);
+            assert!(stored.bytes() < memory.bytes() / 3, "{:?}", storage);
+            for index in [0, 255, 256, 1234, 9999] {
+                assert_eq!(stored.get(index), memory.get(index), "{:?} {}", storage, index);
+            }
+            let mut seen = 0;
+            stored.for_each_text(|index, text| {
+                assert_eq!(text, format!("line {}", index));
+                seen += 1;
+            });
+            assert_eq!(seen, 10_000);
+        }
+    }
+
+    #[test]
+    fn byte_limit_drops_whole_pages() {
+        let page = filled(ScrollbackStorage::Memory, 1000, None, PAGE_ROWS).bytes();
+        let mut scrollback = filled(ScrollbackStorage::Memory, 100_000, Some(page * 3), PAGE_ROWS * 10);
+        assert!(scrollback.bytes() <= page * 3);
+        assert!(scrollback.len() > PAGE_ROWS * 2);
+        let last = scrollback.len() - 1;
+        assert_eq!(scrollback.get(last).unwrap().text(), format!("line {}", PAGE_ROWS * 10 - 1));
+        scrollback.clear();
+        assert_eq!((scrollback.len(), scrollback.bytes()), (0, 0));
+    }
+
+    #[test]
+    fn text_after_the_limits_drop_lines() {
+        let page = filled(ScrollbackStorage::Memory, 1000, None, PAGE_ROWS).bytes();
+        for storage in [ScrollbackStorage::Memory, ScrollbackStorage::Compressed, ScrollbackStorage::Disk] {
+            for (max_lines, max_bytes) in [(300, None), (1_000_000, Some(page * 3))] {
+                let scrollback = filled(storage, max_lines, max_bytes, PAGE_ROWS * 10 + 100);
+                let first = PAGE_ROWS * 10 + 100 - scrollback.len();
+                assert!(first > 0, "{:?}", storage);
+                let mut seen = 0;
+                scrollback.for_each_text(|index, text| {
+                    assert_eq!(index, seen);
+                    assert_eq!(text, format!("line {}", first + index), "{:?}", storage);
+                    seen += 1;
+                });
+                assert_eq!(seen, scrollback.len());
+            }
+        }
+    }
+
+    #[test]
+    fn wide_and_combining_cells_are_stored() {
+        let mut row = Row::new(4);
+        for (cell, c) in row.cells.iter_mut().zip(['中', WIDE_CONTINUATION, 'e']) {
+            cell.c = c;
+        }
+        row.cells[2].combining = Some('\u{301}');
+        let mut scrollback = Scrollback::new(10_000, None, ScrollbackStorage::Compressed);
+        for _ in 0..PAGE_ROWS * (HOT_PAGES + 1) {
+            scrollback.push(row.clone());
+        }
+        assert!(matches!(scrollback.pages[0], Page::Compressed(_)));
+        assert_eq!(scrollback.get(0).as_deref(), Some(&row));
+        scrollback.for_each_text(|_, text| assert_eq!(text, "中e\u{301}"));
+    }
+}
@@ -0,0 +1,494 @@
+// A long-lived interactive bash that commands are fed to one at a time, so
+// variables, aliases, functions and the working directory carry over from
+// one command to the next
+use std::cell::RefCell;
+use std::env;
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::pty::pty::PtyProcess;
+
+// The session is shared between the executor that starts it and the
+// terminal that shows its output
+pub type SharedSession = Rc<RefCell<ShellSession>>;
+
+// bash prints these around every command (see RC_FILE): `133;C` right after
+// it read a command line, `133;D;<status>`, `7;file://<host><cwd>` and
+// `1337;Jobs=<count>` just before it would show its prompt
+const COMMAND_START: &[u8] = b"\x1b]133;C";
+const COMMAND_DONE: &[u8] = b"\x1b]133;D";
+const WORKING_DIR: &[u8] = b"\x1b]7;";
+const JOB_COUNT: &[u8] = b"\x1b]1337;Jobs=";
+// Give up on a marker that doesn't end within this many bytes
+const MAX_MARKER_LEN: usize = 4096;
+
+// Keeps the rc files of several sessions (and tests) apart
+static RC_FILES: AtomicUsize = AtomicUsize::new(0);
+
+// Sourced instead of ~/.bashrc. The user's PROMPT_COMMAND still runs, between
+// saving $? and reporting it, and the prompts are blanked after it since
+// our own prompt is drawn by the terminal.
+const RC_FILE: &str = r#"[ -f ~/.bashrc ] && . ~/.bashrc
+__rte_save_status() {
+    __rte_status=$?
+    return $__rte_status
+}
+__rte_prompt() {
+    local __rte_jobs=($(jobs -p))
+    printf '\033]133;D;%s\007\033]7;file://%s%s\007\033]1337;Jobs=%s\007' \
+        "$__rte_status" "$HOSTNAME" "$PWD" "${#__rte_jobs[@]}"
+    PS1=''
+    PS2=''
+}
+PROMPT_COMMAND="__rte_save_status
+${PROMPT_COMMAND}
+__rte_prompt"
+PS0=$'\033]133;C\007'
+"#;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionEvent {
+    // The command handed to the shell finished with this exit status
+    Finished(i32),
+    DirectoryChanged(PathBuf),
+    // How many background jobs the shell has
+    JobsChanged(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    // Loading the rc files; output is shown as is
+    Starting,
+    // At the (invisible) prompt, waiting for a command
+    Idle,
+    // A command was sent; what arrives up to the first newline is the tty
+    // echoing it back
+    Echo,
+    Running,
+}
+
+// Splits the shell's output into what goes on screen and the markers bash
+// prints around each command
+pub struct MarkerScanner {
+    state: State,
+    // Start of a marker cut off at the end of the last chunk
+    partial: Vec<u8>,
+    cwd: Option<PathBuf>,
+    jobs: usize,
+}
+
+impl Default for MarkerScanner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MarkerScanner {
+    pub fn new() -> Self {
+        Self {
+            state: State::Starting,
+            partial: Vec::new(),
+            cwd: None,
+            jobs: 0,
+        }
+    }
+
+    // Mark a command as sent; the line it's echoed on is dropped
+    pub fn command_sent(&mut self) {
+        self.state = State::Echo;
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.state == State::Idle
+    }
+
+    pub fn is_busy(&self) -> bool {
+        matches!(self.state, State::Echo | State::Running)
+    }
+
+    // Returns the bytes to display, and appends any markers found to `events`
+    pub fn scan(&mut self, bytes: &[u8], events: &mut Vec<SessionEvent>) -> Vec<u8> {
+        let mut data = std::mem::take(&mut self.partial);
+        data.extend_from_slice(bytes);
+
+        let mut out = Vec::with_capacity(data.len());
+        let mut i = 0;
+        while i < data.len() {
+            let Some(esc) = data[i..].iter().position(|&b| b == 0x1b).map(|p| i + p) else {
+                self.emit(&data[i..], &mut out);
+                break;
+            };
+            self.emit(&data[i..esc], &mut out);
+
+            let rest = &data[esc..];
+            let markers = [COMMAND_START, COMMAND_DONE, WORKING_DIR, JOB_COUNT];
+            if !markers.iter().any(|marker| rest.starts_with(marker)) {
+                if markers.iter().any(|marker| marker.starts_with(rest)) {
+                    // Might be a marker whose rest hasn't arrived yet
+                    self.partial = rest.to_vec();
+                    break;
+                }
+                self.emit(&rest[..1], &mut out);
+                i = esc + 1;
+                continue;
+            }
+
+            match rest.iter().position(|&b| b == 0x07) {
+                Some(end) => {
+                    self.marker(&rest[2..end], events);
+                    i = esc + end + 1;
+                }
+                None if rest.len() < MAX_MARKER_LEN => {
+                    self.partial = rest.to_vec();
+                    break;
+                }
+                None => {
+                    self.emit(&rest[..1], &mut out);
+                    i = esc + 1;
+                }
+            }
+        }
+        out
+    }
+
+    // Everything after the echoed command line is shown, so a command bash
+    // can't parse, which never gets to `133;C`, still shows its error
+    fn emit(&mut self, bytes: &[u8], out: &mut Vec<u8>) {
+        if self.state != State::Echo {
+            out.extend_from_slice(bytes);
+            return;
+        }
+        if let Some(newline) = bytes.iter().position(|&b| b == b'\n') {
+            self.state = State::Running;
+            out.extend_from_slice(&bytes[newline + 1..]);
+        }
+    }
+
+    // Handle the body of a marker, without the leading `ESC ]` and the BEL
+    fn marker(&mut self, body: &[u8], events: &mut Vec<SessionEvent>) {
+        if body == b"133;C" {
+            if self.state == State::Echo {
+                self.state = State::Running;
+            }
+        } else if let Some(status) = body.strip_prefix(b"133;D") {
+            let status = status
+                .strip_prefix(b";")
+                .and_then(|s| std::str::from_utf8(s).ok())
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0);
+            if self.is_busy() {
+                events.push(SessionEvent::Finished(status));
+            }
+            self.state = State::Idle;
+        } else if let Some(url) = body.strip_prefix(b"7;file://") {
+            // The host is followed by the path, which bash prints unencoded
+            let Some(slash) = url.iter().position(|&b| b == b'/') else {
+                return;
+            };
+            let path = PathBuf::from(String::from_utf8_lossy(&url[slash..]).into_owned());
+            if self.cwd.as_ref() != Some(&path) {
+                self.cwd = Some(path.clone());
+                events.push(SessionEvent::DirectoryChanged(path));
+            }
+        } else if let Some(count) = body.strip_prefix(b"1337;Jobs=") {
+            let Some(jobs) = std::str::from_utf8(count).ok().and_then(|s| s.parse().ok()) else {
+                return;
+            };
+            if self.jobs != jobs {
+                self.jobs = jobs;
+                events.push(SessionEvent::JobsChanged(jobs));
+            }
+        }
+    }
+}
+
+pub struct ShellSession {
+    process: PtyProcess,
+    scanner: MarkerScanner,
+    events: Vec<SessionEvent>,
+    // Command waiting for the shell to finish starting up, and what was
+    // typed for it in the meantime
+    pending: Option<String>,
+    typeahead: Vec<u8>,
+    // Removed, with the directory it's in, once bash has read it
+    rc_file: Option<PathBuf>,
+}
+
+impl ShellSession {
+    pub fn start(cols: u16, rows: u16) -> io::Result<Self> {
+        Self::start_with("bash", cols, rows)
+    }
+
+    // Start `shell`, which has to understand bash's options and rc file
+    pub fn start_with(shell: &str, cols: u16, rows: u16) -> io::Result<Self> {
+        let rc_file = write_rc_file()?;
+
+        // Line editing is ours, so bash reads plain lines from the tty
+        let command = format!(
+            "exec {} --rcfile {} --noediting -i",
+            shell_quote(shell),
+            shell_quote(&rc_file.to_string_lossy())
+        );
+        let process = match PtyProcess::spawn_in(shell, &command, cols, rows) {
+            Ok(process) => process,
+            Err(e) => {
+                remove_rc_file(&rc_file);
+                return Err(e);
+            }
+        };
+
+        Ok(Self {
+            process,
+            scanner: MarkerScanner::new(),
+            events: Vec::new(),
+            pending: None,
+            typeahead: Vec::new(),
+            rc_file: Some(rc_file),
+        })
+    }
+
+    pub fn shared(self) -> SharedSession {
+        Rc::new(RefCell::new(self))
+    }
+
+    // Hand a command line to the shell
+    pub fn run(&mut self, command: &str) -> io::Result<()> {
+        if self.scanner.is_busy() || self.pending.is_some() {
+            return Err(io::Error::other("the shell is still running a command"));
+        }
+        if self.scanner.is_idle() {
+            self.send(command)
+        } else {
+            self.pending = Some(command.to_string());
+            Ok(())
+        }
+    }
+
+    fn send(&mut self, command: &str) -> io::Result<()> {
+        // bash would run each line of a multi-line command on its own, with
+        // a prompt in between, so those are passed as a single eval
+        let line = if command.contains('\n') {
+            format!("eval {}", ansi_c_quote(command))
+        } else {
+            command.to_string()
+        };
+        self.scanner.command_sent();
+        self.process.write_all(format!("{}\n", line).as_bytes())?;
+        if !self.typeahead.is_empty() {
+            let typeahead = std::mem::take(&mut self.typeahead);
+            self.process.write_all(&typeahead)?;
+        }
+        Ok(())
+    }
+    
+    // Input for the running command
+    pub fn write_all(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if self.pending.is_some() {
+            // Would otherwise be read by the shell before the command itself
+            self.typeahead.extend_from_slice(bytes);
+            return Ok(());
+        }
+        self.process.write_all(bytes)
+    }
+
+    // Whether a command is running, or waiting for the shell to start
+    pub fn is_busy(&self) -> bool {
+        self.scanner.is_busy() || self.pending.is_some()
+    }
+
+    // Output to display; markers found in it are kept for take_events
+    pub fn take_output(&mut self) -> io::Result<Vec<u8>> {
+        let raw = self.process.take_output();
+        let output = if raw.is_empty() {
+            Vec::new()
+        } else {
+            self.scanner.scan(&raw, &mut self.events)
+        };
+
+        if self.scanner.is_idle() {
+            if let Some(rc_file) = self.rc_file.take() {
+                remove_rc_file(&rc_file);
+            }
+            if let Some(command) = self.pending.take() {
+                self.send(&command)?;
+            }
+        }
+        Ok(output)
+    }
+
+    pub fn take_events(&mut self) -> Vec<SessionEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    // Whether the shell itself exited and all of its output has been taken
+    pub fn is_finished(&mut self) -> io::Result<bool> {
+        self.process.is_finished()
+    }
+
+    pub fn process(&self) -> &PtyProcess {
+        &self.process
+    }
+
+    pub fn process_mut(&mut self) -> &mut PtyProcess {
+        &mut self.process
+    }
+}
+
+impl Drop for ShellSession {
+    fn drop(&mut self) {
+        if let Some(rc_file) = &self.rc_file {
+            remove_rc_file(rc_file);
+        }
+    }
+}
+
+// The rc file goes in a directory of its own that only we can get into, so
+// no one else can swap in a file or a symlink of theirs for bash to source
+fn write_rc_file() -> io::Result<PathBuf> {
+    let base = env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from).unwrap_or_else(env::temp_dir);
+    let number = RC_FILES.fetch_add(1, Ordering::Relaxed);
+    let dir = base.join(format!("rust-terminal-emulator-{}-{}", std::process::id(), number));
+    DirBuilder::new().mode(0o700).create(&dir)?;
+    let rc_file = dir.join("bashrc");
+    let written = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&rc_file)
+        .and_then(|mut file| file.write_all(RC_FILE.as_bytes()));
+    if let Err(e) = written {
+        remove_rc_file(&rc_file);
+        return Err(e);
+    }
+    Ok(rc_file)
+}
+
+fn remove_rc_file(rc_file: &Path) {
+    let _ = fs::remove_file(rc_file);
+    if let Some(dir) = rc_file.parent() {
+        let _ = fs::remove_dir(dir);
+    }
+}
+
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+// Quote as $'...' so the result fits on a single line
+pub fn ansi_c_quote(s: &str) -> String {
+    let mut quoted = String::from("$'");
+    for c in s.chars() {
+        match c {
+            '\\' => quoted.push_str(r"\\"),
+            '\'' => quoted.push_str(r"\'"),
+            '\n' => quoted.push_str(r"\n"),
+            '\r' => quoted.push_str(r"\r"),
+            '\t' => quoted.push_str(r"\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('\'');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn idle_scanner() -> MarkerScanner {
+        let mut scanner = MarkerScanner::new();
+        scanner.scan(b"\x1b]133;D;0\x07", &mut Vec::new());
+        scanner
+    }
+
+    #[test]
+    fn startup_output_is_shown() {
+        let mut scanner = MarkerScanner::new();
+        let mut events = Vec::new();
+        let out = scanner.scan(b"motd\r\n\x1b]133;D;0\x07", &mut events);
+        assert_eq!(out, b"motd\r\n");
+        assert!(events.is_empty());
+        assert!(scanner.is_idle());
+    }
+
+    #[test]
+    fn echo_is_dropped_and_status_reported() {
+        let mut scanner = idle_scanner();
+        let mut events = Vec::new();
+        scanner.command_sent();
+        let out = scanner.scan(b"false\r\n\x1b]133;C\x07out\r\n\x1b]133;D;1\x07", &mut events);
+        assert_eq!(out, b"out\r\n");
+        assert_eq!(events, vec![SessionEvent::Finished(1)]);
+        assert!(scanner.is_idle());
+    }
+
+    #[test]
+    fn parse_errors_are_shown() {
+        let mut scanner = idle_scanner();
+        let mut events = Vec::new();
+        scanner.command_sent();
+        let out = scanner.scan(b"echo hi )\r\nbash: syntax error near unexpected token `)'\r\n\x1b]133;D;2\x07", &mut events);
+        assert_eq!(out, b"bash: syntax error near unexpected token `)'\r\n");
+        assert_eq!(events, vec![SessionEvent::Finished(2)]);
+    }
+
+    #[test]
+    fn markers_split_across_chunks() {
+        let mut scanner = idle_scanner();
+        let mut events = Vec::new();
+        scanner.command_sent();
+        let mut out = scanner.scan(b"ls\r\n\x1b]13", &mut events);
+        out.extend(scanner.scan(b"3;C\x07a\x1b", &mut events));
+        out.extend(scanner.scan(b"[1mb\x1b]133;D;", &mut events));
+        out.extend(scanner.scan(b"0\x07", &mut events));
+        assert_eq!(out, b"a\x1b[1mb");
+        assert_eq!(events, vec![SessionEvent::Finished(0)]);
+    }
+
+    #[test]
+    fn directory_changes_are_reported_once() {
+        let mut scanner = MarkerScanner::new();
+        let mut events = Vec::new();
+        scanner.scan(b"\x1b]7;file://host/tmp/a b\x07", &mut events);
+        scanner.scan(b"\x1b]7;file://host/tmp/a b\x07", &mut events);
+        assert_eq!(events, vec![SessionEvent::DirectoryChanged(PathBuf::from("/tmp/a b"))]);
+    }
+
+    #[test]
+    fn job_counts_are_reported_when_they_change() {
+        let mut scanner = idle_scanner();
+        let mut events = Vec::new();
+        let out = scanner.scan(b"\x1b]1337;Jobs=0\x07\x1b]1337;Jobs=2\x07\x1b]1337;Jobs=2\x07", &mut events);
+        assert!(out.is_empty());
+        assert_eq!(events, vec![SessionEvent::JobsChanged(2)]);
+    }
+
+    #[test]
+    fn other_escape_sequences_pass_through() {
+        let mut scanner = idle_scanner();
+        let out = scanner.scan(b"\x1b]0;title\x07\x1b[31mred", &mut Vec::new());
+        assert_eq!(out, b"\x1b]0;title\x07\x1b[31mred");
+    }
+
+    #[test]
+    fn rc_file_is_private() {
+        let rc_file = write_rc_file().unwrap();
+        let file_mode = fs::metadata(&rc_file).unwrap().permissions().mode() & 0o777;
+        let dir_mode = fs::metadata(rc_file.parent().unwrap()).unwrap().permissions().mode() & 0o777;
+        let contents = fs::read_to_string(&rc_file).unwrap();
+        remove_rc_file(&rc_file);
+        assert_eq!((file_mode, dir_mode), (0o600, 0o700));
+        assert_eq!(contents, RC_FILE);
+        assert!(!rc_file.parent().unwrap().exists());
+    }
+
+    #[test]
+    fn multi_line_commands_are_quoted_onto_one_line() {
+        assert_eq!(ansi_c_quote("a\nb 'c' \\"), r"$'a\nb \'c\' \\'");
+    }
+}
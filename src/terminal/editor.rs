@@ -0,0 +1,450 @@
+// Line editing for the input line: cursor movement, word motions, a kill
+// ring and undo/redo, with emacs-style (readline) key bindings
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use super::keymap::{Action, Keymap};
+
+// How many killed pieces of text are kept for yanking
+const KILL_RING_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Edit {
+    Other,
+    // Typing a word, which is undone as a whole
+    Insert,
+    KillForward,
+    KillBackward,
+    // Yanked text sits right before the cursor, starting at this position
+    Yank(usize),
+}
+
+#[derive(Default)]
+pub struct LineEditor {
+    text: String,
+    // Byte offset into text, always on a char boundary
+    cursor: usize,
+    kill_ring: Vec<String>,
+    // Entry of the kill ring Alt+Y last yanked
+    yank_index: usize,
+    undo_stack: Vec<(String, usize)>,
+    redo_stack: Vec<(String, usize)>,
+    last_edit: Option<Edit>,
+}
+
+impl LineEditor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    // The text before the cursor, which is what decides where it's drawn
+    pub fn before_cursor(&self) -> &str {
+        &self.text[..self.cursor]
+    }
+
+    // Replace the whole line (history recall), with the cursor at the end
+    pub fn set_text(&mut self, text: &str) {
+        self.text = text.to_string();
+        self.cursor = self.text.len();
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.last_edit = None;
+    }
+
+    pub fn clear(&mut self) {
+        self.set_text("");
+    }
+
+    // Apply an editing key with its default binding, or type it. Returns
+    // false for keys that aren't for the editor.
+    pub fn handle_key(&mut self, key: &KeyEvent) -> bool {
+        if let Some(action) = Keymap::default_action(key) {
+            return self.perform(action);
+        }
+        match key.code {
+            KeyCode::Char(c) if !key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => {
+                self.insert_char(c);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Returns false for actions that aren't about editing the line
+    pub fn perform(&mut self, action: Action) -> bool {
+        match action {
+            Action::BeginningOfLine => self.move_to(self.line_start()),
+            Action::EndOfLine => self.move_to(self.line_end()),
+            Action::BackwardChar => self.move_to(self.prev_char()),
+            Action::ForwardChar => self.move_to(self.next_char()),
+            Action::BackwardWord => self.move_to(self.prev_word()),
+            Action::ForwardWord => self.move_to(self.next_word()),
+            Action::BackwardDeleteChar => self.backspace(),
+            Action::DeleteChar => self.delete(),
+            Action::UnixLineDiscard => self.kill(self.line_start(), self.cursor),
+            // At the end of a line this joins it with the next
+            Action::KillLine if self.cursor == self.line_end() => self.kill(self.cursor, self.next_char()),
+            Action::KillLine => self.kill(self.cursor, self.line_end()),
+            Action::KillWord => self.kill(self.cursor, self.next_word()),
+            Action::BackwardKillWord => self.kill(self.prev_word(), self.cursor),
+            Action::UnixWordRubout => self.kill(self.prev_whitespace_word(), self.cursor),
+            Action::Yank => self.yank(),
+            Action::YankPop => self.yank_pop(),
+            Action::TransposeChars => self.transpose_chars(),
+            Action::Undo => self.undo(),
+            Action::Redo => self.redo(),
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn insert_char(&mut self, c: char) {
+        // A word and the space after it make one undo step
+        let continues_word = self.last_edit == Some(Edit::Insert)
+            && !self.before_cursor().ends_with(char::is_whitespace);
+        if !continues_word {
+            self.save_undo();
+        }
+        self.text.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+        self.last_edit = Some(Edit::Insert);
+    }
+
+    pub fn insert_str(&mut self, s: &str) {
+        self.save_undo();
+        self.text.insert_str(self.cursor, s);
+        self.cursor += s.len();
+        self.last_edit = Some(Edit::Other);
+    }
+
+    // Replace the text between `start` and the cursor, as completion does
+    pub fn replace_before_cursor(&mut self, start: usize, replacement: &str) {
+        self.save_undo();
+        self.text.replace_range(start..self.cursor, replacement);
+        self.cursor = start + replacement.len();
+        self.last_edit = Some(Edit::Other);
+    }
+
+    pub fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.delete_range(self.prev_char(), self.cursor);
+        }
+    }
+
+    pub fn delete(&mut self) {
+        if self.cursor < self.text.len() {
+            self.delete_range(self.cursor, self.next_char());
+        }
+    }
+
+    pub fn move_to(&mut self, position: usize) {
+        self.cursor = position.min(self.text.len());
+        self.last_edit = None;
+    }
+
+    // Move to the line above or below in multi-line input, keeping the
+    // column where possible. False when there's no line to move to.
+    pub fn move_line(&mut self, up: bool) -> bool {
+        let start = self.line_start();
+        let column = self.text[start..self.cursor].chars().count();
+        let target_start = if up {
+            if start == 0 {
+                return false;
+            }
+            self.text[..start - 1].rfind('\n').map_or(0, |i| i + 1)
+        } else {
+            match self.text[self.cursor..].find('\n') {
+                Some(i) => self.cursor + i + 1,
+                None => return false,
+            }
+        };
+        let line = &self.text[target_start..];
+        let line = &line[..line.find('\n').unwrap_or(line.len())];
+        let offset = line.char_indices().nth(column).map_or(line.len(), |(i, _)| i);
+        self.move_to(target_start + offset);
+        true
+    }
+
+    // Where the cursor's line starts and ends in multi-line input
+    pub fn line_start(&self) -> usize {
+        self.before_cursor().rfind('\n').map_or(0, |i| i + 1)
+    }
+
+    pub fn line_end(&self) -> usize {
+        self.text[self.cursor..].find('\n').map_or(self.text.len(), |i| self.cursor + i)
+    }
+
+    pub fn delete_range(&mut self, start: usize, end: usize) {
+        self.replace_range(start, end, "");
+    }
+
+    // Put `replacement` in place of the text between two byte offsets,
+    // leaving the cursor after it
+    pub fn replace_range(&mut self, start: usize, end: usize, replacement: &str) {
+        self.save_undo();
+        self.text.replace_range(start..end, replacement);
+        self.cursor = start + replacement.len();
+        self.last_edit = Some(Edit::Other);
+    }
+
+    // Cut text into the kill ring. Consecutive kills build up one entry,
+    // the way readline joins repeated Ctrl+K or Ctrl+W.
+    fn kill(&mut self, start: usize, end: usize) {
+        if start == end {
+            return;
+        }
+        let killed = self.text[start..end].to_string();
+        let forward = start == self.cursor;
+        let kind = if forward { Edit::KillForward } else { Edit::KillBackward };
+
+        match (self.last_edit, self.kill_ring.last_mut()) {
+            (Some(Edit::KillForward | Edit::KillBackward), Some(last)) => {
+                if forward {
+                    last.push_str(&killed);
+                } else {
+                    last.insert_str(0, &killed);
+                }
+            }
+            _ => {
+                self.kill_ring.push(killed);
+                if self.kill_ring.len() > KILL_RING_SIZE {
+                    self.kill_ring.remove(0);
+                }
+            }
+        }
+
+        self.save_undo();
+        self.text.replace_range(start..end, "");
+        self.cursor = start;
+        self.last_edit = Some(kind);
+    }
+
+    // Paste the most recent kill
+    fn yank(&mut self) {
+        let Some(text) = self.kill_ring.last().cloned() else {
+            return;
+        };
+        self.yank_index = self.kill_ring.len() - 1;
+        let start = self.cursor;
+        self.insert_str(&text);
+        self.last_edit = Some(Edit::Yank(start));
+    }
+
+    // Right after a yank, swap the yanked text for the kill before it
+    fn yank_pop(&mut self) {
+        let Some(Edit::Yank(start)) = self.last_edit else {
+            return;
+        };
+        if self.kill_ring.len() < 2 {
+            return;
+        }
+        self.yank_index = match self.yank_index {
+            0 => self.kill_ring.len() - 1,
+            n => n - 1,
+        };
+        let text = self.kill_ring[self.yank_index].clone();
+        self.text.replace_range(start..self.cursor, &text);
+        self.cursor = start + text.len();
+        self.last_edit = Some(Edit::Yank(start));
+    }
+
+    fn transpose_chars(&mut self) {
+        if self.cursor == 0 || self.text.chars().count() < 2 {
+            return;
+        }
+        // At the end of the line the last two characters are swapped
+        if self.cursor == self.text.len() {
+            self.cursor = self.prev_char();
+        }
+        let before = self.prev_char();
+        let after = self.next_char();
+        let swapped: String = self.text[self.cursor..after].chars().chain(self.text[before..self.cursor].chars()).collect();
+        self.save_undo();
+        self.text.replace_range(before..after, &swapped);
+        self.cursor = after;
+        self.last_edit = Some(Edit::Other);
+    }
+
+    pub fn undo(&mut self) {
+        if let Some((text, cursor)) = self.undo_stack.pop() {
+            self.redo_stack.push((std::mem::replace(&mut self.text, text), self.cursor));
+            self.cursor = cursor;
+        }
+        self.last_edit = None;
+    }
+
+    pub fn redo(&mut self) {
+        if let Some((text, cursor)) = self.redo_stack.pop() {
+            self.undo_stack.push((std::mem::replace(&mut self.text, text), self.cursor));
+            self.cursor = cursor;
+        }
+        self.last_edit = None;
+    }
+
+    fn save_undo(&mut self) {
+        self.undo_stack.push((self.text.clone(), self.cursor));
+        self.redo_stack.clear();
+    }
+
+    fn prev_char(&self) -> usize {
+        self.before_cursor().char_indices().next_back().map_or(0, |(i, _)| i)
+    }
+
+    fn next_char(&self) -> usize {
+        self.text[self.cursor..].chars().next().map_or(self.cursor, |c| self.cursor + c.len_utf8())
+    }
+
+    // Start of the word before the cursor, where words are runs of
+    // alphanumerics (Alt+B, Alt+Backspace)
+    pub fn prev_word(&self) -> usize {
+        let before = self.before_cursor();
+        let end = before.trim_end_matches(|c: char| !c.is_alphanumeric()).len();
+        before[..end].trim_end_matches(char::is_alphanumeric).len()
+    }
+
+    // End of the word after the cursor (Alt+F, Alt+D)
+    pub fn next_word(&self) -> usize {
+        let after = &self.text[self.cursor..];
+        let start = after.len() - after.trim_start_matches(|c: char| !c.is_alphanumeric()).len();
+        let rest = &after[start..];
+        self.cursor + start + (rest.len() - rest.trim_start_matches(char::is_alphanumeric).len())
+    }
+
+    // Start of the whitespace-delimited word before the cursor (Ctrl+W)
+    fn prev_whitespace_word(&self) -> usize {
+        let before = self.before_cursor().trim_end();
+        before.trim_end_matches(|c: char| !c.is_whitespace()).len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    fn ctrl(c: char) -> KeyEvent {
+        key(KeyCode::Char(c), KeyModifiers::CONTROL)
+    }
+
+    fn alt(c: char) -> KeyEvent {
+        key(KeyCode::Char(c), KeyModifiers::ALT)
+    }
+
+    fn editor(text: &str) -> LineEditor {
+        let mut editor = LineEditor::new();
+        for c in text.chars() {
+            editor.insert_char(c);
+        }
+        editor
+    }
+
+    #[test]
+    fn inserts_at_the_cursor() {
+        let mut e = editor("echo world");
+        e.handle_key(&alt('b'));
+        for c in "hello ".chars() {
+            e.insert_char(c);
+        }
+        assert_eq!(e.text(), "echo hello world");
+        assert_eq!(e.before_cursor(), "echo hello ");
+    }
+
+    #[test]
+    fn word_motions() {
+        let mut e = editor("git commit --amend");
+        e.handle_key(&alt('b'));
+        assert_eq!(e.before_cursor(), "git commit --");
+        e.handle_key(&alt('b'));
+        assert_eq!(e.before_cursor(), "git ");
+        e.handle_key(&alt('f'));
+        assert_eq!(e.before_cursor(), "git commit");
+        e.handle_key(&ctrl('a'));
+        assert_eq!(e.cursor(), 0);
+        e.handle_key(&ctrl('e'));
+        assert_eq!(e.cursor(), e.text().len());
+    }
+
+    #[test]
+    fn kills_and_yanks() {
+        let mut e = editor("ls -la /tmp");
+        e.handle_key(&ctrl('w'));
+        assert_eq!(e.text(), "ls -la ");
+        e.handle_key(&ctrl('w'));
+        assert_eq!(e.text(), "ls ");
+        // Both kills were joined into one entry
+        e.handle_key(&ctrl('y'));
+        assert_eq!(e.text(), "ls -la /tmp");
+
+        e.handle_key(&ctrl('a'));
+        e.handle_key(&ctrl('k'));
+        assert_eq!(e.text(), "");
+        e.handle_key(&ctrl('y'));
+        e.handle_key(&alt('y'));
+        assert_eq!(e.text(), "-la /tmp");
+    }
+
+    #[test]
+    fn delete_and_ctrl_u() {
+        let mut e = editor("abc");
+        e.handle_key(&key(KeyCode::Left, KeyModifiers::NONE));
+        e.handle_key(&key(KeyCode::Delete, KeyModifiers::NONE));
+        assert_eq!(e.text(), "ab");
+        e.handle_key(&ctrl('u'));
+        assert_eq!(e.text(), "");
+    }
+
+    #[test]
+    fn undo_and_redo_by_word() {
+        let mut e = editor("make test");
+        e.undo();
+        assert_eq!(e.text(), "make ");
+        e.undo();
+        assert_eq!(e.text(), "");
+        e.redo();
+        assert_eq!(e.text(), "make ");
+        e.handle_key(&ctrl('u'));
+        e.undo();
+        assert_eq!(e.text(), "make ");
+    }
+
+    #[test]
+    fn multibyte_characters() {
+        let mut e = editor("héé");
+        e.handle_key(&key(KeyCode::Left, KeyModifiers::NONE));
+        e.handle_key(&key(KeyCode::Backspace, KeyModifiers::NONE));
+        assert_eq!(e.text(), "hé");
+        assert_eq!(e.before_cursor(), "h");
+    }
+
+    #[test]
+    fn moves_across_lines() {
+        let mut editor = LineEditor::new();
+        editor.set_text("for f in *; do\n  echo $f\ndone");
+        assert!(!editor.move_line(false));
+        assert!(editor.move_line(true));
+        assert_eq!(editor.cursor(), 19);
+        editor.handle_key(&key(KeyCode::Home, KeyModifiers::NONE));
+        assert_eq!(editor.cursor(), 15);
+        assert!(editor.move_line(true));
+        assert_eq!(editor.cursor(), 0);
+        assert!(!editor.move_line(true));
+        editor.handle_key(&key(KeyCode::Char('e'), KeyModifiers::CONTROL));
+        assert_eq!(editor.cursor(), 14);
+        editor.handle_key(&key(KeyCode::Char('k'), KeyModifiers::CONTROL));
+        assert_eq!(editor.text(), "for f in *; do  echo $f\ndone");
+    }
+}
@@ -0,0 +1,325 @@
+// Syntax highlighting of the input line: each character gets the kind of
+// shell token it belongs to
+use std::env;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use crate::screen::cell::{Attributes, CellColor};
+use crate::theme::theme::Theme;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Highlight {
+    Plain,
+    Command,
+    UnknownCommand,
+    Keyword,
+    String,
+    Variable,
+    Operator,
+    Redirection,
+    Comment,
+    // An unterminated quote
+    Error,
+}
+
+impl Highlight {
+    pub fn attributes(self, theme: &Theme) -> Attributes {
+        let color = |n| CellColor::Indexed(n);
+        let mut attrs = Attributes::default();
+        match self {
+            Highlight::Plain => {}
+            Highlight::Command => attrs.fg = color(2),
+            Highlight::UnknownCommand => attrs.fg = theme.error,
+            Highlight::Keyword => {
+                attrs.fg = color(5);
+                attrs.bold = true;
+            }
+            Highlight::String => attrs.fg = color(3),
+            Highlight::Variable => attrs.fg = color(6),
+            Highlight::Operator => attrs.fg = color(4),
+            Highlight::Redirection => attrs.fg = color(5),
+            Highlight::Comment => attrs.fg = color(8),
+            Highlight::Error => {
+                attrs.fg = theme.error;
+                attrs.underline = true;
+            }
+        }
+        attrs
+    }
+}
+
+const KEYWORDS: &[&str] = &[
+    "if", "then", "else", "elif", "fi", "for", "while", "until", "do", "done", "case", "esac", "select", "function",
+    "time", "!", "{", "}", "[[", "]]",
+];
+
+// Builtins bash runs itself, which aren't in $PATH
+const SHELL_BUILTINS: &[&str] = &[
+    ".", ":", "alias", "bg", "bind", "break", "builtin", "cd", "command", "compgen", "complete", "continue",
+    "declare", "dirs", "disown", "echo", "enable", "eval", "exec", "exit", "export", "false", "fc", "fg", "getopts",
+    "hash", "help", "history", "jobs", "kill", "let", "local", "logout", "mapfile", "popd", "printf", "pushd", "pwd",
+    "read", "readonly", "return", "set", "shift", "shopt", "source", "test", "[", "times", "trap", "true", "type",
+    "typeset", "ulimit", "umask", "unalias", "unset", "wait",
+];
+
+// Whether bash would find `name` as a builtin or a program
+pub fn is_shell_command(name: &str, cwd: &Path) -> bool {
+    if SHELL_BUILTINS.contains(&name) {
+        return true;
+    }
+    if name.contains('/') {
+        let path = match name.strip_prefix("~/") {
+            Some(rest) => dirs::home_dir().map_or_else(|| Path::new(name).to_path_buf(), |home| home.join(rest)),
+            None => cwd.join(name),
+        };
+        return is_executable(&path);
+    }
+    env::var_os("PATH").is_some_and(|paths| env::split_paths(&paths).any(|dir| is_executable(&dir.join(name))))
+}
+
+fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+// The highlight of every character of `line`. `is_command` says whether a
+// command word names something that can run.
+pub fn highlight(line: &str, is_command: impl Fn(&str) -> bool) -> Vec<Highlight> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut styles = vec![Highlight::Plain; chars.len()];
+    let mut command_position = true;
+    let mut redirect_target = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            // Each line of multi-line input is a new command
+            if c == '\n' {
+                command_position = true;
+                redirect_target = false;
+            }
+            i += 1;
+            continue;
+        }
+        if c == '#' {
+            styles[i..].fill(Highlight::Comment);
+            break;
+        }
+        if let Some(len) = redirection_len(&chars[i..]) {
+            styles[i..i + len].fill(Highlight::Redirection);
+            redirect_target = true;
+            i += len;
+            continue;
+        }
+        if is_operator(c) {
+            let doubled = chars.get(i + 1) == Some(&c) && matches!(c, '|' | '&' | ';');
+            let len = if doubled { 2 } else { 1 };
+            styles[i..i + len].fill(Highlight::Operator);
+            command_position = true;
+            i += len;
+            continue;
+        }
+
+        // A word, made of plain, quoted and variable parts
+        let start = i;
+        let mut value = String::new();
+        let mut quoted = false;
+        while i < chars.len() && !chars[i].is_whitespace() && !is_operator(chars[i]) && !matches!(chars[i], '<' | '>') {
+            match chars[i] {
+                '\\' => {
+                    value.extend(chars.get(i + 1));
+                    i = (i + 2).min(chars.len());
+                }
+                '\'' => {
+                    quoted = true;
+                    let end = chars[i + 1..].iter().position(|&c| c == '\'').map(|p| i + 1 + p);
+                    let Some(end) = end else {
+                        styles[i..].fill(Highlight::Error);
+                        return styles;
+                    };
+                    styles[i..=end].fill(Highlight::String);
+                    value.extend(&chars[i + 1..end]);
+                    i = end + 1;
+                }
+                '"' => {
+                    quoted = true;
+                    let open = i;
+                    i += 1;
+                    loop {
+                        match chars.get(i) {
+                            None => {
+                                styles[open..].fill(Highlight::Error);
+                                return styles;
+                            }
+                            Some('"') => break,
+                            Some('\\') => {
+                                value.extend(chars.get(i + 1));
+                                let end = (i + 2).min(chars.len());
+                                styles[i..end].fill(Highlight::String);
+                                i = end;
+                            }
+                            Some('$') if variable_len(&chars[i..]) > 1 => {
+                                let len = variable_len(&chars[i..]);
+                                styles[i..i + len].fill(Highlight::Variable);
+                                value.extend(&chars[i..i + len]);
+                                i += len;
+                            }
+                            Some(&c) => {
+                                styles[i] = Highlight::String;
+                                value.push(c);
+                                i += 1;
+                            }
+                        }
+                    }
+                    styles[open] = Highlight::String;
+                    styles[i] = Highlight::String;
+                    i += 1;
+                }
+                '$' if variable_len(&chars[i..]) > 1 => {
+                    let len = variable_len(&chars[i..]);
+                    styles[i..i + len].fill(Highlight::Variable);
+                    value.extend(&chars[i..i + len]);
+                    i += len;
+                }
+                c => {
+                    value.push(c);
+                    i += 1;
+                }
+            }
+        }
+
+        if redirect_target {
+            redirect_target = false;
+            continue;
+        }
+        if !command_position {
+            continue;
+        }
+
+        // `NAME=value` before the command
+        if let Some(eq) = assignment_len(&chars[start..i]) {
+            styles[start..start + eq].fill(Highlight::Variable);
+            continue;
+        }
+        let kind = if !quoted && KEYWORDS.contains(&value.as_str()) {
+            // The word after these isn't a command
+            command_position = !matches!(value.as_str(), "for" | "case" | "select" | "function");
+            Highlight::Keyword
+        } else {
+            command_position = false;
+            if is_command(&value) {
+                Highlight::Command
+            } else {
+                Highlight::UnknownCommand
+            }
+        };
+        for style in &mut styles[start..i] {
+            if *style == Highlight::Plain {
+                *style = kind;
+            }
+        }
+    }
+    styles
+}
+
+fn is_operator(c: char) -> bool {
+    matches!(c, '|' | '&' | ';' | '(' | ')')
+}
+
+// `>`, `>>`, `2>`, `2>&1`, `&>`, `<`, `<<<` and the like
+fn redirection_len(chars: &[char]) -> Option<usize> {
+    let mut i = chars.iter().take_while(|c| c.is_ascii_digit()).count();
+    if i == 0 && chars.first() == Some(&'&') && chars.get(1) == Some(&'>') {
+        i = 1;
+    }
+    let op = *chars.get(i)?;
+    if op != '<' && op != '>' {
+        return None;
+    }
+    i += 1;
+    // Up to `<<<`
+    for _ in 0..2 {
+        if chars.get(i) == Some(&op) {
+            i += 1;
+        }
+    }
+    // `2>&1`, `>&-`
+    if chars.get(i) == Some(&'&') {
+        i += 1;
+        i += chars[i..].iter().take_while(|c| c.is_ascii_digit() || **c == '-').count();
+    }
+    Some(i)
+}
+
+// `$NAME`, `${...}`, `$1`, `$?` and friends; 1 for a lone `$`
+fn variable_len(chars: &[char]) -> usize {
+    match chars.get(1) {
+        Some('{') => chars.iter().position(|&c| c == '}').map_or(chars.len(), |end| end + 1),
+        Some(c) if c.is_ascii_digit() || matches!(c, '?' | '$' | '!' | '#' | '@' | '*' | '-') => 2,
+        Some(c) if c.is_ascii_alphabetic() || *c == '_' => {
+            1 + chars[1..].iter().take_while(|c| c.is_ascii_alphanumeric() || **c == '_').count()
+        }
+        _ => 1,
+    }
+}
+
+// Length of `NAME` in a `NAME=value` word
+fn assignment_len(word: &[char]) -> Option<usize> {
+    let eq = word.iter().position(|&c| c == '=')?;
+    let name = &word[..eq];
+    let valid = !name.is_empty()
+        && !name[0].is_ascii_digit()
+        && name.iter().all(|c| c.is_ascii_alphanumeric() || *c == '_');
+    valid.then_some(eq)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Highlight::*;
+
+    fn styles(line: &str) -> Vec<Highlight> {
+        highlight(line, |name| name == "ls" || name == "grep")
+    }
+
+    // The highlight of each whitespace-separated part
+    fn parts(line: &str) -> Vec<Highlight> {
+        let styles = styles(line);
+        let mut parts = Vec::new();
+        let mut offset = 0;
+        for part in line.split(' ') {
+            if let Some(&style) = styles.get(offset) {
+                parts.push(style);
+            }
+            offset += part.chars().count() + 1;
+        }
+        parts
+    }
+
+    #[test]
+    fn commands_and_arguments() {
+        assert_eq!(parts("ls -l | grep x"), vec![Command, Plain, Operator, Command, Plain]);
+        assert_eq!(parts("lss -l && ls"), vec![UnknownCommand, Plain, Operator, Command]);
+        assert_eq!(parts("FOO=1 ls # all"), vec![Variable, Command, Comment, Comment]);
+        assert_eq!(parts("if ls; then nope; fi"), vec![Keyword, Command, Keyword, UnknownCommand, Keyword]);
+        assert_eq!(styles("ls |\nls")[5], Command);
+    }
+
+    #[test]
+    fn strings_variables_and_redirections() {
+        assert_eq!(parts("ls 'a b' $HOME >out 2>&1"), vec![Command, String, String, Variable, Redirection, Redirection]);
+        let quoted = styles("ls \"x $HOME\"");
+        assert_eq!(quoted[3], String);
+        assert_eq!(quoted[6], Variable);
+        assert_eq!(quoted[11], String);
+        // The redirection target isn't a command
+        assert_eq!(parts("> out"), vec![Redirection, Plain]);
+    }
+
+    #[test]
+    fn flags_unterminated_quotes() {
+        assert_eq!(styles("ls \"abc")[3..], [Error; 4]);
+        assert_eq!(styles("ls 'a")[3..], [Error; 2]);
+    }
+}
@@ -0,0 +1,205 @@
+// Reverse incremental search through the command history (Ctrl+R)
+use std::collections::HashSet;
+
+use crate::history::history::HistoryEntry;
+
+pub struct HistoryMatch {
+    // Index into the history
+    pub index: usize,
+    // Character positions of the command that matched the query
+    pub positions: Vec<usize>,
+}
+
+pub struct HistorySearch {
+    query: String,
+    // Input line to go back to when the search is cancelled
+    original: String,
+    matches: Vec<HistoryMatch>,
+    // Which match is shown, 0 being the most recent
+    selected: usize,
+}
+
+impl HistorySearch {
+    pub fn new(original: &str, history: &[HistoryEntry]) -> Self {
+        let mut search = Self {
+            query: String::new(),
+            original: original.to_string(),
+            matches: Vec::new(),
+            selected: 0,
+        };
+        search.update(history);
+        search
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn original(&self) -> &str {
+        &self.original
+    }
+
+    // No match for a non-empty query
+    pub fn failed(&self) -> bool {
+        !self.query.is_empty() && self.matches.is_empty()
+    }
+
+    pub fn current(&self) -> Option<&HistoryMatch> {
+        self.matches.get(self.selected)
+    }
+
+    pub fn push(&mut self, c: char, history: &[HistoryEntry]) {
+        self.query.push(c);
+        self.update(history);
+    }
+
+    pub fn pop(&mut self, history: &[HistoryEntry]) {
+        self.query.pop();
+        self.update(history);
+    }
+
+    // Repeated Ctrl+R: the next older match
+    pub fn older(&mut self) {
+        if self.selected + 1 < self.matches.len() {
+            self.selected += 1;
+        }
+    }
+
+    // Ctrl+S: back to a more recent match
+    pub fn newer(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    // Collect the matches, newest first and each command once. Commands
+    // containing the query come before ones that only match fuzzily.
+    fn update(&mut self, history: &[HistoryEntry]) {
+        self.selected = 0;
+        self.matches.clear();
+        if self.query.is_empty() {
+            return;
+        }
+
+        let mut seen: HashSet<&str> = HashSet::new();
+        let mut fuzzy = Vec::new();
+        for (index, entry) in history.iter().enumerate().rev() {
+            let command = &entry.command;
+            if seen.contains(command.as_str()) {
+                continue;
+            }
+            if let Some(positions) = substring_match(command, &self.query) {
+                self.matches.push(HistoryMatch { index, positions });
+                seen.insert(command);
+            } else if let Some(positions) = fuzzy_match(command, &self.query) {
+                fuzzy.push(HistoryMatch { index, positions });
+                seen.insert(command);
+            }
+        }
+        self.matches.extend(fuzzy);
+    }
+}
+
+// Search is case-insensitive unless the query has capitals in it
+fn chars_equal(a: char, b: char, ignore_case: bool) -> bool {
+    if ignore_case {
+        a.to_lowercase().eq(b.to_lowercase())
+    } else {
+        a == b
+    }
+}
+
+fn ignores_case(query: &str) -> bool {
+    !query.chars().any(char::is_uppercase)
+}
+
+fn substring_match(command: &str, query: &str) -> Option<Vec<usize>> {
+    let ignore_case = ignores_case(query);
+    let command: Vec<char> = command.chars().collect();
+    let query: Vec<char> = query.chars().collect();
+    // The last occurrence, as that's the one closest to what was typed last
+    (0..=command.len().checked_sub(query.len())?)
+        .rev()
+        .find(|&start| {
+            query
+                .iter()
+                .enumerate()
+                .all(|(i, &q)| chars_equal(command[start + i], q, ignore_case))
+        })
+        .map(|start| (start..start + query.len()).collect())
+}
+
+// The query's characters appear in the command in order
+fn fuzzy_match(command: &str, query: &str) -> Option<Vec<usize>> {
+    let ignore_case = ignores_case(query);
+    let mut positions = Vec::new();
+    let mut chars = command.chars().enumerate();
+    for q in query.chars().filter(|c| !c.is_whitespace()) {
+        let (position, _) = chars.find(|&(_, c)| chars_equal(c, q, ignore_case))?;
+        positions.push(position);
+    }
+    Some(positions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history() -> Vec<HistoryEntry> {
+        ["git status", "cargo build", "git commit -m wip", "cargo test", "git status"]
+            .iter()
+            .map(|command| HistoryEntry::new(command, std::path::Path::new("/")))
+            .collect()
+    }
+
+    fn commands(search: &HistorySearch, history: &[HistoryEntry]) -> Vec<String> {
+        search.matches.iter().map(|m| history[m.index].command.clone()).collect()
+    }
+
+    #[test]
+    fn substring_matches_newest_first_without_duplicates() {
+        let history = history();
+        let mut search = HistorySearch::new("", &history);
+        for c in "git".chars() {
+            search.push(c, &history);
+        }
+        assert_eq!(commands(&search, &history), vec!["git status", "git commit -m wip"]);
+        assert_eq!(search.current().unwrap().positions, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn fuzzy_matches_come_last() {
+        let history = history();
+        let mut search = HistorySearch::new("", &history);
+        for c in "ct".chars() {
+            search.push(c, &history);
+        }
+        assert_eq!(commands(&search, &history), vec!["cargo test", "git commit -m wip"]);
+        search.pop(&history);
+        search.push('b', &history);
+        assert_eq!(commands(&search, &history), vec!["cargo build"]);
+    }
+
+    #[test]
+    fn cycling_and_failure() {
+        let history = history();
+        let mut search = HistorySearch::new("ls", &history);
+        search.push('c', &history);
+        search.push('a', &history);
+        assert_eq!(history[search.current().unwrap().index].command, "cargo test");
+        search.older();
+        assert_eq!(history[search.current().unwrap().index].command, "cargo build");
+        search.older();
+        assert_eq!(history[search.current().unwrap().index].command, "cargo build");
+        search.newer();
+        assert_eq!(history[search.current().unwrap().index].command, "cargo test");
+
+        search.push('z', &history);
+        assert!(search.failed());
+        assert_eq!(search.original(), "ls");
+    }
+
+    #[test]
+    fn smart_case() {
+        assert!(substring_match("Makefile", "make").is_some());
+        assert!(substring_match("makefile", "Make").is_none());
+    }
+}
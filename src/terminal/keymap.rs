@@ -0,0 +1,717 @@
+// What keys do: key chords, or sequences of them such as `ctrl-x ctrl-u`,
+// bound to named actions. At the prompt the defaults are readline's emacs
+// bindings; the completion menu, the searches and running programs have
+// single keys of their own. The `[keybindings]` section of the config file
+// can change or remove any of them.
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::OnceLock;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::de::{self, DeserializeSeed, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+
+// The bindings the prompt starts from: readline's emacs ones, or vi's
+// modes on top of them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EditMode {
+    #[default]
+    Emacs,
+    Vi,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Action {
+    Quit,
+    // Run the input, or start a new line when it's unfinished
+    AcceptLine,
+    InsertNewline,
+    // Up a line in multi-line input, else the previous history entry
+    HistoryPrev,
+    HistoryNext,
+    ScrollUp,
+    ScrollDown,
+    ScrollPageUp,
+    ScrollPageDown,
+    ScrollToBottom,
+    Complete,
+    SearchHistory,
+    // Find text in the scrollback and on screen
+    SearchScrollback,
+    ClearScreen,
+    BeginningOfLine,
+    EndOfLine,
+    BackwardChar,
+    ForwardChar,
+    BackwardWord,
+    ForwardWord,
+    BackwardDeleteChar,
+    DeleteChar,
+    // Cut to the end of the line
+    KillLine,
+    // Cut to the start of the line
+    UnixLineDiscard,
+    KillWord,
+    BackwardKillWord,
+    // Cut the whitespace-delimited word before the cursor
+    UnixWordRubout,
+    Yank,
+    YankPop,
+    TransposeChars,
+    Undo,
+    Redo,
+    // In the completion menu
+    MenuNext,
+    MenuPrevious,
+    // Keep the candidate filled in
+    MenuAccept,
+    // Go back to what was typed
+    MenuCancel,
+    // In the history and scrollback searches
+    SearchOlder,
+    SearchNewer,
+    // Take the match, or in the scrollback search go from typing the query
+    // to stepping through the matches, then stop there
+    SearchAccept,
+    // Go back to where things were before the search
+    SearchCancel,
+    ToggleRegex,
+    // Smart case, match case and ignore case in turn
+    ToggleCase,
+    // Start a new query
+    EditSearch,
+}
+
+const ACTION_NAMES: &[(Action, &str)] = &[
+    (Action::Quit, "quit"),
+    (Action::AcceptLine, "accept-line"),
+    (Action::InsertNewline, "insert-newline"),
+    (Action::HistoryPrev, "history-prev"),
+    (Action::HistoryNext, "history-next"),
+    (Action::ScrollUp, "scroll-up"),
+    (Action::ScrollDown, "scroll-down"),
+    (Action::ScrollPageUp, "scroll-page-up"),
+    (Action::ScrollPageDown, "scroll-page-down"),
+    (Action::ScrollToBottom, "scroll-to-bottom"),
+    (Action::Complete, "complete"),
+    (Action::SearchHistory, "search-history"),
+    (Action::SearchScrollback, "search-scrollback"),
+    (Action::ClearScreen, "clear-screen"),
+    (Action::BeginningOfLine, "beginning-of-line"),
+    (Action::EndOfLine, "end-of-line"),
+    (Action::BackwardChar, "backward-char"),
+    (Action::ForwardChar, "forward-char"),
+    (Action::BackwardWord, "backward-word"),
+    (Action::ForwardWord, "forward-word"),
+    (Action::BackwardDeleteChar, "backward-delete-char"),
+    (Action::DeleteChar, "delete-char"),
+    (Action::KillLine, "kill-line"),
+    (Action::UnixLineDiscard, "unix-line-discard"),
+    (Action::KillWord, "kill-word"),
+    (Action::BackwardKillWord, "backward-kill-word"),
+    (Action::UnixWordRubout, "unix-word-rubout"),
+    (Action::Yank, "yank"),
+    (Action::YankPop, "yank-pop"),
+    (Action::TransposeChars, "transpose-chars"),
+    (Action::Undo, "undo"),
+    (Action::Redo, "redo"),
+    (Action::MenuNext, "menu-next"),
+    (Action::MenuPrevious, "menu-previous"),
+    (Action::MenuAccept, "menu-accept"),
+    (Action::MenuCancel, "menu-cancel"),
+    (Action::SearchOlder, "search-older"),
+    (Action::SearchNewer, "search-newer"),
+    (Action::SearchAccept, "search-accept"),
+    (Action::SearchCancel, "search-cancel"),
+    (Action::ToggleRegex, "toggle-regex"),
+    (Action::ToggleCase, "toggle-case"),
+    (Action::EditSearch, "edit-search"),
+];
+
+// Readline's emacs mode, plus the arrows and keys of our own
+const DEFAULT_BINDINGS: &[(&str, Action)] = &[
+    ("esc", Action::Quit),
+    ("enter", Action::AcceptLine),
+    ("alt-enter", Action::InsertNewline),
+    ("up", Action::HistoryPrev),
+    ("ctrl-p", Action::HistoryPrev),
+    ("down", Action::HistoryNext),
+    ("ctrl-n", Action::HistoryNext),
+    ("ctrl-up", Action::ScrollUp),
+    ("ctrl-down", Action::ScrollDown),
+    ("pageup", Action::ScrollPageUp),
+    ("pagedown", Action::ScrollPageDown),
+    ("tab", Action::Complete),
+    ("ctrl-r", Action::SearchHistory),
+    ("ctrl-s", Action::SearchScrollback),
+    ("ctrl-l", Action::ClearScreen),
+    ("ctrl-a", Action::BeginningOfLine),
+    ("home", Action::BeginningOfLine),
+    ("ctrl-e", Action::EndOfLine),
+    ("end", Action::EndOfLine),
+    ("ctrl-b", Action::BackwardChar),
+    ("left", Action::BackwardChar),
+    ("ctrl-f", Action::ForwardChar),
+    ("right", Action::ForwardChar),
+    ("alt-b", Action::BackwardWord),
+    ("ctrl-left", Action::BackwardWord),
+    ("alt-left", Action::BackwardWord),
+    ("alt-f", Action::ForwardWord),
+    ("ctrl-right", Action::ForwardWord),
+    ("alt-right", Action::ForwardWord),
+    ("backspace", Action::BackwardDeleteChar),
+    ("ctrl-h", Action::BackwardDeleteChar),
+    ("delete", Action::DeleteChar),
+    ("ctrl-d", Action::DeleteChar),
+    ("ctrl-k", Action::KillLine),
+    ("ctrl-u", Action::UnixLineDiscard),
+    ("alt-d", Action::KillWord),
+    ("alt-backspace", Action::BackwardKillWord),
+    ("ctrl-backspace", Action::BackwardKillWord),
+    ("ctrl-w", Action::UnixWordRubout),
+    ("ctrl-y", Action::Yank),
+    ("alt-y", Action::YankPop),
+    ("ctrl-t", Action::TransposeChars),
+    // Ctrl+_ arrives as Ctrl+7 from most terminals
+    ("ctrl-_", Action::Undo),
+    ("ctrl-7", Action::Undo),
+    ("ctrl-/", Action::Undo),
+    ("ctrl-x ctrl-u", Action::Undo),
+    ("alt-_", Action::Redo),
+    ("alt-/", Action::Redo),
+];
+
+// Where a key is pressed, each place with bindings of its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyContext {
+    Prompt,
+    // The completion menu is open
+    Menu,
+    HistorySearch,
+    ScrollbackSearch,
+    // A program is running; keys not bound here go to it
+    Running,
+}
+
+// Their own bindings, one key each. In the searches, characters typed while
+// the query is being typed go into it whatever they're bound to.
+const CONTEXT_BINDINGS: &[(KeyContext, &str, Action)] = &[
+    (KeyContext::Menu, "tab", Action::MenuNext),
+    (KeyContext::Menu, "down", Action::MenuNext),
+    (KeyContext::Menu, "right", Action::MenuNext),
+    (KeyContext::Menu, "shift-tab", Action::MenuPrevious),
+    (KeyContext::Menu, "up", Action::MenuPrevious),
+    (KeyContext::Menu, "left", Action::MenuPrevious),
+    (KeyContext::Menu, "enter", Action::MenuAccept),
+    (KeyContext::Menu, "esc", Action::MenuCancel),
+    (KeyContext::HistorySearch, "ctrl-r", Action::SearchOlder),
+    (KeyContext::HistorySearch, "ctrl-s", Action::SearchNewer),
+    (KeyContext::HistorySearch, "enter", Action::SearchAccept),
+    (KeyContext::HistorySearch, "esc", Action::SearchCancel),
+    (KeyContext::HistorySearch, "ctrl-g", Action::SearchCancel),
+    (KeyContext::HistorySearch, "ctrl-c", Action::SearchCancel),
+    (KeyContext::HistorySearch, "backspace", Action::BackwardDeleteChar),
+    (KeyContext::ScrollbackSearch, "up", Action::SearchOlder),
+    (KeyContext::ScrollbackSearch, "ctrl-p", Action::SearchOlder),
+    (KeyContext::ScrollbackSearch, "n", Action::SearchOlder),
+    (KeyContext::ScrollbackSearch, "down", Action::SearchNewer),
+    (KeyContext::ScrollbackSearch, "ctrl-n", Action::SearchNewer),
+    (KeyContext::ScrollbackSearch, "N", Action::SearchNewer),
+    (KeyContext::ScrollbackSearch, "enter", Action::SearchAccept),
+    (KeyContext::ScrollbackSearch, "q", Action::SearchAccept),
+    (KeyContext::ScrollbackSearch, "esc", Action::SearchCancel),
+    (KeyContext::ScrollbackSearch, "ctrl-g", Action::SearchCancel),
+    (KeyContext::ScrollbackSearch, "ctrl-c", Action::SearchCancel),
+    (KeyContext::ScrollbackSearch, "backspace", Action::BackwardDeleteChar),
+    (KeyContext::ScrollbackSearch, "alt-r", Action::ToggleRegex),
+    (KeyContext::ScrollbackSearch, "alt-c", Action::ToggleCase),
+    (KeyContext::ScrollbackSearch, "/", Action::EditSearch),
+    (KeyContext::Running, "shift-pageup", Action::ScrollPageUp),
+    (KeyContext::Running, "shift-pagedown", Action::ScrollPageDown),
+];
+
+impl KeyContext {
+    // The sub-table of `[keybindings]` with its bindings
+    fn from_section(name: &str) -> Option<Self> {
+        match name {
+            "menu" => Some(KeyContext::Menu),
+            "history-search" => Some(KeyContext::HistorySearch),
+            "scrollback-search" => Some(KeyContext::ScrollbackSearch),
+            "running" => Some(KeyContext::Running),
+            _ => None,
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            KeyContext::Prompt => "at the prompt",
+            KeyContext::Menu => "in the completion menu",
+            KeyContext::HistorySearch => "in the history search",
+            KeyContext::ScrollbackSearch => "in the scrollback search",
+            KeyContext::Running => "while a program runs",
+        }
+    }
+
+    // Whether an action means anything here
+    pub fn allows(self, action: Action) -> bool {
+        let menu = matches!(action, Action::MenuNext | Action::MenuPrevious | Action::MenuAccept | Action::MenuCancel);
+        let search = matches!(
+            action,
+            Action::SearchOlder | Action::SearchNewer | Action::SearchAccept | Action::SearchCancel | Action::BackwardDeleteChar
+        );
+        let scrollback_search = matches!(action, Action::ToggleRegex | Action::ToggleCase | Action::EditSearch);
+        match self {
+            KeyContext::Prompt => action == Action::BackwardDeleteChar || !(menu || search || scrollback_search),
+            KeyContext::Menu => menu,
+            KeyContext::HistorySearch => search,
+            KeyContext::ScrollbackSearch => search || scrollback_search,
+            KeyContext::Running => matches!(
+                action,
+                Action::ScrollUp | Action::ScrollDown | Action::ScrollPageUp | Action::ScrollPageDown | Action::ScrollToBottom
+            ),
+        }
+    }
+}
+
+impl Action {
+    pub fn from_name(name: &str) -> Option<Self> {
+        ACTION_NAMES.iter().find(|(_, n)| *n == name).map(|(action, _)| *action)
+    }
+
+    pub fn name(self) -> &'static str {
+        ACTION_NAMES.iter().find(|(a, _)| *a == self).map_or("", |(_, name)| name)
+    }
+}
+
+impl TryFrom<String> for Action {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, String> {
+        Self::from_name(&name).ok_or_else(|| format!("unknown action `{}`", name))
+    }
+}
+
+// A key with its modifiers. Shift is folded into the character it types,
+// and Ctrl with a letter means the same whatever its case.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyChord {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+impl KeyChord {
+    pub fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
+        let code = match code {
+            KeyCode::Char(c) if modifiers.contains(KeyModifiers::CONTROL) => KeyCode::Char(c.to_ascii_lowercase()),
+            KeyCode::Char(c) if modifiers.contains(KeyModifiers::SHIFT) => KeyCode::Char(c.to_ascii_uppercase()),
+            code => code,
+        };
+        let modifiers = match code {
+            KeyCode::Char(_) | KeyCode::BackTab => modifiers - KeyModifiers::SHIFT,
+            _ => modifiers,
+        };
+        Self { code, modifiers }
+    }
+
+    pub fn from_event(key: &KeyEvent) -> Self {
+        Self::new(key.code, key.modifiers)
+    }
+
+    // `ctrl-x`, `alt-enter`, `shift-tab`, `f5`, `C-a`, `M-f`
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut rest = text;
+        loop {
+            let lower = rest.to_ascii_lowercase();
+            let (modifier, len) = if lower.starts_with("ctrl-") {
+                (KeyModifiers::CONTROL, 5)
+            } else if lower.starts_with("alt-") {
+                (KeyModifiers::ALT, 4)
+            } else if lower.starts_with("shift-") {
+                (KeyModifiers::SHIFT, 6)
+            } else if rest.len() > 2 && (rest.starts_with("C-") || rest.starts_with("M-")) {
+                let modifier = if rest.starts_with('C') { KeyModifiers::CONTROL } else { KeyModifiers::ALT };
+                (modifier, 2)
+            } else {
+                break;
+            };
+            modifiers |= modifier;
+            rest = &rest[len..];
+        }
+
+        let mut chars = rest.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match rest.to_ascii_lowercase().as_str() {
+                "space" => KeyCode::Char(' '),
+                "enter" | "return" => KeyCode::Enter,
+                "tab" if modifiers.contains(KeyModifiers::SHIFT) => KeyCode::BackTab,
+                "tab" => KeyCode::Tab,
+                "backtab" => KeyCode::BackTab,
+                "esc" | "escape" => KeyCode::Esc,
+                "backspace" => KeyCode::Backspace,
+                "delete" | "del" => KeyCode::Delete,
+                "insert" => KeyCode::Insert,
+                "up" => KeyCode::Up,
+                "down" => KeyCode::Down,
+                "left" => KeyCode::Left,
+                "right" => KeyCode::Right,
+                "home" => KeyCode::Home,
+                "end" => KeyCode::End,
+                "pageup" => KeyCode::PageUp,
+                "pagedown" => KeyCode::PageDown,
+                name => match name.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+                    Some(n @ 1..=24) => KeyCode::F(n),
+                    _ => return Err(format!("unknown key `{}`", text)),
+                },
+            },
+        };
+        Ok(Self::new(code, modifiers))
+    }
+
+    // The character this chord types, if it's plain text
+    pub fn typed_char(&self) -> Option<char> {
+        match self.code {
+            KeyCode::Char(c) if !self.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => Some(c),
+            _ => None,
+        }
+    }
+}
+
+// One or more chords separated by spaces, pressed one after the other
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub struct KeySequence(pub Vec<KeyChord>);
+
+impl KeySequence {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let chords = text.split_whitespace().map(KeyChord::parse).collect::<Result<Vec<_>, _>>()?;
+        if chords.is_empty() {
+            return Err("empty key sequence".to_string());
+        }
+        Ok(Self(chords))
+    }
+}
+
+impl TryFrom<String> for KeySequence {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, String> {
+        Self::parse(&text)
+    }
+}
+
+// An action, or `none` to leave the keys unbound
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Binding(pub Option<Action>);
+
+impl TryFrom<String> for Binding {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, String> {
+        if name == "none" {
+            return Ok(Self(None));
+        }
+        Action::try_from(name).map(|action| Self(Some(action)))
+    }
+}
+
+// The `[keybindings]` section: bindings at the prompt, and sub-tables like
+// `[keybindings.menu]` with those of the other contexts
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KeyBindings(HashMap<KeyContext, HashMap<KeySequence, Binding>>);
+
+impl KeyBindings {
+    pub fn iter(&self) -> impl Iterator<Item = (KeyContext, &KeySequence, Binding)> {
+        self.0
+            .iter()
+            .flat_map(|(context, bindings)| bindings.iter().map(move |(keys, binding)| (*context, keys, *binding)))
+    }
+
+    pub fn get(&self, context: KeyContext, keys: &KeySequence) -> Option<Binding> {
+        self.0.get(&context)?.get(keys).copied()
+    }
+}
+
+// A key of `[keybindings]`: keys to bind, or a context's sub-table
+#[derive(Deserialize)]
+#[serde(try_from = "String")]
+enum BindingsKey {
+    Keys(KeySequence),
+    Context(KeyContext),
+}
+
+impl TryFrom<String> for BindingsKey {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, String> {
+        match KeyContext::from_section(&text) {
+            Some(context) => Ok(Self::Context(context)),
+            None => KeySequence::parse(&text).map(Self::Keys),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyBindings {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(BindingsVisitor(None))
+    }
+}
+
+// Reads the whole section with None, or a context's sub-table
+struct BindingsVisitor(Option<KeyContext>);
+
+impl<'de> Visitor<'de> for BindingsVisitor {
+    type Value = KeyBindings;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a table of key bindings")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<KeyBindings, A::Error> {
+        let mut bindings = KeyBindings::default();
+        while let Some(key) = map.next_key_seed(KeySeed(self.0))? {
+            match key {
+                BindingsKey::Context(context) => {
+                    let table = map.next_value_seed(ContextSeed(context))?;
+                    bindings.0.extend(table.0);
+                }
+                BindingsKey::Keys(keys) => {
+                    let context = self.0.unwrap_or(KeyContext::Prompt);
+                    let binding = map.next_value_seed(BindingSeed(context))?;
+                    bindings.0.entry(context).or_default().insert(keys, binding);
+                }
+            }
+        }
+        Ok(bindings)
+    }
+}
+
+// Keys of a context's sub-table are single keys to bind
+struct KeySeed(Option<KeyContext>);
+
+impl<'de> DeserializeSeed<'de> for KeySeed {
+    type Value = BindingsKey;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<BindingsKey, D::Error> {
+        if self.0.is_none() {
+            return BindingsKey::deserialize(deserializer);
+        }
+        let keys = KeySequence::deserialize(deserializer)?;
+        if keys.0.len() > 1 {
+            return Err(de::Error::custom("key sequences only work at the prompt"));
+        }
+        Ok(BindingsKey::Keys(keys))
+    }
+}
+
+struct ContextSeed(KeyContext);
+
+impl<'de> DeserializeSeed<'de> for ContextSeed {
+    type Value = KeyBindings;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<KeyBindings, D::Error> {
+        deserializer.deserialize_map(BindingsVisitor(Some(self.0)))
+    }
+}
+
+// A binding, which has to be to an action of its context
+struct BindingSeed(KeyContext);
+
+impl<'de> DeserializeSeed<'de> for BindingSeed {
+    type Value = Binding;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Binding, D::Error> {
+        let binding = Binding::deserialize(deserializer)?;
+        match binding.0 {
+            Some(action) if !self.0.allows(action) => {
+                Err(de::Error::custom(format!("`{}` doesn't work {}", action.name(), self.0.description())))
+            }
+            _ => Ok(binding),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyLookup {
+    Action(Action),
+    // The start of a longer sequence; wait for the next key
+    Pending,
+    // Keys no binding starts with
+    Unbound(Vec<KeyChord>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    // At the prompt
+    bindings: HashMap<Vec<KeyChord>, Action>,
+    // Keys of a sequence typed so far
+    pending: Vec<KeyChord>,
+    // Everywhere else, where sequences aren't used
+    context_bindings: HashMap<(KeyContext, KeyChord), Action>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut keymap = Self {
+            bindings: HashMap::new(),
+            pending: Vec::new(),
+            context_bindings: HashMap::new(),
+        };
+        for (keys, action) in DEFAULT_BINDINGS {
+            if let Ok(keys) = KeySequence::parse(keys) {
+                keymap.bind(KeyContext::Prompt, &keys, Some(*action));
+            }
+        }
+        for (context, keys, action) in CONTEXT_BINDINGS {
+            if let Ok(keys) = KeySequence::parse(keys) {
+                keymap.bind(*context, &keys, Some(*action));
+            }
+        }
+        keymap
+    }
+}
+
+impl Keymap {
+    // Bind `keys` to `action`, or unbind them with None. Outside the prompt
+    // only single keys are bound.
+    pub fn bind(&mut self, context: KeyContext, keys: &KeySequence, action: Option<Action>) {
+        match (context, keys.0.as_slice()) {
+            (KeyContext::Prompt, _) => match action {
+                Some(action) => self.bindings.insert(keys.0.clone(), action),
+                None => self.bindings.remove(&keys.0),
+            },
+            (_, [chord]) => match action {
+                Some(action) => self.context_bindings.insert((context, *chord), action),
+                None => self.context_bindings.remove(&(context, *chord)),
+            },
+            _ => None,
+        };
+    }
+
+    // The action of a key anywhere but at the prompt
+    pub fn lookup(&self, context: KeyContext, key: &KeyEvent) -> Option<Action> {
+        self.context_bindings.get(&(context, KeyChord::from_event(key))).copied()
+    }
+
+    // The action of a single key in the default bindings
+    pub fn default_action(key: &KeyEvent) -> Option<Action> {
+        // Parsed the first time they're needed
+        static DEFAULTS: OnceLock<HashMap<KeyChord, Action>> = OnceLock::new();
+        let defaults = DEFAULTS.get_or_init(|| {
+            DEFAULT_BINDINGS
+                .iter()
+                .filter_map(|(keys, action)| match KeySequence::parse(keys).ok()?.0.as_slice() {
+                    [chord] => Some((*chord, *action)),
+                    _ => None,
+                })
+                .collect()
+        });
+        defaults.get(&KeyChord::from_event(key)).copied()
+    }
+
+    // Look up a key pressed after the ones still pending. A sequence that
+    // is also the start of a longer one waits for the longer one.
+    pub fn feed(&mut self, key: &KeyEvent) -> KeyLookup {
+        self.pending.push(KeyChord::from_event(key));
+        let longer = self
+            .bindings
+            .keys()
+            .any(|keys| keys.len() > self.pending.len() && keys.starts_with(&self.pending));
+        if longer {
+            return KeyLookup::Pending;
+        }
+        let keys = std::mem::take(&mut self.pending);
+        match self.bindings.get(&keys) {
+            Some(action) => KeyLookup::Action(*action),
+            None => KeyLookup::Unbound(keys),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    #[test]
+    fn parses_chords() {
+        let chord = |text| KeyChord::parse(text).unwrap();
+        assert_eq!(chord("ctrl-x"), KeyChord::new(KeyCode::Char('x'), KeyModifiers::CONTROL));
+        assert_eq!(chord("C-X"), chord("ctrl-x"));
+        assert_eq!(chord("M-f"), KeyChord::new(KeyCode::Char('f'), KeyModifiers::ALT));
+        assert_eq!(chord("alt-enter"), KeyChord::new(KeyCode::Enter, KeyModifiers::ALT));
+        assert_eq!(chord("shift-tab"), KeyChord::new(KeyCode::BackTab, KeyModifiers::NONE));
+        assert_eq!(chord("ctrl--"), KeyChord::new(KeyCode::Char('-'), KeyModifiers::CONTROL));
+        assert_eq!(chord("f5"), KeyChord::new(KeyCode::F(5), KeyModifiers::NONE));
+        assert_eq!(chord("A"), KeyChord::from_event(&press(KeyCode::Char('A'), KeyModifiers::SHIFT)));
+        assert_eq!(chord("shift-a"), chord("A"));
+        assert!(KeyChord::parse("ctrl-nope").is_err());
+        assert!(KeySequence::parse(" ").is_err());
+        assert_eq!(Action::from_name(Action::ClearScreen.name()), Some(Action::ClearScreen));
+    }
+
+    #[test]
+    fn follows_key_sequences() {
+        let mut keymap = Keymap::default();
+        let ctrl = |c| press(KeyCode::Char(c), KeyModifiers::CONTROL);
+        assert_eq!(keymap.feed(&ctrl('a')), KeyLookup::Action(Action::BeginningOfLine));
+        assert_eq!(keymap.feed(&ctrl('x')), KeyLookup::Pending);
+        assert_eq!(keymap.feed(&ctrl('u')), KeyLookup::Action(Action::Undo));
+        assert_eq!(keymap.feed(&ctrl('x')), KeyLookup::Pending);
+        assert_eq!(
+            keymap.feed(&ctrl('q')),
+            KeyLookup::Unbound(vec![KeyChord::parse("ctrl-x").unwrap(), KeyChord::parse("ctrl-q").unwrap()])
+        );
+        assert_eq!(
+            keymap.feed(&press(KeyCode::Char('q'), KeyModifiers::NONE)),
+            KeyLookup::Unbound(vec![KeyChord::parse("q").unwrap()])
+        );
+    }
+
+    #[test]
+    fn bindings_can_be_changed() {
+        let mut keymap = Keymap::default();
+        let keys = |text| KeySequence::parse(text).unwrap();
+        keymap.bind(KeyContext::Prompt, &keys("ctrl-a"), Some(Action::ClearScreen));
+        keymap.bind(KeyContext::Prompt, &keys("esc"), None);
+        keymap.bind(KeyContext::Prompt, &keys("ctrl-g g"), Some(Action::ScrollToBottom));
+        assert_eq!(keymap.feed(&press(KeyCode::Char('a'), KeyModifiers::CONTROL)), KeyLookup::Action(Action::ClearScreen));
+        assert!(matches!(keymap.feed(&press(KeyCode::Esc, KeyModifiers::NONE)), KeyLookup::Unbound(_)));
+        assert_eq!(keymap.feed(&press(KeyCode::Char('g'), KeyModifiers::CONTROL)), KeyLookup::Pending);
+        assert_eq!(keymap.feed(&press(KeyCode::Char('g'), KeyModifiers::NONE)), KeyLookup::Action(Action::ScrollToBottom));
+        assert_eq!(Binding::try_from("none".to_string()), Ok(Binding(None)));
+        assert!(Binding::try_from("fly".to_string()).is_err());
+    }
+
+    #[test]
+    fn contexts_have_their_own_bindings() {
+        let mut keymap = Keymap::default();
+        let enter = press(KeyCode::Enter, KeyModifiers::NONE);
+        assert_eq!(keymap.lookup(KeyContext::Menu, &enter), Some(Action::MenuAccept));
+        assert_eq!(keymap.lookup(KeyContext::ScrollbackSearch, &enter), Some(Action::SearchAccept));
+        assert_eq!(keymap.feed(&enter), KeyLookup::Action(Action::AcceptLine));
+
+        let page_up = press(KeyCode::PageUp, KeyModifiers::SHIFT);
+        assert_eq!(keymap.lookup(KeyContext::Running, &page_up), Some(Action::ScrollPageUp));
+        assert_eq!(keymap.lookup(KeyContext::Running, &press(KeyCode::PageUp, KeyModifiers::NONE)), None);
+
+        let ctrl_j = press(KeyCode::Char('j'), KeyModifiers::CONTROL);
+        keymap.bind(KeyContext::Menu, &KeySequence::parse("ctrl-j").unwrap(), Some(Action::MenuNext));
+        keymap.bind(KeyContext::Menu, &KeySequence::parse("tab").unwrap(), None);
+        assert_eq!(keymap.lookup(KeyContext::Menu, &ctrl_j), Some(Action::MenuNext));
+        assert_eq!(keymap.lookup(KeyContext::Menu, &press(KeyCode::Tab, KeyModifiers::NONE)), None);
+        assert_eq!(keymap.feed(&press(KeyCode::Tab, KeyModifiers::NONE)), KeyLookup::Action(Action::Complete));
+
+        assert!(KeyContext::ScrollbackSearch.allows(Action::ToggleRegex));
+        assert!(!KeyContext::HistorySearch.allows(Action::ToggleRegex));
+        assert!(!KeyContext::Prompt.allows(Action::MenuNext));
+        assert!(!KeyContext::Running.allows(Action::AcceptLine));
+    }
+}
@@ -0,0 +1,98 @@
+// Translate crossterm key events back into the byte sequences a program
+// running on a pseudo-terminal expects to read from its tty
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+// `application_cursor` is the DECCKM mode of the child, which switches the
+// unmodified arrow keys to their SS3 (`ESC O A`) form
+pub fn encode_key(key: &KeyEvent, application_cursor: bool) -> Option<Vec<u8>> {
+    let alt = key.modifiers.contains(KeyModifiers::ALT);
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+
+    let mut bytes = match key.code {
+        KeyCode::Char(c) if ctrl => vec![control_byte(c)?],
+        KeyCode::Char(c) => c.to_string().into_bytes(),
+        KeyCode::Enter => vec![b'\r'],
+        KeyCode::Tab => vec![b'\t'],
+        KeyCode::BackTab => b"\x1b[Z".to_vec(),
+        KeyCode::Backspace => vec![0x7f],
+        KeyCode::Esc => vec![0x1b],
+        KeyCode::Up => return Some(cursor_key(b'A', key.modifiers, application_cursor)),
+        KeyCode::Down => return Some(cursor_key(b'B', key.modifiers, application_cursor)),
+        KeyCode::Right => return Some(cursor_key(b'C', key.modifiers, application_cursor)),
+        KeyCode::Left => return Some(cursor_key(b'D', key.modifiers, application_cursor)),
+        KeyCode::Home => return Some(cursor_key(b'H', key.modifiers, application_cursor)),
+        KeyCode::End => return Some(cursor_key(b'F', key.modifiers, application_cursor)),
+        KeyCode::Insert => tilde_key(2, key.modifiers),
+        KeyCode::Delete => tilde_key(3, key.modifiers),
+        KeyCode::PageUp => tilde_key(5, key.modifiers),
+        KeyCode::PageDown => tilde_key(6, key.modifiers),
+        KeyCode::F(n @ 1..=4) => vec![0x1b, b'O', b'P' + (n - 1)],
+        KeyCode::F(n) => {
+            let code = match n {
+                5 => 15,
+                6 => 17,
+                7 => 18,
+                8 => 19,
+                9 => 20,
+                10 => 21,
+                11 => 23,
+                12 => 24,
+                _ => return None,
+            };
+            tilde_key(code, key.modifiers)
+        }
+        _ => return None,
+    };
+
+    // Alt sends an ESC prefix, like xterm's metaSendsEscape
+    if alt && matches!(key.code, KeyCode::Char(_) | KeyCode::Backspace | KeyCode::Enter) {
+        bytes.insert(0, 0x1b);
+    }
+
+    Some(bytes)
+}
+
+// Ctrl+<key> maps onto the C0 control range
+fn control_byte(c: char) -> Option<u8> {
+    match c.to_ascii_lowercase() {
+        c @ 'a'..='z' => Some(c as u8 - b'a' + 1),
+        '@' | ' ' | '2' => Some(0x00),
+        '[' | '3' => Some(0x1b),
+        '\\' | '4' => Some(0x1c),
+        ']' | '5' => Some(0x1d),
+        '^' | '6' => Some(0x1e),
+        '_' | '/' | '7' => Some(0x1f),
+        '8' | '?' => Some(0x7f),
+        _ => None,
+    }
+}
+
+// xterm encodes modifiers as 1 + shift(1) + alt(2) + ctrl(4)
+fn modifier_param(modifiers: KeyModifiers) -> u8 {
+    let mut param = 1;
+    if modifiers.contains(KeyModifiers::SHIFT) {
+        param += 1;
+    }
+    if modifiers.contains(KeyModifiers::ALT) {
+        param += 2;
+    }
+    if modifiers.contains(KeyModifiers::CONTROL) {
+        param += 4;
+    }
+    param
+}
+
+fn cursor_key(final_byte: u8, modifiers: KeyModifiers, application_cursor: bool) -> Vec<u8> {
+    match modifier_param(modifiers) {
+        1 if application_cursor => vec![0x1b, b'O', final_byte],
+        1 => vec![0x1b, b'[', final_byte],
+        m => format!("\x1b[1;{}{}", m, final_byte as char).into_bytes(),
+    }
+}
+
+fn tilde_key(code: u8, modifiers: KeyModifiers) -> Vec<u8> {
+    match modifier_param(modifiers) {
+        1 => format!("\x1b[{}~", code).into_bytes(),
+        m => format!("\x1b[{};{}~", code, m).into_bytes(),
+    }
+}
@@ -0,0 +1,88 @@
+// The menu of completion candidates shown above the prompt
+use crate::completion::completion::{Candidate, Completion};
+
+// Most rows the menu takes up, as a share of the screen
+const MAX_MENU_SHARE: usize = 3;
+
+pub struct CompletionMenu {
+    pub completion: Completion,
+    // Candidate currently filled into the input line
+    pub selected: Option<usize>,
+    // Input line and cursor from before the menu changed anything
+    pub original: (String, usize),
+}
+
+// Where each candidate goes: `rows` of candidate indices, each column
+// `column_width` characters wide
+pub struct MenuLayout {
+    pub rows: Vec<Vec<usize>>,
+    pub column_width: usize,
+    // Candidates are listed one per row with their descriptions
+    pub described: bool,
+}
+
+impl CompletionMenu {
+    pub fn new(completion: Completion, text: &str, cursor: usize) -> Self {
+        Self {
+            completion,
+            selected: None,
+            original: (text.to_string(), cursor),
+        }
+    }
+
+    pub fn select_next(&mut self) -> &Candidate {
+        let count = self.completion.candidates.len();
+        let next = self.selected.map_or(0, |i| (i + 1) % count);
+        self.selected = Some(next);
+        &self.completion.candidates[next]
+    }
+
+    pub fn select_previous(&mut self) -> &Candidate {
+        let count = self.completion.candidates.len();
+        let previous = self.selected.map_or(count - 1, |i| (i + count - 1) % count);
+        self.selected = Some(previous);
+        &self.completion.candidates[previous]
+    }
+
+    // Lay the candidates out in columns across `width`, or one per row
+    // when they have descriptions
+    pub fn layout(&self, width: usize) -> MenuLayout {
+        let candidates = &self.completion.candidates;
+        let longest = candidates.iter().map(|c| c.display.chars().count()).max().unwrap_or(0);
+
+        if candidates.iter().any(|c| c.description.is_some()) {
+            return MenuLayout {
+                rows: (0..candidates.len()).map(|i| vec![i]).collect(),
+                column_width: (longest + 2).min(width / 2),
+                described: true,
+            };
+        }
+
+        let column_width = (longest + 2).min(width.max(1));
+        let columns = (width / column_width).max(1);
+        MenuLayout {
+            rows: (0..candidates.len())
+                .collect::<Vec<_>>()
+                .chunks(columns)
+                .map(|row| row.to_vec())
+                .collect(),
+            column_width,
+            described: false,
+        }
+    }
+
+    // The rows to show on a screen `height` rows tall: all of them if they
+    // fit, otherwise a window that keeps the selected candidate in view
+    pub fn visible_rows(&self, layout: &MenuLayout, height: usize) -> std::ops::Range<usize> {
+        let max_rows = (height / MAX_MENU_SHARE).max(1);
+        if layout.rows.len() <= max_rows {
+            return 0..layout.rows.len();
+        }
+        let selected_row = self
+            .selected
+            .and_then(|selected| layout.rows.iter().position(|row| row.contains(&selected)))
+            .unwrap_or(0);
+        let first = selected_row.saturating_sub(max_rows - 1);
+        first..first + max_rows
+    }
+}
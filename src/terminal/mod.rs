@@ -1 +1,13 @@
-pub mod terminal;
\ No newline at end of file
+#[allow(clippy::module_inception)]
+pub mod terminal;
+pub mod keys;
+pub mod render;
+pub mod editor;
+pub mod history_search;
+pub mod scrollback_search;
+pub mod keymap;
+pub mod vi;
+pub mod menu;
+pub mod highlight;
+pub mod suggest;
+pub mod multiline;
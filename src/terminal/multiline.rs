@@ -0,0 +1,226 @@
+// Deciding whether the input is a finished command or needs more lines,
+// the way bash decides to show its PS2 prompt
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Block {
+    If,
+    // for, while, until and select, closed by `done`
+    Loop,
+    Case,
+    Brace,
+    Paren,
+}
+
+// An unclosed quote, a trailing backslash or pipe, an open
+// if/for/while/case/{/( or a here-document without its end line
+pub fn is_incomplete(text: &str) -> bool {
+    let chars: Vec<char> = text.chars().collect();
+    let mut blocks: Vec<Block> = Vec::new();
+    let mut heredocs: Vec<(String, bool)> = Vec::new();
+    let mut command_position = true;
+    // After `|`, `&&` or `||`, which need a command to follow
+    let mut pending_operator = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\n' => {
+                i += 1;
+                // Here-document bodies follow the line that started them
+                for (delimiter, strip_tabs) in heredocs.drain(..) {
+                    loop {
+                        if i >= chars.len() {
+                            return true;
+                        }
+                        let end = chars[i..].iter().position(|&c| c == '\n').map_or(chars.len(), |p| i + p);
+                        let line: String = chars[i..end].iter().collect();
+                        let line = if strip_tabs { line.trim_start_matches('\t') } else { &line };
+                        let found = line == delimiter;
+                        i = end + 1;
+                        if found {
+                            break;
+                        }
+                    }
+                }
+                command_position = true;
+            }
+            ' ' | '\t' => i += 1,
+            '#' => {
+                i = chars[i..].iter().position(|&c| c == '\n').map_or(chars.len(), |p| i + p);
+            }
+            '|' | '&' | ';' => {
+                let doubled = chars.get(i + 1) == Some(&c);
+                if c == '&' && chars.get(i + 1) == Some(&'>') {
+                    i += 2;
+                    continue;
+                }
+                pending_operator = c == '|' || (c == '&' && doubled);
+                command_position = true;
+                i += if doubled { 2 } else { 1 };
+            }
+            '(' => {
+                // `(` before a case pattern is optional syntax
+                if blocks.last() != Some(&Block::Case) {
+                    blocks.push(Block::Paren);
+                }
+                command_position = true;
+                i += 1;
+            }
+            ')' => {
+                match blocks.last() {
+                    Some(Block::Paren) => {
+                        blocks.pop();
+                    }
+                    // The end of a case pattern
+                    Some(Block::Case) => command_position = true,
+                    _ => {}
+                }
+                i += 1;
+            }
+            '<' | '>' => {
+                if c == '<' && chars.get(i + 1) == Some(&'<') && chars.get(i + 2) != Some(&'<') {
+                    i += 2;
+                    let strip_tabs = chars.get(i) == Some(&'-');
+                    if strip_tabs {
+                        i += 1;
+                    }
+                    while chars.get(i).is_some_and(|c| *c == ' ' || *c == '\t') {
+                        i += 1;
+                    }
+                    let start = i;
+                    while chars.get(i).is_some_and(|c| !c.is_whitespace() && !matches!(c, ';' | '|' | '&')) {
+                        i += 1;
+                    }
+                    let delimiter: String = chars[start..i].iter().filter(|c| !matches!(c, '\'' | '"' | '\\')).collect();
+                    if !delimiter.is_empty() {
+                        heredocs.push((delimiter, strip_tabs));
+                    }
+                } else {
+                    while chars.get(i).is_some_and(|c| matches!(c, '<' | '>' | '&')) {
+                        i += 1;
+                    }
+                }
+                pending_operator = false;
+            }
+            _ => {
+                // A word; quotes and escapes may run over lines
+                let start = i;
+                let mut quoted = false;
+                while i < chars.len() {
+                    match chars[i] {
+                        '\\' => {
+                            if i + 1 >= chars.len() {
+                                return true;
+                            }
+                            i += 2;
+                        }
+                        q @ ('\'' | '"' | '`') => {
+                            quoted = true;
+                            i += 1;
+                            loop {
+                                match chars.get(i) {
+                                    None => return true,
+                                    Some(&c) if c == q => break,
+                                    Some('\\') if q != '\'' => i += 2,
+                                    Some(_) => i += 1,
+                                }
+                            }
+                            i += 1;
+                        }
+                        c if c.is_whitespace() || matches!(c, '|' | '&' | ';' | '(' | ')' | '<' | '>') => break,
+                        _ => i += 1,
+                    }
+                }
+                pending_operator = false;
+                let word: String = chars[start..i.min(chars.len())].iter().collect();
+                if quoted {
+                    command_position = false;
+                    continue;
+                }
+
+                // `esac` ends a case from pattern position too
+                if word == "esac" && blocks.last() == Some(&Block::Case) {
+                    blocks.pop();
+                    command_position = false;
+                    continue;
+                }
+                if !command_position {
+                    continue;
+                }
+                match word.as_str() {
+                    "if" => blocks.push(Block::If),
+                    "for" | "select" => {
+                        blocks.push(Block::Loop);
+                        command_position = false;
+                    }
+                    "while" | "until" => blocks.push(Block::Loop),
+                    "case" => {
+                        blocks.push(Block::Case);
+                        command_position = false;
+                    }
+                    "{" => blocks.push(Block::Brace),
+                    "then" | "else" | "elif" | "do" | "!" | "time" => {}
+                    "fi" | "done" | "}" => {
+                        let expected = match word.as_str() {
+                            "fi" => Block::If,
+                            "done" => Block::Loop,
+                            _ => Block::Brace,
+                        };
+                        if blocks.last() == Some(&expected) {
+                            blocks.pop();
+                        }
+                        command_position = false;
+                    }
+                    _ => command_position = false,
+                }
+            }
+        }
+    }
+
+    pending_operator || !blocks.is_empty() || !heredocs.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finished_commands() {
+        for text in [
+            "ls -l",
+            "echo 'a b' \"c\\\"d\"",
+            "cat a | grep b && echo ok || echo no; sleep 1 &",
+            "if true; then echo y; fi",
+            "for f in *; do echo \"$f\"; done",
+            "case $x in a) echo a;; (b|c) echo bc;; esac",
+            "f() { echo hi; }",
+            "echo $(date) `pwd` 2>&1 >out",
+            "cat <<EOF\nhello\nEOF",
+            "echo if for case # while",
+            "echo \"multi\nline\"",
+        ] {
+            assert!(!is_incomplete(text), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn incomplete_commands() {
+        for text in [
+            "echo 'abc",
+            "echo \"abc",
+            "echo abc \\",
+            "ls |",
+            "true &&",
+            "if true; then",
+            "for f in *; do\necho $f",
+            "while true\ndo",
+            "case $x in\na) echo a;;",
+            "f() {",
+            "echo $(date",
+            "cat <<-EOF\n\thello",
+            "cat <<EOF",
+        ] {
+            assert!(is_incomplete(text), "{:?}", text);
+        }
+    }
+}
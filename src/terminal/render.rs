@@ -0,0 +1,104 @@
+// Drawing of screen cells onto the real terminal
+use crossterm::{
+    cursor::MoveTo,
+    queue,
+    style::{Attribute, Color, Print, SetAttribute, SetBackgroundColor, SetForegroundColor},
+    terminal::{Clear, ClearType},
+};
+use std::io::{self, Write};
+
+use crate::screen::cell::{Attributes, CellColor, Row};
+use crate::theme::theme::Theme;
+
+// Colors go through the theme, which also brings them down to what the
+// terminal can show
+pub fn to_color(color: CellColor, theme: &Theme) -> Color {
+    match theme.color(color) {
+        CellColor::Default => Color::Reset,
+        CellColor::Indexed(n) => Color::AnsiValue(n),
+        CellColor::Rgb(r, g, b) => Color::Rgb { r, g, b },
+    }
+}
+
+fn set_attributes(out: &mut impl Write, attrs: &Attributes, theme: &Theme) -> io::Result<()> {
+    queue!(
+        out,
+        SetAttribute(Attribute::Reset),
+        SetForegroundColor(to_color(attrs.fg, theme)),
+        SetBackgroundColor(to_color(attrs.bg, theme))
+    )?;
+    if attrs.bold {
+        queue!(out, SetAttribute(Attribute::Bold))?;
+    }
+    if attrs.dim {
+        queue!(out, SetAttribute(Attribute::Dim))?;
+    }
+    if attrs.italic {
+        queue!(out, SetAttribute(Attribute::Italic))?;
+    }
+    if attrs.underline {
+        queue!(out, SetAttribute(Attribute::Underlined))?;
+    }
+    if attrs.inverse {
+        queue!(out, SetAttribute(Attribute::Reverse))?;
+    }
+    Ok(())
+}
+
+// Draw one row at line `y`, clipped to `width` columns, and clear the rest
+pub fn draw_row(out: &mut impl Write, y: u16, row: Option<&Row>, width: usize, theme: &Theme) -> io::Result<()> {
+    queue!(out, MoveTo(0, y))?;
+
+    let mut drawn = 0;
+    if let Some(row) = row {
+        // Emit runs of cells sharing the same attributes in one go
+        let mut current = Attributes::default();
+        let mut run = String::new();
+        let cells = &row.cells[..row.cells.len().min(width)];
+        for (i, cell) in cells.iter().enumerate() {
+            if cell.attrs != current {
+                queue!(out, Print(&run))?;
+                run.clear();
+                set_attributes(out, &cell.attrs, theme)?;
+                current = cell.attrs;
+            }
+            // The real terminal moves past both columns of a wide character;
+            // half of one that was cut in two shows as a blank
+            if cell.is_continuation() {
+                if i == 0 || !cells[i - 1].is_wide() {
+                    run.push(' ');
+                }
+            } else if cell.is_wide() && !cells.get(i + 1).is_some_and(|next| next.is_continuation()) {
+                run.push(' ');
+            } else {
+                cell.push_text(&mut run);
+            }
+            drawn += 1;
+        }
+        queue!(out, Print(&run))?;
+    }
+
+    queue!(out, SetAttribute(Attribute::Reset))?;
+    // Clearing from the last column would erase the character drawn there
+    if drawn < width {
+        queue!(out, Clear(ClearType::UntilNewLine))?;
+    }
+    Ok(())
+}
+
+// Print characters with their own attributes at the cursor position
+pub fn print_styled(out: &mut impl Write, text: impl Iterator<Item = (char, Attributes)>, theme: &Theme) -> io::Result<()> {
+    let mut current = Attributes::default();
+    let mut run = String::new();
+    for (c, attrs) in text {
+        if attrs != current {
+            queue!(out, Print(&run))?;
+            run.clear();
+            set_attributes(out, &attrs, theme)?;
+            current = attrs;
+        }
+        run.push(c);
+    }
+    queue!(out, Print(&run), SetAttribute(Attribute::Reset))?;
+    Ok(())
+}
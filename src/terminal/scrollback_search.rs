@@ -0,0 +1,328 @@
+// Searching the scrollback and the screen for text or a regex. Every match
+// is kept so all of them can be highlighted; one is current, and n/N step
+// to older and newer ones.
+use regex::RegexBuilder;
+
+use crate::screen::cell::char_width;
+use crate::screen::screen::Screen;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchMatch {
+    // Index into the scrollback and screen seen as one list of lines
+    pub line: usize,
+    // Columns of the first cell matched and the one after the last
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CaseMode {
+    // Case only matters once the query has a capital in it
+    #[default]
+    Smart,
+    Sensitive,
+    Insensitive,
+}
+
+impl CaseMode {
+    // Alt+C goes around smart, sensitive and insensitive
+    fn next(self) -> Self {
+        match self {
+            CaseMode::Smart => CaseMode::Sensitive,
+            CaseMode::Sensitive => CaseMode::Insensitive,
+            CaseMode::Insensitive => CaseMode::Smart,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CaseMode::Smart => "smart case",
+            CaseMode::Sensitive => "match case",
+            CaseMode::Insensitive => "ignore case",
+        }
+    }
+}
+
+pub struct ScrollbackSearch {
+    query: String,
+    regex: bool,
+    case: CaseMode,
+    // Typing the query, as opposed to stepping through the matches
+    editing: bool,
+    // How far the view was scrolled back, to go back to when cancelled
+    original_offset: usize,
+    // In the order of the lines they're on
+    matches: Vec<SearchMatch>,
+    current: Option<usize>,
+    // Why the query isn't a usable pattern
+    error: Option<String>,
+}
+
+impl ScrollbackSearch {
+    pub fn new(original_offset: usize) -> Self {
+        Self {
+            query: String::new(),
+            regex: false,
+            case: CaseMode::default(),
+            editing: true,
+            original_offset,
+            matches: Vec::new(),
+            current: None,
+            error: None,
+        }
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn is_regex(&self) -> bool {
+        self.regex
+    }
+
+    pub fn case(&self) -> CaseMode {
+        self.case
+    }
+
+    pub fn editing(&self) -> bool {
+        self.editing
+    }
+
+    pub fn original_offset(&self) -> usize {
+        self.original_offset
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    pub fn match_count(&self) -> usize {
+        self.matches.len()
+    }
+
+    // Which match is current, counting from 0, and the match itself
+    pub fn current(&self) -> Option<(usize, &SearchMatch)> {
+        self.current.map(|index| (index, &self.matches[index]))
+    }
+
+    // The matches on one line
+    pub fn matches_on(&self, line: usize) -> &[SearchMatch] {
+        let start = self.matches.partition_point(|found| found.line < line);
+        let end = self.matches.partition_point(|found| found.line <= line);
+        &self.matches[start..end]
+    }
+
+    // The query changes and the search starts over from `from`, the
+    // bottom line of the view
+    pub fn push(&mut self, c: char, screen: &Screen, from: usize) {
+        self.query.push(c);
+        self.update(screen, from);
+    }
+
+    pub fn pop(&mut self, screen: &Screen, from: usize) {
+        self.query.pop();
+        self.update(screen, from);
+    }
+
+    pub fn toggle_regex(&mut self, screen: &Screen, from: usize) {
+        self.regex = !self.regex;
+        self.update(screen, from);
+    }
+
+    pub fn toggle_case(&mut self, screen: &Screen, from: usize) {
+        self.case = self.case.next();
+        self.update(screen, from);
+    }
+
+    // Enter stops editing and n/N take over
+    pub fn finish_editing(&mut self) {
+        self.editing = false;
+    }
+
+    // `/` starts a new query
+    pub fn edit(&mut self) {
+        self.editing = true;
+        self.query.clear();
+        self.matches.clear();
+        self.current = None;
+        self.error = None;
+    }
+
+    // n: the match before the current one, wrapping around to the newest
+    pub fn older(&mut self) {
+        if let Some(current) = self.current {
+            self.current = Some(current.checked_sub(1).unwrap_or(self.matches.len() - 1));
+        }
+    }
+
+    // N: the one after, wrapping around to the oldest
+    pub fn newer(&mut self) {
+        if let Some(current) = self.current {
+            self.current = Some((current + 1) % self.matches.len());
+        }
+    }
+
+    fn ignores_case(&self) -> bool {
+        match self.case {
+            CaseMode::Sensitive => false,
+            CaseMode::Insensitive => true,
+            CaseMode::Smart => !has_capitals(&self.query, self.regex),
+        }
+    }
+
+    fn update(&mut self, screen: &Screen, from: usize) {
+        self.matches.clear();
+        self.current = None;
+        self.error = None;
+        if self.query.is_empty() {
+            return;
+        }
+
+        let pattern = if self.regex { self.query.clone() } else { regex::escape(&self.query) };
+        let regex = match RegexBuilder::new(&pattern).case_insensitive(self.ignores_case()).build() {
+            Ok(regex) => regex,
+            Err(_) => {
+                self.error = Some("invalid regex".to_string());
+                return;
+            }
+        };
+        screen.for_each_text(|line, text| {
+            // Wide characters take up two cells and combining marks none
+            let columns = |text: &str| text.chars().map(char_width).sum::<usize>();
+            for found in regex.find_iter(text).filter(|found| !found.is_empty()) {
+                let start = columns(&text[..found.start()]);
+                let end = start + columns(found.as_str());
+                self.matches.push(SearchMatch { line, start, end });
+            }
+        });
+
+        // The nearest match at or above the bottom of the view, or else the
+        // first one below it
+        if !self.matches.is_empty() {
+            let above = self.matches.partition_point(|found| found.line <= from);
+            self.current = Some(above.saturating_sub(1));
+        }
+    }
+}
+
+// Capitals that make smart case match case; in a regex, escapes like `\S`
+// and `\W` don't count
+fn has_capitals(query: &str, regex: bool) -> bool {
+    let mut chars = query.chars();
+    while let Some(c) = chars.next() {
+        if regex && c == '\\' {
+            chars.next();
+        } else if c.is_uppercase() {
+            return true;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::screen::scrollback::{Scrollback, ScrollbackStorage};
+
+    fn screen(lines: &[&str]) -> Screen {
+        let mut screen = Screen::new(20, 3, 100);
+        for line in lines {
+            screen.feed(line.as_bytes());
+            screen.feed(b"\r\n");
+        }
+        screen
+    }
+
+    fn search(screen: &Screen, query: &str) -> ScrollbackSearch {
+        let mut search = ScrollbackSearch::new(0);
+        for c in query.chars() {
+            search.push(c, screen, screen.total_lines() - 1);
+        }
+        search
+    }
+
+    fn found(search: &ScrollbackSearch) -> Vec<(usize, usize, usize)> {
+        search.matches.iter().map(|found| (found.line, found.start, found.end)).collect()
+    }
+
+    #[test]
+    fn finds_every_match() {
+        let screen = screen(&["error: one", "ok", "Error: two error", "done"]);
+        let search = search(&screen, "error");
+        assert_eq!(found(&search), vec![(0, 0, 5), (2, 0, 5), (2, 11, 16)]);
+        // The newest match is current to start with
+        assert_eq!(search.current().map(|(index, _)| index), Some(2));
+        assert_eq!(search.matches_on(1), &[]);
+    }
+
+    #[test]
+    fn smart_case_and_toggling() {
+        let screen = screen(&["error: one", "Error: two error"]);
+        let mut search = search(&screen, "Error");
+        assert_eq!(search.match_count(), 1);
+        let from = screen.total_lines() - 1;
+        search.toggle_case(&screen, from);
+        assert_eq!(search.case(), CaseMode::Sensitive);
+        assert_eq!(search.match_count(), 1);
+        search.toggle_case(&screen, from);
+        assert_eq!(search.match_count(), 3);
+        search.toggle_case(&screen, from);
+        assert_eq!(search.case(), CaseMode::Smart);
+        assert!(!has_capitals(r"\S+\d", true));
+        assert!(has_capitals(r"\S+\d", false));
+    }
+
+    #[test]
+    fn regex_patterns() {
+        let screen = screen(&["took 12ms", "took 7ms", "a.b"]);
+        let mut search = search(&screen, r"\d+ms");
+        assert_eq!(search.match_count(), 0);
+        search.toggle_regex(&screen, screen.total_lines() - 1);
+        assert_eq!(found(&search), vec![(0, 5, 9), (1, 5, 8)]);
+
+        let plain = self::search(&screen, "a.b");
+        assert_eq!(found(&plain), vec![(2, 0, 3)]);
+        let mut broken = self::search(&screen, "(");
+        broken.toggle_regex(&screen, 0);
+        assert_eq!(broken.error(), Some("invalid regex"));
+    }
+
+    #[test]
+    fn columns_count_cells() {
+        let screen = screen(&["中文 e\u{301}rror"]);
+        let search = search(&screen, "rror");
+        assert_eq!(found(&search), vec![(0, 6, 10)]);
+        assert_eq!(screen.line(0).unwrap().cells[6].c, 'r');
+    }
+
+    #[test]
+    fn searches_a_scrollback_that_dropped_lines() {
+        for storage in [ScrollbackStorage::Memory, ScrollbackStorage::Compressed, ScrollbackStorage::Disk] {
+            for (max_lines, max_bytes) in [(300, None), (1_000_000, Some(100_000))] {
+                let mut screen = Screen::with_scrollback(20, 3, Scrollback::new(max_lines, max_bytes, storage));
+                for n in 0..2000 {
+                    screen.feed(format!("line {}\r\n", n).as_bytes());
+                }
+                let search = search(&screen, "line 1999");
+                assert_eq!(search.match_count(), 1, "{:?}", storage);
+                let (_, found) = search.current().unwrap();
+                assert_eq!(screen.line(found.line).unwrap().text(), "line 1999");
+            }
+        }
+    }
+
+    #[test]
+    fn n_and_shift_n_wrap_around() {
+        let screen = screen(&["x", "x", "x"]);
+        let mut search = search(&screen, "x");
+        let current = |search: &ScrollbackSearch| search.current().map(|(index, _)| index);
+        assert_eq!(current(&search), Some(2));
+        search.older();
+        assert_eq!(current(&search), Some(1));
+        search.newer();
+        search.newer();
+        assert_eq!(current(&search), Some(0));
+        search.older();
+        assert_eq!(current(&search), Some(2));
+    }
+}
@@ -0,0 +1,104 @@
+// Fish-style autosuggestions: the rest of the most recent history entry
+// that starts with what's been typed, shown dimmed after the cursor
+use std::path::{Path, PathBuf};
+
+use crate::history::history::HistoryEntry;
+
+// The text to suggest after `input`. Entries run in `cwd` win over more
+// recent ones run elsewhere, and entries naming paths that no longer
+// exist are passed over.
+pub fn suggest(input: &str, history: &[HistoryEntry], cwd: &Path) -> Option<String> {
+    if input.trim().is_empty() {
+        return None;
+    }
+    let matching = || {
+        history
+            .iter()
+            .rev()
+            .filter(|entry| entry.command.len() > input.len() && entry.command.starts_with(input))
+            .filter(|entry| !entry.command.contains('\n'))
+    };
+    matching()
+        .filter(|entry| entry.cwd == cwd)
+        .chain(matching())
+        .find(|entry| paths_exist(&entry.command, cwd))
+        .map(|entry| entry.command[input.len()..].to_string())
+}
+
+// The suggestion up to the end of its first word, for accepting one word
+// at a time
+pub fn first_word(suggestion: &str) -> &str {
+    let start = suggestion.len() - suggestion.trim_start().len();
+    let end = suggestion[start..]
+        .find(char::is_whitespace)
+        .map_or(suggestion.len(), |end| start + end);
+    &suggestion[..end]
+}
+
+// Arguments that look like paths all exist
+fn paths_exist(command: &str, cwd: &Path) -> bool {
+    command
+        .split_whitespace()
+        .skip(1)
+        .map(|word| word.trim_matches(|c| c == '\'' || c == '"'))
+        .filter(|word| looks_like_path(word, cwd))
+        .all(|word| resolve(word, cwd).exists())
+}
+
+// `/x`, `./x`, `~/x`, or `src/x` when there's a `src` here; `origin/main`
+// with no `origin` directory is more likely a git ref
+fn looks_like_path(word: &str, cwd: &Path) -> bool {
+    if word.starts_with('-') || word.contains("://") || word.contains(['$', '*', '?', '=', '`']) {
+        return false;
+    }
+    if word.starts_with(['/', '~']) || word.starts_with("./") || word.starts_with("../") {
+        return true;
+    }
+    match word.split_once('/') {
+        Some((first, _)) => cwd.join(first).is_dir(),
+        None => false,
+    }
+}
+
+fn resolve(word: &str, cwd: &Path) -> PathBuf {
+    if let Some(rest) = word.strip_prefix('~') {
+        if let Some(home) = dirs::home_dir() {
+            return home.join(rest.trim_start_matches('/'));
+        }
+    }
+    cwd.join(word)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(command: &str, cwd: &str) -> HistoryEntry {
+        HistoryEntry::new(command, Path::new(cwd))
+    }
+
+    #[test]
+    fn prefers_the_same_directory() {
+        let history = vec![entry("cargo build", "/project"), entry("cargo test", "/elsewhere")];
+        assert_eq!(suggest("cargo ", &history, Path::new("/project")), Some("build".to_string()));
+        assert_eq!(suggest("cargo ", &history, Path::new("/tmp")), Some("test".to_string()));
+        assert_eq!(suggest("cargo test", &history, Path::new("/tmp")), None);
+        assert_eq!(suggest("", &history, Path::new("/tmp")), None);
+    }
+
+    #[test]
+    fn skips_paths_that_are_gone() {
+        let history = vec![entry("cat /etc/hostname", "/"), entry("cat /no/such/file", "/")];
+        assert_eq!(suggest("cat", &history, Path::new("/")), Some(" /etc/hostname".to_string()));
+        let history = vec![entry("git log usr/gone", "/"), entry("git log origin/main", "/")];
+        assert_eq!(suggest("git log ", &history, Path::new("/")), Some("origin/main".to_string()));
+        assert_eq!(suggest("git log u", &history, Path::new("/")), None);
+    }
+
+    #[test]
+    fn accepts_a_word_at_a_time() {
+        assert_eq!(first_word(" build --release"), " build");
+        assert_eq!(first_word("ld --release"), "ld");
+        assert_eq!(first_word("ld"), "ld");
+    }
+}
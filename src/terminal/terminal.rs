@@ -1,53 +1,178 @@
 // Terminal module that encapsulates terminal functionality
 use crossterm::{
-    cursor::MoveTo,
+    cursor::{Hide, MoveTo, SetCursorStyle, Show},
     event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
-    execute,
-    style::{Color, Print, ResetColor, SetForegroundColor},
+    execute, queue,
+    style::{Print, ResetColor, SetBackgroundColor, SetForegroundColor},
     terminal::{
         disable_raw_mode, enable_raw_mode, Clear, ClearType, EnterAlternateScreen, 
         LeaveAlternateScreen, size
     },
 };
 use std::{
+    borrow::Cow,
     io::{self, Write},
-    time::Duration,
+    time::{Duration, Instant},
     env, // Add this for current directory functions
     path::PathBuf, // Add this for path manipulation
 };
 
-use crate::command::command::{CommandRegistry, CommandResult};
+use crate::command::command::{CommandRegistry, CommandResult, ShellMode};
+use crate::config::config::Config;
+use crate::history::history::{History, HistoryEntry};
+use crate::jobs::jobs::{strip_background, JobTable};
+use crate::prompt::git::GitPrompt;
+use crate::prompt::prompt::{local_time, to_ansi, PromptConfig, PromptContext, PromptTemplate, StyledText};
+use crate::pty::pty::ProcessStatus;
+use crate::screen::cell::{char_width, Attributes, CellColor, Row};
+use crate::screen::screen::Screen;
+use crate::screen::scrollback::Scrollback;
+use crate::session::session::{SessionEvent, SharedSession};
+use crate::theme::depth::ColorDepth;
+use crate::theme::theme::Theme;
+use crate::completion::bash::BashCompletion;
+use crate::completion::completion::complete;
+use crate::completion::help::HelpFlags;
+use super::editor::LineEditor;
+use super::highlight::{highlight, is_shell_command, Highlight};
+use super::history_search::HistorySearch;
+use super::scrollback_search::ScrollbackSearch;
+use super::keymap::{Action, EditMode, KeyChord, KeyContext, KeyLookup, Keymap};
+use super::keys::encode_key;
+use super::menu::{CompletionMenu, MenuLayout};
+use super::multiline::is_incomplete;
+use super::render::{draw_row, print_styled, to_color};
+use super::suggest::{first_word, suggest};
+use super::vi::{ViEditor, ViResult};
 
 // Store the terminal state
 pub struct Terminal {
     pub width: u16,
     pub height: u16,
-    pub history: Vec<String>,
-    pub scroll_position: usize,
-    pub prompt: String,
-    pub input_buffer: String,
-    pub command_history: Vec<String>,
+    pub screen: Screen,
+    // How many lines the view is scrolled back from the live screen
+    pub scroll_offset: usize,
+    // What keys do at the prompt
+    pub keymap: Keymap,
+    // Vi's modes, when the config asks for them
+    pub vi: Option<ViEditor>,
+    // Settings from the config file, with the prompt environment
+    // variables applied
+    pub config: Config,
+    // Why the config file or a prompt variable was ignored; shown by init
+    pub config_errors: Vec<String>,
+    // Colors of everything drawn
+    pub theme: Theme,
+    // Git status for the prompt, worked out in the background
+    pub git: GitPrompt,
+    pub input_buffer: LineEditor,
+    pub command_history: History,
     pub command_history_position: Option<usize>,
+    // Ctrl+R search in progress, if any
+    pub history_search: Option<HistorySearch>,
+    // Search of the scrollback in progress, if any
+    pub scrollback_search: Option<ScrollbackSearch>,
+    // Completion candidates shown above the prompt after Tab
+    pub completion_menu: Option<CompletionMenu>,
+    // Helper bash answering from installed bash-completion specs
+    pub bash_completion: BashCompletion,
+    // Flags learned from `--help` for commands without a spec
+    pub help_flags: HelpFlags,
     pub command_registry: CommandRegistry,
     pub current_dir: PathBuf, // Add current directory tracking
+    pub jobs: JobTable,
+    // Job currently running in the foreground, if any
+    pub foreground: Option<usize>,
+    // Persistent shell commands are handed to in session mode
+    pub session: Option<SharedSession>,
+    // Exit status of the last command
+    pub last_status: i32,
+    // How long the last command ran
+    pub last_duration: Option<Duration>,
+    // Background jobs of the session shell, which keeps its own table
+    pub session_jobs: usize,
+    // The running command, added to the history once it finishes
+    pub pending_history: Option<(HistoryEntry, Instant)>,
 }
 
 impl Terminal {
     pub fn new() -> io::Result<Self> {
+        // A broken config file is reported once the screen is up
+        let mut config_errors = Vec::new();
+        let config = match Config::default_path().map(|path| Config::load(&path)) {
+            Some(Ok(config)) => config,
+            Some(Err(e)) => {
+                config_errors.push(e);
+                Config::default()
+            }
+            None => Config::default(),
+        };
+        Self::with_config(config, config_errors)
+    }
+    
+    pub fn with_config(mut config: Config, mut config_errors: Vec<String>) -> io::Result<Self> {
         // Get terminal size
         let (width, height) = size()?;
         
+        if let Err(e) = config.prompt.apply_env() {
+            config_errors.push(e);
+        }
+        if let Some(dir) = &config.startup.directory {
+            if let Err(e) = env::set_current_dir(dir) {
+                config_errors.push(format!("startup directory {}: {}", dir.display(), e));
+            }
+        }
+        
+        // Fall back to an in-memory history if the file can't be used
+        let command_history = match config.history.file.clone().or_else(History::default_path) {
+            Some(path) => History::open(&path, config.history.size).unwrap_or_else(|e| {
+                config_errors.push(format!("history file {}: {}, history won't be saved", path.display(), e));
+                History::new(config.history.size)
+            }),
+            None => History::new(config.history.size),
+        };
+        let theme = Theme::load(&config.theme).unwrap_or_else(|e| {
+            config_errors.push(format!("theme: {}", e));
+            Theme {
+                depth: config.theme.colors.unwrap_or_else(ColorDepth::from_env),
+                ..Theme::default()
+            }
+        });
+        let scrollback = Scrollback::new(config.scrollback.lines, config.scrollback.max_bytes, config.scrollback.storage);
+        let command_registry = CommandRegistry::with_shell(config.shell.mode, &config.shell.program);
+        let mut keymap = Keymap::default();
+        for (context, keys, binding) in config.keybindings.iter() {
+            keymap.bind(context, keys, binding.0);
+        }
+        
         Ok(Self {
             width,
             height,
-            history: Vec::new(),
-            scroll_position: 0,
-            prompt: String::from("user@host: "),
-            input_buffer: String::new(),
-            command_history: Vec::new(),
+            screen: Screen::with_scrollback(width, height, scrollback),
+            scroll_offset: 0,
+            keymap,
+            vi: (config.editor.mode == EditMode::Vi).then(ViEditor::new),
+            config,
+            config_errors,
+            theme,
+            git: GitPrompt::new(),
+            input_buffer: LineEditor::new(),
+            command_history,
             command_history_position: None,
-            command_registry: CommandRegistry::new(),
+            history_search: None,
+            scrollback_search: None,
+            completion_menu: None,
+            bash_completion: BashCompletion::new(),
+            help_flags: HelpFlags::default(),
+            command_registry,
             current_dir: env::current_dir()?, // Initialize current directory
+            jobs: JobTable::new(),
+            foreground: None,
+            session: None,
+            last_status: 0,
+            last_duration: None,
+            session_jobs: 0,
+            pending_history: None,
         })
     }
 
@@ -56,198 +181,841 @@ impl Terminal {
         enable_raw_mode()?;
         execute!(io::stdout(), EnterAlternateScreen)?;
         
-        // Add Rust ASCII logo
-        self.add_to_history("".to_string());
-        self.add_to_history("                 _~^~^~_                 ".to_string());
-        self.add_to_history("             \\) /  o o  \\ (/            ".to_string());
-        self.add_to_history("               '_   v   _'               ".to_string());
-        self.add_to_history("              / '-----' \\               ".to_string());
-        self.add_to_history("                                         ".to_string());
-        self.add_to_history("         ██████╗ ██╗   ██╗███████╗████████╗".to_string());
-        self.add_to_history("         ██╔══██╗██║   ██║██╔════╝╚══██╔══╝".to_string());
-        self.add_to_history("         ██████╔╝██║   ██║███████╗   ██║   ".to_string());
-        self.add_to_history("         ██╔══██╗██║   ██║╚════██║   ██║   ".to_string());
-        self.add_to_history("         ██║  ██║╚██████╔╝███████║   ██║   ".to_string());
-        self.add_to_history("         ╚═╝  ╚═╝ ╚═════╝ ╚══════╝   ╚═╝   ".to_string());
-        self.add_to_history("                                         ".to_string());
-        self.add_to_history("  ******** Rust Terminal Emulator ******** ".to_string());
-        self.add_to_history("".to_string());
-        self.add_to_history("Use Ctrl+Up/Down or PageUp/PageDown to scroll through terminal history.".to_string());
-        self.add_to_history("Type 'exit' or press ESC to quit.".to_string());
-        self.add_to_history("".to_string());
+        if self.config.startup.banner {
+            self.print_banner();
+        }
+        for e in std::mem::take(&mut self.config_errors) {
+            self.print_error(&format!("Config error, using defaults: {}", e));
+        }
         
         // Render initial screen
         self.render()?;
         
         Ok(())
     }
+    
+    fn print_banner(&mut self) {
+        // Add Rust ASCII logo
+        self.print_line("");
+        self.print_line("                 _~^~^~_                 ");
+        self.print_line("             \\) /  o o  \\ (/            ");
+        self.print_line("               '_   v   _'               ");
+        self.print_line("              / '-----' \\               ");
+        self.print_line("                                         ");
+        self.print_line("         ██████╗ ██╗   ██╗███████╗████████╗");
+        self.print_line("         ██╔══██╗██║   ██║██╔════╝╚══██╔══╝");
+        self.print_line("         ██████╔╝██║   ██║███████╗   ██║   ");
+        self.print_line("         ██╔══██╗██║   ██║╚════██║   ██║   ");
+        self.print_line("         ██║  ██║╚██████╔╝███████║   ██║   ");
+        self.print_line("         ╚═╝  ╚═╝ ╚═════╝ ╚══════╝   ╚═╝   ");
+        self.print_line("                                         ");
+        self.print_line("  ******** Rust Terminal Emulator ******** ");
+        self.print_line("");
+        self.print_line("Use Ctrl+Up/Down or PageUp/PageDown to scroll through terminal history.");
+        // Esc leaves insert mode in vi mode
+        if self.vi.is_some() {
+            self.print_line("Type 'exit' to quit.");
+        } else {
+            self.print_line("Type 'exit' or press ESC to quit.");
+        }
+        self.print_line("");
+    }
 
     pub fn cleanup(&mut self) -> io::Result<()> {
+        // Don't leave jobs running (or stopped forever) without a terminal
+        self.jobs.hangup_all();
+        if let Some(session) = &self.session {
+            let _ = session.borrow().process().signal_group(libc::SIGHUP);
+        }
+        
         // Disable raw mode and leave alternate screen
+        if self.vi.is_some() {
+            execute!(io::stdout(), SetCursorStyle::DefaultUserShape)?;
+        }
         execute!(io::stdout(), LeaveAlternateScreen)?;
         disable_raw_mode()?;
         Ok(())
     }
 
-    pub fn add_to_history(&mut self, line: String) {
-        self.history.push(line);
+    // Write a line of our own (banner, messages) onto the screen
+    pub fn print_line(&mut self, line: &str) {
+        self.screen.feed(line.as_bytes());
+        self.screen.feed(b"\r\n");
         // Auto-scroll to the bottom when adding new content
         self.scroll_to_bottom();
     }
 
+    // A message about something that went wrong, in the theme's color
+    pub fn print_error(&mut self, line: &str) {
+        let attrs = Attributes {
+            fg: self.theme.error,
+            ..Attributes::default()
+        };
+        let styled: Vec<_> = line.chars().map(|c| (c, attrs)).collect();
+        self.print_line(&to_ansi(&styled));
+    }
+
     pub fn scroll_up(&mut self, lines: usize) {
-        if self.scroll_position > 0 {
-            self.scroll_position = self.scroll_position.saturating_sub(lines);
-        }
+        self.scroll_offset = (self.scroll_offset + lines).min(self.max_scroll());
     }
 
     pub fn scroll_down(&mut self, lines: usize) {
-        let max_scroll = self.max_scroll();
-        if self.scroll_position < max_scroll {
-            self.scroll_position = (self.scroll_position + lines).min(max_scroll);
-        }
+        self.scroll_offset = self.scroll_offset.saturating_sub(lines);
     }
 
     pub fn scroll_to_bottom(&mut self) {
-        self.scroll_position = self.max_scroll();
+        self.scroll_offset = 0;
     }
 
     fn max_scroll(&self) -> usize {
-        self.history.len().saturating_sub(self.height as usize - 1)
+        self.screen.scrollback_len()
+    }
+
+    pub fn resize(&mut self, width: u16, height: u16) -> io::Result<()> {
+        self.width = width;
+        self.height = height;
+        self.screen.resize(width, height);
+        self.scroll_offset = self.scroll_offset.min(self.max_scroll());
+        if let Some(job) = self.foreground.and_then(|id| self.jobs.get(id)) {
+            job.process.resize(width, height)?;
+        }
+        if let Some(session) = &self.session {
+            session.borrow().process().resize(width, height)?;
+        }
+        Ok(())
+    }
+    
+    // Whether a command owns the screen and the keyboard
+    pub fn is_running(&self) -> bool {
+        self.foreground.is_some() || self.session.as_ref().is_some_and(|session| session.borrow().is_busy())
     }
 
+    // Draw the screen. While waiting for input the grid's last row is covered
+    // by the prompt; a program running in the foreground gets every row.
     pub fn render(&self) -> io::Result<()> {
-        // Clear the screen
-        execute!(io::stdout(), Clear(ClearType::All), MoveTo(0, 0))?;
+        let mut stdout = io::stdout();
+        let running = self.is_running();
+        let prompt_rows = if running { 0 } else { 1 };
+        
+        // The completion menu pushes the output up
+        let menu = self.completion_menu.as_ref().filter(|_| !running).map(|menu| {
+            let layout = menu.layout(self.width as usize);
+            let rows = menu.visible_rows(&layout, self.height as usize);
+            (menu, layout, rows)
+        });
+        let menu_rows = menu.as_ref().map_or(0, |(_, _, rows)| rows.len());
+        
+        // And so do the continuation lines of multi-line input
+        let input_lines = match (&self.history_search, &self.scrollback_search) {
+            (None, None) if !running => self.input_buffer.text().split('\n').count(),
+            _ => 1,
+        };
+        let input_rows = input_lines.min((self.height as usize).saturating_sub(menu_rows + 1).max(1));
+        let extra_rows = if running { 0 } else { menu_rows + input_rows - 1 };
+        let view_rows = (self.height as usize - prompt_rows).saturating_sub(extra_rows);
+        
+        queue!(stdout, Hide)?;
         
-        // Calculate visible range
-        let visible_end = (self.scroll_position + self.height as usize - 1).min(self.history.len());
-        let visible_range = self.scroll_position..visible_end;
+        // Calculate visible range over scrollback + grid
+        let visible_end = self.screen.scrollback_len() + self.screen.rows() - prompt_rows - self.scroll_offset;
+        let visible_start = visible_end.saturating_sub(view_rows);
         
-        // Render visible history
-        for (i, line) in self.history[visible_range].iter().enumerate() {
-            // Position cursor and print line
-            execute!(io::stdout(), MoveTo(0, i as u16), Print(line))?;
+        // Render visible lines
+        for i in 0..view_rows {
+            let line = visible_start + i;
+            let highlighted = self.scrollback_search.as_ref().and_then(|search| self.highlight_matches(search, line));
+            let row = highlighted.map(Cow::Owned).or_else(|| self.screen.line(line));
+            draw_row(&mut stdout, i as u16, row.as_deref(), self.width as usize, &self.theme)?;
         }
         
-        // Get dynamic prompt with actual path
-        let dynamic_prompt = self.get_prompt();
+        if let Some((menu, layout, rows)) = &menu {
+            self.render_menu(&mut stdout, menu, layout, rows.clone(), view_rows)?;
+        }
         
-        // Position cursor for input line at the bottom
-        execute!(
-            io::stdout(), 
-            MoveTo(0, self.height - 1),
-            SetForegroundColor(Color::Green),
-            Print(&dynamic_prompt),
-            ResetColor,
-            Print(&self.input_buffer)
-        )?;
+        if running {
+            // Put the cursor where the program wants it, unless we're scrolled back
+            let cursor = self.screen.cursor();
+            if self.scroll_offset == 0 && self.screen.cursor_visible() {
+                queue!(stdout, MoveTo(cursor.col as u16, cursor.row as u16), Show)?;
+            }
+            if self.vi.is_some() {
+                queue!(stdout, SetCursorStyle::DefaultUserShape)?;
+            }
+        } else if let Some(search) = &self.history_search {
+            self.render_history_search(&mut stdout, search)?;
+        } else if let Some(search) = &self.scrollback_search {
+            self.render_scrollback_search(&mut stdout, search)?;
+        } else {
+            self.render_input(&mut stdout, input_rows)?;
+        }
         
-        io::stdout().flush()?;
+        stdout.flush()?;
         
         Ok(())
     }
+    
+    // Draw the input at the bottom, `rows` lines of it: the prompt before
+    // the first line and the continuation prompt before the others
+    fn render_input(&self, stdout: &mut io::Stdout, rows: usize) -> io::Result<()> {
+        let dynamic_prompt = self.get_prompt();
+        let continuation = self.continuation_prompt();
+        let right_prompt = self.right_prompt();
+        let text = self.input_buffer.text();
+        let lines: Vec<&str> = text.split('\n').collect();
+        let before_cursor = self.input_buffer.before_cursor();
+        let cursor_line = before_cursor.matches('\n').count();
+        let cursor_column = before_cursor.rsplit('\n').next().unwrap_or("").chars().count();
+        let mut styles = self.highlight_input().into_iter();
+        let selection = self.vi.as_ref().and_then(|vi| vi.selection(&self.input_buffer));
+        
+        // The suggestion only shows with the cursor at the very end
+        let suggestion = match self.completion_menu {
+            None => self.suggestion().unwrap_or_default(),
+            Some(_) => String::new(),
+        };
+        
+        // Keep the cursor's line in view when there are more than fit
+        let first = (cursor_line + 1).saturating_sub(rows);
+        let top = self.height as usize - rows;
+        let mut cursor = (0, self.height - 1);
+        let mut line_offset = 0;
+        for (index, line) in lines.iter().enumerate() {
+            let line_styles: Vec<_> = styles.by_ref().take(line.chars().count() + 1).collect();
+            let offset = line_offset;
+            line_offset += line.chars().count() + 1;
+            if index < first || index >= first + rows {
+                continue;
+            }
+            let y = (top + index - first) as u16;
+            let prefix = if index == 0 { &dynamic_prompt } else { &continuation };
+            
+            // Scroll the line sideways when it doesn't fit, keeping the
+            // cursor in view
+            let prefix_width = prefix.len();
+            let available = (self.width as usize).saturating_sub(prefix_width).max(1);
+            let skip = if index == cursor_line { (cursor_column + 1).saturating_sub(available) } else { 0 };
+            
+            // The right prompt only shows on the first line, while the
+            // input leaves room for it
+            let right = right_prompt
+                .as_ref()
+                .filter(|right| index == 0 && prefix_width + line.chars().count() + right.len() < self.width as usize);
+            let reserved = right.map_or(0, |right| right.len() + 1);
+            let visible = line
+                .chars()
+                .zip(line_styles)
+                .enumerate()
+                .skip(skip)
+                .take(available)
+                .map(|(i, (c, style))| {
+                    let mut attributes = style.attributes(&self.theme);
+                    if selection.as_ref().is_some_and(|selection| selection.contains(&(offset + i))) {
+                        attributes.bg = self.theme.selection;
+                    }
+                    (c, attributes)
+                });
+            
+            queue!(stdout, MoveTo(0, y))?;
+            print_styled(stdout, prefix.iter().copied(), &self.theme)?;
+            print_styled(stdout, visible, &self.theme)?;
+            
+            if index == cursor_line {
+                // The suggestion fills what room is left after the input
+                let room = available.saturating_sub(line.chars().count() - skip + reserved);
+                let ghost: String = suggestion.chars().take(room).collect();
+                queue!(stdout, SetForegroundColor(to_color(self.theme.suggestion, &self.theme)), Print(&ghost), ResetColor)?;
+                cursor = ((prefix_width + cursor_column - skip).min(self.width as usize - 1) as u16, y);
+            }
+            queue!(stdout, Clear(ClearType::UntilNewLine))?;
+            if let Some(right) = right {
+                queue!(stdout, MoveTo(self.width - right.len() as u16, y))?;
+                print_styled(stdout, right.iter().copied(), &self.theme)?;
+            }
+        }
+        
+        if let Some(vi) = &self.vi {
+            queue!(stdout, vi.mode().cursor_style())?;
+        }
+        queue!(stdout, MoveTo(cursor.0, cursor.1), Show)
+    }
+
+    // Color the input line by what each part is to the shell
+    fn highlight_input(&self) -> Vec<Highlight> {
+        highlight(self.input_buffer.text(), |name| {
+            self.command_registry.find(name).is_some()
+                || self.command_registry.aliases().contains_key(name)
+                || is_shell_command(name, &self.current_dir)
+        })
+    }
 
     pub fn process_keyboard_input(&mut self) -> io::Result<bool> {
-        // Check for keyboard events with a timeout
-        if event::poll(Duration::from_millis(100))? {
-            if let Event::Key(KeyEvent { code, modifiers, .. }) = event::read()? {
-                match code {
-                    KeyCode::Esc => {
-                        self.add_to_history("Exiting...".to_string());
-                        self.render()?;
-                        return Ok(false); // Signal to exit
-                    }
-                    
-                    KeyCode::Enter => {
-                        // Process the current input
-                        let command = self.input_buffer.clone();
-                        
-                        // Get the current dynamic prompt
-                        let current_prompt = self.get_prompt();
-                        
-                        // Add command to history display with the current dynamic prompt
-                        self.add_to_history(format!("{}{}", current_prompt, command));
-                        
-                        // Check for exit command
-                        if command.trim() == "exit" {
-                            self.add_to_history("Exiting...".to_string());
-                            self.render()?;
-                            return Ok(false); // Signal to exit
-                        }
-                        
-                        // Add to command history if not empty
-                        if !command.trim().is_empty() {
-                            self.command_history.push(command.clone());
-                            self.command_history_position = None;
-                        }
-                        
-                        // Clear input buffer
-                        self.input_buffer.clear();
-                        
-                        // Process and display command output
-                        if !command.trim().is_empty() {
-                            self.execute_command(&command)?;
-                        }
-                        
-                        // Re-render after command execution
-                        self.render()?;
-                    }
-                    
-                    KeyCode::Char(c) => {
-                        // Add character to input buffer
-                        self.input_buffer.push(c);
-                        self.render()?;
-                    }
-                    
-                    KeyCode::Backspace => {
-                        // Remove last character from input
-                        if !self.input_buffer.is_empty() {
-                            self.input_buffer.pop();
-                            self.render()?;
-                        }
+        // Check for keyboard events with a timeout, a short one while a
+        // program is running so its output keeps flowing
+        let timeout = if self.is_running() { 10 } else { 100 };
+        if event::poll(Duration::from_millis(timeout))? {
+            match event::read()? {
+                Event::Resize(width, height) => {
+                    self.resize(width, height)?;
+                    self.render()?;
+                }
+                Event::Key(key) if self.is_running() => {
+                    self.forward_key(key)?;
+                }
+                Event::Key(key) => {
+                    let keep_running = if self.history_search.is_some() {
+                        self.handle_search_key(key)?
+                    } else if self.scrollback_search.is_some() {
+                        self.handle_scrollback_search_key(key)?
+                    } else if self.completion_menu.is_some() {
+                        self.handle_menu_key(key)?
+                    } else {
+                        self.handle_prompt_key(key)?
+                    };
+                    if !keep_running {
+                        return Ok(false);
                     }
-                    
-                    KeyCode::Up => {
-                        if modifiers.contains(KeyModifiers::CONTROL) {
-                            // Scroll up with Ctrl+Up
-                            self.scroll_up(1);
-                            self.render()?;
-                        } else {
-                            // Navigate command history (up)
-                            self.navigate_history_up()?;
-                        }
+                }
+                _ => {}
+            }
+        }
+        
+        Ok(true) // Continue running
+    }
+    
+    // Keys at the prompt go through the keymap. Returns false when it's
+    // time to exit.
+    fn handle_prompt_key(&mut self, key: KeyEvent) -> io::Result<bool> {
+        if let Some(vi) = &mut self.vi {
+            match vi.handle_key(&key, &mut self.input_buffer) {
+                ViResult::Handled => {
+                    self.render()?;
+                    return Ok(true);
+                }
+                ViResult::Action(action) => {
+                    let before = self.input_buffer.text().to_string();
+                    if !self.run_action(action)? {
+                        return Ok(false);
                     }
-                    
-                    KeyCode::Down => {
-                        if modifiers.contains(KeyModifiers::CONTROL) {
-                            // Scroll down with Ctrl+Down
-                            self.scroll_down(1);
-                            self.render()?;
-                        } else {
-                            // Navigate command history (down)
-                            self.navigate_history_down()?;
-                        }
+                    // A recalled command starts with the cursor on its
+                    // first character, as in vi mode in bash
+                    if self.input_buffer.text() != before {
+                        self.input_buffer.move_to(0);
                     }
-                    
-                    KeyCode::PageUp => {
-                        // Scroll up one page
-                        self.scroll_up(self.height as usize / 2);
-                        self.render()?;
+                    if let Some(vi) = &self.vi {
+                        vi.clamp_cursor(&mut self.input_buffer);
                     }
-                    
-                    KeyCode::PageDown => {
-                        // Scroll down one page
-                        self.scroll_down(self.height as usize / 2);
+                    self.render()?;
+                    return Ok(true);
+                }
+                ViResult::Unhandled => {}
+            }
+        }
+        match self.keymap.feed(&key) {
+            KeyLookup::Action(action) => self.run_action(action),
+            KeyLookup::Pending => Ok(true),
+            // Text is typed in; a sequence that led nowhere is dropped
+            KeyLookup::Unbound(keys) => {
+                if let [chord] = keys.as_slice() {
+                    if let Some(c) = chord.typed_char() {
+                        self.input_buffer.insert_char(c);
                         self.render()?;
                     }
-                    
-                    _ => {}
+                }
+                Ok(true)
+            }
+        }
+    }
+    
+    fn run_action(&mut self, action: Action) -> io::Result<bool> {
+        match action {
+            Action::Quit => {
+                self.print_line("Exiting...");
+                self.render()?;
+                return Ok(false); // Signal to exit
+            }
+            
+            // Starts a new line without running anything
+            Action::InsertNewline => self.input_buffer.insert_str("\n"),
+            
+            // An unfinished command continues on the next line
+            Action::AcceptLine if is_incomplete(self.input_buffer.text()) => {
+                self.input_buffer.move_to(self.input_buffer.text().len());
+                self.input_buffer.insert_str("\n");
+            }
+            
+            Action::AcceptLine => return self.accept_line(),
+            
+            Action::HistoryPrev => {
+                if !self.input_buffer.move_line(true) {
+                    return self.navigate_history_up().map(|_| true);
+                }
+            }
+            
+            Action::HistoryNext => {
+                if !self.input_buffer.move_line(false) {
+                    return self.navigate_history_down().map(|_| true);
+                }
+            }
+            
+            Action::ScrollUp => self.scroll_up(1),
+            Action::ScrollDown => self.scroll_down(1),
+            Action::ScrollPageUp => self.scroll_up(self.scroll_step()),
+            Action::ScrollPageDown => self.scroll_down(self.scroll_step()),
+            Action::ScrollToBottom => self.scroll_to_bottom(),
+            
+            Action::Complete => {
+                self.complete()?;
+                return Ok(true);
+            }
+            
+            Action::SearchHistory => {
+                self.history_search = Some(HistorySearch::new(self.input_buffer.text(), self.command_history.entries()));
+            }
+            
+            Action::SearchScrollback => {
+                self.scrollback_search = Some(ScrollbackSearch::new(self.scroll_offset));
+            }
+            
+            Action::ClearScreen => {
+                self.screen.feed(b"\x1b[H\x1b[2J");
+                self.scroll_to_bottom();
+            }
+            
+            // At the end of the input these take the autosuggestion
+            Action::ForwardChar | Action::EndOfLine | Action::ForwardWord => {
+                if !self.accept_suggestion(action) && !self.input_buffer.perform(action) {
+                    return Ok(true);
+                }
+            }
+            
+            // Everything else is line editing
+            _ => {
+                if !self.input_buffer.perform(action) {
+                    return Ok(true);
                 }
             }
         }
         
-        Ok(true) // Continue running
+        self.render()?;
+        Ok(true)
+    }
+    
+    // Run the input line. Returns false when it's time to exit.
+    fn accept_line(&mut self) -> io::Result<bool> {
+        // Process the current input
+        let command = self.input_buffer.text().to_string();
+        
+        // Get the current dynamic prompt
+        let current_prompt = to_ansi(&self.get_prompt());
+        let continuation = to_ansi(&self.continuation_prompt());
+        
+        // Echo the command onto the screen with the current dynamic prompt
+        for (i, line) in command.split('\n').enumerate() {
+            let prompt = if i == 0 { &current_prompt } else { &continuation };
+            self.print_line(&format!("{}{}", prompt, line));
+        }
+        
+        // Check for exit command
+        if command.trim() == "exit" {
+            self.print_line("Exiting...");
+            self.render()?;
+            return Ok(false); // Signal to exit
+        }
+        
+        // Add to command history if not empty, once we know
+        // how it went
+        if !command.trim().is_empty() {
+            self.pending_history = Some((HistoryEntry::new(&command, &self.current_dir), Instant::now()));
+            self.command_history_position = None;
+        }
+        
+        // Clear input buffer
+        self.input_buffer.clear();
+        if let Some(vi) = &mut self.vi {
+            vi.reset();
+        }
+        
+        // Process and display command output
+        if !command.trim().is_empty() {
+            self.execute_command(&command)?;
+        }
+        if !self.is_running() {
+            self.finish_history_entry();
+        }
+        
+        // Re-render after command execution
+        self.render()?;
+        
+        Ok(true)
+    }
+    
+    // The rest of a history entry matching the input, shown dimmed when
+    // the cursor is at the end of the line
+    fn suggestion(&self) -> Option<String> {
+        if !self.config.history.autosuggest || self.input_buffer.cursor() < self.input_buffer.text().len() {
+            return None;
+        }
+        suggest(self.input_buffer.text(), self.command_history.entries(), &self.current_dir)
+    }
+    
+    // Moving forward takes the whole suggestion, or its next word for a
+    // word motion
+    fn accept_suggestion(&mut self, action: Action) -> bool {
+        let one_word = action == Action::ForwardWord;
+        let Some(suggestion) = self.suggestion() else {
+            return false;
+        };
+        let accepted = if one_word { first_word(&suggestion) } else { &suggestion };
+        self.input_buffer.insert_str(accepted);
+        true
+    }
+    
+    // Complete the word before the cursor: a single candidate is filled
+    // in, several open the menu after filling in what they have in common
+    fn complete(&mut self) -> io::Result<()> {
+        let text = self.input_buffer.text().to_string();
+        let cursor = self.input_buffer.cursor();
+        // Builtins know their own arguments, then installed completion
+        // specs, then flags from `--help`; everything else gets commands,
+        // paths and variables
+        let completion = self
+            .command_registry
+            .complete(&text, cursor)
+            .or_else(|| self.bash_completion.complete(&text, cursor, &self.current_dir))
+            .or_else(|| self.help_flags.complete(&text, cursor))
+            .unwrap_or_else(|| complete(&text, cursor, &self.current_dir));
+        
+        match completion.candidates.as_slice() {
+            [] => {}
+            [candidate] => {
+                let mut replacement = candidate.replacement.clone();
+                if candidate.complete {
+                    replacement.push(' ');
+                }
+                self.input_buffer.replace_before_cursor(completion.start, &replacement);
+            }
+            _ => {
+                let prefix = completion.common_prefix();
+                if prefix.len() > cursor - completion.start {
+                    let prefix = prefix.to_string();
+                    self.input_buffer.replace_before_cursor(completion.start, &prefix);
+                }
+                self.completion_menu = Some(CompletionMenu::new(
+                    completion,
+                    self.input_buffer.text(),
+                    self.input_buffer.cursor(),
+                ));
+            }
+        }
+        
+        self.render()
+    }
+    
+    // Keys while the completion menu is open: Tab and the arrow keys fill in
+    // candidates, Enter keeps the one filled in, Esc goes back to what was
+    // typed. Anything else closes the menu and is handled as usual.
+    fn handle_menu_key(&mut self, key: KeyEvent) -> io::Result<bool> {
+        let action = self.keymap.lookup(KeyContext::Menu, &key);
+        let Some(menu) = self.completion_menu.as_mut() else {
+            return self.handle_prompt_key(key);
+        };
+        
+        let candidate = match action {
+            Some(Action::MenuNext) => menu.select_next(),
+            Some(Action::MenuPrevious) => menu.select_previous(),
+            Some(Action::MenuAccept) if menu.selected.is_some() => {
+                self.completion_menu = None;
+                self.render()?;
+                return Ok(true);
+            }
+            Some(Action::MenuCancel) => {
+                let (text, cursor) = menu.original.clone();
+                self.input_buffer.set_text(&text);
+                self.input_buffer.move_to(cursor);
+                self.completion_menu = None;
+                self.render()?;
+                return Ok(true);
+            }
+            _ => {
+                self.completion_menu = None;
+                return self.handle_prompt_key(key);
+            }
+        };
+        let replacement = candidate.replacement.clone();
+        let start = menu.completion.start;
+        self.input_buffer.replace_before_cursor(start, &replacement);
+        self.render()?;
+        Ok(true)
+    }
+    
+    fn render_menu(
+        &self,
+        stdout: &mut io::Stdout,
+        menu: &CompletionMenu,
+        layout: &MenuLayout,
+        rows: std::ops::Range<usize>,
+        top: usize,
+    ) -> io::Result<()> {
+        let width = self.width as usize;
+        for (y, row) in layout.rows[rows].iter().enumerate() {
+            queue!(stdout, MoveTo(0, (top + y) as u16))?;
+            let mut used = 0;
+            for &index in row {
+                let candidate = &menu.completion.candidates[index];
+                let text: String = candidate.display.chars().take(layout.column_width.saturating_sub(1)).collect();
+                let padding = layout.column_width.saturating_sub(text.chars().count());
+                if menu.selected == Some(index) {
+                    queue!(stdout, SetBackgroundColor(to_color(self.theme.selection, &self.theme)), Print(&text), ResetColor)?;
+                } else {
+                    queue!(stdout, Print(&text))?;
+                }
+                queue!(stdout, Print(" ".repeat(padding)))?;
+                used += layout.column_width;
+                
+                if let (true, Some(description)) = (layout.described, &candidate.description) {
+                    let description: String = description.chars().take(width.saturating_sub(used)).collect();
+                    used += description.chars().count();
+                    queue!(stdout, SetForegroundColor(to_color(self.theme.description, &self.theme)), Print(description), ResetColor)?;
+                }
+            }
+            if used < width {
+                queue!(stdout, Clear(ClearType::UntilNewLine))?;
+            }
+        }
+        Ok(())
+    }
+    
+    // Keys while searching the history: typing refines the search, Ctrl+R
+    // and Ctrl+S step to older and newer matches, Enter takes the match into
+    // the input line and Esc puts back what was there before
+    fn handle_search_key(&mut self, key: KeyEvent) -> io::Result<bool> {
+        let action = self.keymap.lookup(KeyContext::HistorySearch, &key);
+        let Some(search) = self.history_search.as_mut() else {
+            return Ok(true);
+        };
+        
+        match (KeyChord::from_event(&key).typed_char(), action) {
+            (Some(c), _) => search.push(c, self.command_history.entries()),
+            (_, Some(Action::SearchOlder)) => search.older(),
+            (_, Some(Action::SearchNewer)) => search.newer(),
+            (_, Some(Action::SearchCancel)) => self.cancel_history_search(),
+            (_, Some(Action::BackwardDeleteChar)) => search.pop(self.command_history.entries()),
+            (_, Some(Action::SearchAccept)) => self.accept_history_search(),
+            // Anything else ends the search and does what it does at the
+            // prompt to the found command
+            _ => {
+                self.accept_history_search();
+                if !self.handle_prompt_key(key)? {
+                    return Ok(false);
+                }
+            }
+        }
+        
+        self.render()?;
+        Ok(true)
+    }
+    
+    fn accept_history_search(&mut self) {
+        let Some(search) = self.history_search.take() else {
+            return;
+        };
+        match search.current() {
+            Some(found) => {
+                self.input_buffer.set_text(&self.command_history.entries()[found.index].command);
+                // Up/Down carry on from the found command
+                self.command_history_position = Some(found.index);
+            }
+            None => self.input_buffer.set_text(search.original()),
+        }
+    }
+    
+    fn cancel_history_search(&mut self) {
+        if let Some(search) = self.history_search.take() {
+            self.input_buffer.set_text(search.original());
+        }
+    }
+    
+    // The search prompt with the found command, its matching characters
+    // underlined, in place of the input line
+    fn render_history_search(&self, stdout: &mut io::Stdout, search: &HistorySearch) -> io::Result<()> {
+        let label = if search.failed() { "failed reverse-i-search" } else { "reverse-i-search" };
+        let label = format!("({})`{}': ", label, search.query());
+        let cursor_col = label.chars().count().min(self.width as usize - 1);
+        let mut remaining = (self.width as usize).saturating_sub(label.chars().count());
+        
+        queue!(
+            stdout,
+            MoveTo(0, self.height - 1),
+            SetForegroundColor(to_color(self.theme.status_bar, &self.theme)),
+            Print(&label),
+            ResetColor
+        )?;
+        if let Some(found) = search.current() {
+            for (i, c) in self.command_history.entries()[found.index].command.chars().enumerate() {
+                // The bar is one row, so a multi-line command shows where
+                // its lines break
+                let c = match c {
+                    '\n' => '↵',
+                    c if c.is_control() => ' ',
+                    c => c,
+                };
+                let width = char_width(c);
+                if width > remaining {
+                    break;
+                }
+                if found.positions.contains(&i) {
+                    queue!(
+                        stdout,
+                        SetForegroundColor(to_color(CellColor::Indexed(0), &self.theme)),
+                        SetBackgroundColor(to_color(self.theme.search_match, &self.theme)),
+                        Print(c),
+                        ResetColor
+                    )?;
+                } else {
+                    queue!(stdout, Print(c))?;
+                }
+                remaining -= width;
+            }
+        }
+        queue!(
+            stdout,
+            Clear(ClearType::UntilNewLine),
+            MoveTo(cursor_col as u16, self.height - 1),
+            Show
+        )?;
+        Ok(())
+    }
+    
+    // Keys while searching the scrollback. While the query is typed, Up and
+    // Down step through the matches and Enter moves on to n and N; Esc puts
+    // the view back where it was, and any other key after the query ends the
+    // search and goes to the prompt. Returns false when it's time to exit.
+    fn handle_scrollback_search_key(&mut self, key: KeyEvent) -> io::Result<bool> {
+        let from = self.view_bottom();
+        let action = self.keymap.lookup(KeyContext::ScrollbackSearch, &key);
+        let Some(search) = self.scrollback_search.as_mut() else {
+            return Ok(true);
+        };
+        let typed = KeyChord::from_event(&key).typed_char().filter(|_| search.editing());
+        
+        match (typed, action) {
+            (Some(c), _) => search.push(c, &self.screen, from),
+            (_, Some(Action::SearchCancel)) => self.cancel_scrollback_search(),
+            (_, Some(Action::ToggleRegex)) => search.toggle_regex(&self.screen, from),
+            (_, Some(Action::ToggleCase)) => search.toggle_case(&self.screen, from),
+            (_, Some(Action::SearchOlder)) => search.older(),
+            (_, Some(Action::SearchNewer)) => search.newer(),
+            (_, Some(Action::BackwardDeleteChar)) if search.editing() => search.pop(&self.screen, from),
+            (_, Some(Action::SearchAccept)) if search.editing() && search.match_count() > 0 => search.finish_editing(),
+            (_, Some(Action::SearchAccept)) => self.scrollback_search = None,
+            (_, Some(Action::EditSearch)) if !search.editing() => search.edit(),
+            // Anything else ends the search where it is and goes to the
+            // prompt
+            _ => {
+                self.scrollback_search = None;
+                return self.handle_prompt_key(key);
+            }
+        }
+        
+        self.scroll_to_match();
+        self.render()?;
+        Ok(true)
+    }
+    
+    fn cancel_scrollback_search(&mut self) {
+        if let Some(search) = self.scrollback_search.take() {
+            self.scroll_offset = search.original_offset().min(self.max_scroll());
+        }
+    }
+    
+    // The line at the bottom of the view while the search bar is shown
+    fn view_bottom(&self) -> usize {
+        (self.screen.total_lines() - 1 - self.scroll_offset).saturating_sub(1)
+    }
+    
+    // Scroll so the current match is in the middle of the view, unless it's
+    // already in view
+    fn scroll_to_match(&mut self) {
+        let Some((_, found)) = self.scrollback_search.as_ref().and_then(|search| search.current()) else {
+            return;
+        };
+        let view_rows = self.height as usize - 1;
+        let bottom = self.view_bottom();
+        if found.line + view_rows > bottom && found.line <= bottom {
+            return;
+        }
+        let end = (found.line + view_rows / 2 + 1).min(self.screen.total_lines() - 1);
+        self.scroll_offset = (self.screen.total_lines() - 1 - end).min(self.max_scroll());
+    }
+    
+    // A copy of the line with its matches colored, the current one in
+    // reverse, or None when it has none
+    fn highlight_matches(&self, search: &ScrollbackSearch, line: usize) -> Option<Row> {
+        let matches = search.matches_on(line);
+        if matches.is_empty() {
+            return None;
+        }
+        let mut row = self.screen.line(line)?.into_owned();
+        let current = search.current().map(|(_, found)| *found);
+        for found in matches {
+            for cell in row.cells.iter_mut().take(found.end).skip(found.start) {
+                cell.attrs.fg = CellColor::Indexed(0);
+                cell.attrs.bg = self.theme.search_match;
+                cell.attrs.inverse = current == Some(*found);
+            }
+        }
+        Some(row)
+    }
+    
+    // The query at the bottom, with how it's matched and which of how many
+    // matches is current on the right
+    fn render_scrollback_search(&self, stdout: &mut io::Stdout, search: &ScrollbackSearch) -> io::Result<()> {
+        let label = if search.is_regex() { "regex search: " } else { "search: " };
+        let status = match (search.error(), search.current()) {
+            (Some(error), _) => error.to_string(),
+            (None, Some((index, _))) => format!("{}/{}", index + 1, search.match_count()),
+            (None, None) if search.query().is_empty() => String::new(),
+            (None, None) => "no matches".to_string(),
+        };
+        let right = format!("[{}] {}", search.case().name(), status);
+        let width = self.width as usize;
+        let room = width.saturating_sub(label.len() + right.chars().count() + 1);
+        // The end of a long query, where the typing is
+        let query: String = {
+            let skip = search.query().chars().count().saturating_sub(room);
+            search.query().chars().skip(skip).collect()
+        };
+        let cursor_col = (label.len() + query.chars().count()).min(width - 1);
+        let status_color = if search.error().is_some() || search.current().is_none() {
+            self.theme.error
+        } else {
+            self.theme.status_bar
+        };
+        
+        queue!(
+            stdout,
+            MoveTo(0, self.height - 1),
+            SetForegroundColor(to_color(self.theme.status_bar, &self.theme)),
+            Print(label),
+            ResetColor,
+            Print(&query),
+            Clear(ClearType::UntilNewLine)
+        )?;
+        if label.len() + query.chars().count() + right.chars().count() < width {
+            queue!(
+                stdout,
+                MoveTo((width - right.chars().count()) as u16, self.height - 1),
+                Print(format!("[{}] ", search.case().name())),
+                SetForegroundColor(to_color(status_color, &self.theme)),
+                Print(&status),
+                ResetColor
+            )?;
+        }
+        queue!(stdout, MoveTo(cursor_col as u16, self.height - 1))?;
+        if search.editing() {
+            queue!(stdout, Show)?;
+        }
+        Ok(())
     }
     
     fn navigate_history_up(&mut self) -> io::Result<()> {
@@ -262,7 +1030,7 @@ impl Terminal {
         };
         
         if let Some(pos) = new_pos {
-            self.input_buffer = self.command_history[pos].clone();
+            self.input_buffer.set_text(&self.command_history.entries()[pos].command);
             self.command_history_position = Some(pos);
             self.render()?;
         }
@@ -283,7 +1051,7 @@ impl Terminal {
         
         match new_pos {
             Some(pos) => {
-                self.input_buffer = self.command_history[pos].clone();
+                self.input_buffer.set_text(&self.command_history.entries()[pos].command);
                 self.command_history_position = Some(pos);
             },
             None => {
@@ -298,45 +1066,370 @@ impl Terminal {
 
     // Execute a command using the command registry
     fn execute_command(&mut self, command: &str) -> io::Result<()> {
-        // Temporarily disable raw mode while running the command
-        disable_raw_mode()?;
+        // A persistent shell does its own job control
+        let own_jobs = self.command_registry.shell_mode() == ShellMode::PerCommand;
+        
+        // Job control builtins work on our own job table
+        if own_jobs && self.run_job_builtin(command)? {
+            return Ok(());
+        }
+        
+        // A trailing `&` starts the command as a background job
+        let (command, background) = match strip_background(command) {
+            Some(command) if own_jobs => (command, true),
+            _ => (command, false),
+        };
         
         // Execute the command using our command registry
-        match self.command_registry.execute_bash_command(command) {
+        match self.command_registry.execute(command) {
             Ok(result) => {
                 match result {
                     CommandResult::Output(output) => {
                         // Split output by lines and add each to history
                         for line in output.lines() {
-                            self.add_to_history(line.to_string());
+                            self.print_line(line);
                         }
+                        self.last_status = 0;
                     },
                     CommandResult::Error(error) => {
                         // Display error with a prefix
                         for line in error.lines() {
-                            self.add_to_history(format!("Error: {}", line));
+                            self.print_error(&format!("Error: {}", line));
                         }
+                        self.last_status = 1;
                     },
                     CommandResult::Empty => {
                         // Command executed successfully but produced no output
+                        self.last_status = 0;
                     },
                     CommandResult::DirectoryChanged(new_dir) => {
                         // Update our tracked current directory
                         self.current_dir = new_dir;
+                        self.last_status = 0;
+                    },
+                    CommandResult::Spawned(process) => {
+                        process.resize(self.width, self.height)?;
+                        let pid = process.pid();
+                        let id = self.jobs.add(command, process);
+                        if background {
+                            self.print_line(&format!("[{}] {}", id, pid));
+                            self.last_status = 0;
+                        } else {
+                            self.set_foreground(id);
+                        }
+                    }
+                    CommandResult::Session(session) => {
+                        self.session = Some(session);
+                        self.scroll_to_bottom();
                     }
                 }
             },
             Err(e) => {
-                self.add_to_history(format!("Failed to execute command: {}", e));
+                self.print_error(&format!("Failed to execute command: {}", e));
+                self.last_status = 1;
             }
         }
         
-        // Re-enable raw mode
-        enable_raw_mode()?;
+        Ok(())
+    }
+
+    // Handle `jobs`, `fg` and `bg`. Returns false for any other command.
+    fn run_job_builtin(&mut self, command: &str) -> io::Result<bool> {
+        let mut words = command.split_whitespace();
+        let builtin = words.next().unwrap_or("");
+        let spec = words.next();
+        
+        match builtin {
+            "jobs" => {
+                let lines: Vec<String> = self.jobs.iter().map(|job| self.jobs.describe(job)).collect();
+                for line in lines {
+                    self.print_line(&line);
+                }
+            }
+            "fg" | "bg" => {
+                let id = match self.jobs.resolve(spec) {
+                    Ok(id) => id,
+                    Err(e) => {
+                        self.print_error(&format!("{}: {}", builtin, e));
+                        self.last_status = 1;
+                        return Ok(true);
+                    }
+                };
+                let Some(job) = self.jobs.get_mut(id) else {
+                    return Ok(true);
+                };
+                
+                if builtin == "bg" && job.process.status() == ProcessStatus::Running {
+                    self.print_line(&format!("bg: job {} already in background", id));
+                    self.last_status = 1;
+                    return Ok(true);
+                }
+                
+                let line = if builtin == "fg" {
+                    job.command.clone()
+                } else {
+                    format!("[{}]+ {} &", id, job.command)
+                };
+                job.process.resize(self.width, self.height)?;
+                job.process.resume()?;
+                job.reported = ProcessStatus::Running;
+                
+                self.print_line(&line);
+                self.jobs.touch(id);
+                if builtin == "fg" {
+                    self.set_foreground(id);
+                }
+            }
+            _ => return Ok(false),
+        }
         
+        self.last_status = 0;
+        Ok(true)
+    }
+
+    // Hand the terminal over to a job; its output is picked up by
+    // process_output on every pass of the main loop
+    fn set_foreground(&mut self, id: usize) {
+        self.foreground = Some(id);
+        self.scroll_to_bottom();
+    }
+
+    // Keys go to the foreground job, except the ones bound while a program
+    // runs (Shift+PageUp/PageDown), which still scroll our own view
+    fn forward_key(&mut self, key: KeyEvent) -> io::Result<()> {
+        if let Some(action) = self.keymap.lookup(KeyContext::Running, &key) {
+            self.run_action(action)?;
+            return Ok(());
+        }
+        
+        // Job control keys become signals for the job's foreground process
+        // group, unless the program switched them off to read them as keys
+        let signal = match key.code {
+            KeyCode::Char('c') => Some(libc::SIGINT),
+            KeyCode::Char('z') => Some(libc::SIGTSTP),
+            KeyCode::Char('\\') | KeyCode::Char('4') => Some(libc::SIGQUIT),
+            _ => None,
+        }
+        .filter(|_| key.modifiers.contains(KeyModifiers::CONTROL));
+        let bytes = encode_key(&key, self.screen.application_cursor());
+        
+        let typed = if let Some(job) = self.foreground.and_then(|id| self.jobs.get_mut(id)) {
+            if let Some(signal) = signal.filter(|_| job.process.signals_enabled()) {
+                job.process.signal_foreground(signal)?;
+                false
+            } else if let Some(bytes) = &bytes {
+                job.process.write_all(bytes)?;
+                true
+            } else {
+                false
+            }
+        } else if let Some(session) = &self.session {
+            let mut session = session.borrow_mut();
+            if let Some(signal) = signal.filter(|_| session.process().signals_enabled()) {
+                session.process().signal_foreground(signal)?;
+                false
+            } else if let Some(bytes) = &bytes {
+                session.write_all(bytes)?;
+                true
+            } else {
+                false
+            }
+        } else {
+            false
+        };
+        
+        // Typing jumps back to the live screen
+        if typed && self.scroll_offset > 0 {
+            self.scroll_to_bottom();
+            self.render()?;
+        }
         Ok(())
     }
+    
+    // Feed output of running jobs into the screen as it arrives, and keep
+    // track of jobs that stopped or finished
+    pub fn process_output(&mut self) -> io::Result<()> {
+        let mut changed = false;
+        
+        for job in self.jobs.iter_mut() {
+            let output = job.process.take_output();
+            if output.is_empty() {
+                continue;
+            }
+            self.screen.feed(&output);
+            // Answer queries like cursor position reports
+            let replies = self.screen.take_replies();
+            if Some(job.id) == self.foreground && !replies.is_empty() {
+                job.process.write_all(&replies)?;
+            }
+            changed = true;
+        }
+        
+        changed |= self.update_foreground()?;
+        changed |= self.update_session()?;
+        changed |= self.git.update(&self.current_dir);
+        
+        if !self.is_running() {
+            self.finish_history_entry();
+        }
+        
+        // Like a shell, only report on background jobs while at the prompt
+        if self.foreground.is_none() {
+            changed |= self.update_background_jobs()?;
+        }
+        
+        if changed {
+            self.render()?;
+        }
+        
+        Ok(())
+    }
+
+    // Returns whether the foreground job finished or stopped
+    fn update_foreground(&mut self) -> io::Result<bool> {
+        let Some(id) = self.foreground else {
+            return Ok(false);
+        };
+        let Some(job) = self.jobs.get_mut(id) else {
+            self.foreground = None;
+            return Ok(true);
+        };
+        
+        if job.process.is_finished()? {
+            if let Some(code) = job.process.status().code() {
+                self.last_status = code;
+            }
+            self.jobs.remove(id);
+            self.release_foreground();
+            return Ok(true);
+        }
+        
+        if job.process.status() == ProcessStatus::Stopped {
+            job.reported = ProcessStatus::Stopped;
+            // What bash reports for a command stopped by Ctrl+Z
+            self.last_status = 128 + libc::SIGTSTP;
+            self.jobs.touch(id);
+            self.release_foreground();
+            if let Some(job) = self.jobs.get(id) {
+                let line = self.jobs.describe(job);
+                self.print_line("");
+                self.print_line(&line);
+            }
+            return Ok(true);
+        }
+        
+        Ok(false)
+    }
 
+    // Show what the persistent shell printed and notice when its command
+    // finished or the directory changed. Returns whether anything changed.
+    fn update_session(&mut self) -> io::Result<bool> {
+        let Some(shared) = self.session.clone() else {
+            return Ok(false);
+        };
+        let mut session = shared.borrow_mut();
+        let mut changed = false;
+        
+        let output = session.take_output()?;
+        if !output.is_empty() {
+            self.screen.feed(&output);
+            let replies = self.screen.take_replies();
+            if session.is_busy() && !replies.is_empty() {
+                session.process_mut().write_all(&replies)?;
+            }
+            changed = true;
+        }
+        
+        for event in session.take_events() {
+            match event {
+                SessionEvent::Finished(status) => {
+                    self.last_status = status;
+                    self.release_foreground();
+                }
+                SessionEvent::DirectoryChanged(dir) => {
+                    if self.update_current_dir(dir.clone()).is_err() {
+                        self.current_dir = dir;
+                    }
+                }
+                SessionEvent::JobsChanged(count) => self.session_jobs = count,
+            }
+            changed = true;
+        }
+        
+        // The shell itself went away (`exec`, a crash); the executor starts
+        // a new one for the next command
+        if session.is_finished()? {
+            let status = session.process().status().code().unwrap_or(0);
+            drop(session);
+            self.session = None;
+            self.last_status = status;
+            self.release_foreground();
+            self.print_line(&format!("[shell exited with status {}]", status));
+            changed = true;
+        }
+        
+        Ok(changed)
+    }
+
+    // Report background jobs whose status changed; finished ones are removed
+    fn update_background_jobs(&mut self) -> io::Result<bool> {
+        let mut changed = false;
+        for id in self.jobs.ids() {
+            let Some(job) = self.jobs.get_mut(id) else {
+                continue;
+            };
+            let finished = job.process.is_finished()?;
+            let status = job.process.status();
+            if !finished && status == job.reported {
+                continue;
+            }
+            job.reported = status;
+            
+            if let Some(job) = self.jobs.get(id) {
+                let line = self.jobs.describe(job);
+                self.print_line(&line);
+            }
+            if finished {
+                self.jobs.remove(id);
+            }
+            changed = true;
+        }
+        Ok(changed)
+    }
+
+    // Add the command that just finished to the history
+    fn finish_history_entry(&mut self) {
+        let Some((mut entry, started)) = self.pending_history.take() else {
+            return;
+        };
+        entry.exit_status = Some(self.last_status);
+        entry.duration = Some(started.elapsed());
+        self.last_duration = entry.duration;
+        // The command may have committed, staged or switched branches
+        self.git.refresh(&self.current_dir);
+        if let Err(e) = self.command_history.add(entry) {
+            self.print_error(&format!("Failed to save history: {}", e));
+        }
+    }
+
+    // Take the terminal back from the foreground job
+    fn release_foreground(&mut self) {
+        self.foreground = None;
+        
+        // Don't leave the screen in a state only the job understood
+        if self.screen.is_alternate() {
+            self.screen.feed(b"\x1b[?1049l");
+        }
+        self.screen.feed(b"\x1b[0m\x1b[?25h");
+        self.screen.finish_line();
+        self.scroll_to_bottom();
+    }
+
+    // Lines PageUp/PageDown move by
+    fn scroll_step(&self) -> usize {
+        self.config.scrollback.scroll_step.unwrap_or(self.height as usize / 2)
+    }
+    
     // Update current directory
     pub fn update_current_dir(&mut self, new_dir: PathBuf) -> io::Result<()> {
         // Attempt to change to the new directory
@@ -346,7 +1439,22 @@ impl Terminal {
     }
     
     // Generate a prompt with the actual path
-    pub fn get_prompt(&self) -> String {
+    pub fn get_prompt(&self) -> StyledText {
+        self.render_prompt(|prompt| Some(&prompt.left)).unwrap_or_default()
+    }
+    
+    // Shown before the second and later lines of multi-line input, like PS2
+    pub fn continuation_prompt(&self) -> StyledText {
+        self.render_prompt(|prompt| Some(&prompt.continuation)).unwrap_or_default()
+    }
+    
+    pub fn right_prompt(&self) -> Option<StyledText> {
+        self.render_prompt(|prompt| prompt.right.as_ref()).filter(|right| !right.is_empty())
+    }
+    
+    fn render_prompt(&self, template: impl Fn(&PromptConfig) -> Option<&PromptTemplate>) -> Option<StyledText> {
+        let template = template(&self.config.prompt)?;
+        
         // Get current user
         let username = env::var("USER").unwrap_or_else(|_| "user".to_string());
         
@@ -354,9 +1462,27 @@ impl Terminal {
         let hostname = hostname::get()
             .map(|h| h.to_string_lossy().to_string())
             .unwrap_or_else(|_| "host".to_string());
+        let home = dirs::home_dir();
         
-        // Format the prompt with current directory
-        format!("{}@{}({}): ", username, hostname, self.current_dir.display())
+        // In session mode the shell keeps the jobs
+        let jobs = if self.session.is_some() { self.session_jobs } else { self.jobs.len() };
+        let context = PromptContext {
+            user: &username,
+            host: &hostname,
+            cwd: &self.current_dir,
+            home: home.as_deref(),
+            status: self.last_status,
+            duration: self.last_duration,
+            jobs,
+            time: local_time(),
+            git: self.git.status(&self.current_dir).map(|git| git.format(&self.config.prompt.git_symbols)),
+            vi_mode: self.vi.as_ref().map_or("", |vi| vi.mode().name()),
+        };
+        let base = Attributes {
+            fg: self.theme.prompt,
+            ..Attributes::default()
+        };
+        Some(template.render(&context, base))
     }
     
     // Update the terminal after a command may have changed directory
@@ -0,0 +1,811 @@
+// Vi editing of the input line, as `set -o vi` gives in bash: insert,
+// normal and visual modes, motions and operators with counts, `.` to repeat
+// the last change and j/k to walk the history. Insert mode keeps the
+// keymap's bindings; everything else is handled here.
+use std::ops::Range;
+
+use crossterm::cursor::SetCursorStyle;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use super::editor::LineEditor;
+use super::keymap::Action;
+
+// Counts stop growing here, which is far more than an input line needs
+const MAX_COUNT: usize = 9_999;
+
+// Most text p and P put in one go, whatever the count
+const MAX_PUT: usize = 1 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ViMode {
+    // Every line starts out in insert mode
+    #[default]
+    Insert,
+    Normal,
+    Visual,
+}
+
+impl ViMode {
+    // What `{vi_mode}` shows in the prompt
+    pub fn name(self) -> &'static str {
+        match self {
+            ViMode::Insert => "insert",
+            ViMode::Normal => "normal",
+            ViMode::Visual => "visual",
+        }
+    }
+
+    // A bar while typing, a block otherwise
+    pub fn cursor_style(self) -> SetCursorStyle {
+        match self {
+            ViMode::Insert => SetCursorStyle::SteadyBar,
+            ViMode::Normal | ViMode::Visual => SetCursorStyle::SteadyBlock,
+        }
+    }
+}
+
+// What became of a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViResult {
+    Handled,
+    // For the terminal to carry out: running the line, history
+    Action(Action),
+    // Left to the keymap, as typing in insert mode is
+    Unhandled,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Delete,
+    Change,
+    Yank,
+}
+
+// f, F, t and T
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Find {
+    To,
+    ToBack,
+    Till,
+    TillBack,
+}
+
+impl Find {
+    // `,` repeats the last find the other way
+    fn reversed(self) -> Self {
+        match self {
+            Find::To => Find::ToBack,
+            Find::ToBack => Find::To,
+            Find::Till => Find::TillBack,
+            Find::TillBack => Find::Till,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Motion {
+    Left,
+    Right,
+    LineStart,
+    FirstNonBlank,
+    LineEnd,
+    // The flag is for WORDs, which only whitespace separates
+    WordForward(bool),
+    WordBackward(bool),
+    WordEnd(bool),
+    // The flag is set when `;` or `,` repeats it
+    Find(Find, char, bool),
+}
+
+// Keys that need the next character typed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Awaiting {
+    Find(Find),
+    Replace,
+}
+
+#[derive(Default)]
+pub struct ViEditor {
+    mode: ViMode,
+    count: Option<usize>,
+    // An operator waiting for its motion, with the count typed before it
+    operator: Option<(Operator, usize)>,
+    awaiting: Option<Awaiting>,
+    last_find: Option<(Find, char)>,
+    // Where the visual selection started, as a char index
+    anchor: usize,
+    // What d, c and y took, for p and P
+    register: String,
+    // Keys of the change being made, and of the last one made, for `.`
+    recording: Vec<KeyEvent>,
+    last_change: Vec<KeyEvent>,
+    replaying: bool,
+}
+
+// The input as chars, with the cursor and the bounds of its line as
+// indexes into them
+struct Line {
+    chars: Vec<char>,
+    cursor: usize,
+    start: usize,
+    end: usize,
+}
+
+impl Line {
+    fn of(editor: &LineEditor) -> Self {
+        let chars: Vec<char> = editor.text().chars().collect();
+        let cursor = editor.before_cursor().chars().count();
+        let start = chars[..cursor].iter().rposition(|&c| c == '\n').map_or(0, |i| i + 1);
+        let end = chars[cursor..].iter().position(|&c| c == '\n').map_or(chars.len(), |i| cursor + i);
+        Self { chars, cursor, start, end }
+    }
+
+    // The last place the cursor can rest outside of insert mode
+    fn last(&self) -> usize {
+        self.end.saturating_sub(1).max(self.start)
+    }
+}
+
+impl ViEditor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn mode(&self) -> ViMode {
+        self.mode
+    }
+
+    // Back to insert mode for a new line
+    pub fn reset(&mut self) {
+        self.mode = ViMode::Insert;
+        self.cancel();
+        self.recording.clear();
+    }
+
+    // The chars of the input visual mode has selected
+    pub fn selection(&self, editor: &LineEditor) -> Option<Range<usize>> {
+        if self.mode != ViMode::Visual {
+            return None;
+        }
+        let len = editor.text().chars().count();
+        let cursor = editor.before_cursor().chars().count();
+        let anchor = self.anchor.min(len);
+        Some(anchor.min(cursor)..(anchor.max(cursor) + 1).min(len))
+    }
+
+    // Keep the cursor on a character outside of insert mode, as after
+    // history recall leaves it past the end
+    pub fn clamp_cursor(&self, editor: &mut LineEditor) {
+        if self.mode == ViMode::Insert {
+            return;
+        }
+        let line = Line::of(editor);
+        if line.cursor > line.last() {
+            editor.move_to(offset(editor.text(), line.last()));
+        }
+    }
+
+    pub fn handle_key(&mut self, key: &KeyEvent, editor: &mut LineEditor) -> ViResult {
+        // A change is recorded from its first key until it's done, text
+        // typed in insert mode included
+        let recording = match self.mode {
+            ViMode::Insert => !self.recording.is_empty(),
+            _ => true,
+        };
+        if recording && !self.replaying {
+            if self.is_idle() {
+                self.recording.clear();
+            }
+            self.recording.push(*key);
+        }
+
+        if self.mode != ViMode::Insert {
+            return self.command(key, editor);
+        }
+        if key.code != KeyCode::Esc {
+            return ViResult::Unhandled;
+        }
+        // Leaving insert mode steps back onto the last character typed
+        self.mode = ViMode::Normal;
+        self.finish_change();
+        let line = Line::of(editor);
+        if line.cursor > line.start {
+            editor.move_to(offset(editor.text(), line.cursor - 1));
+        }
+        ViResult::Handled
+    }
+
+    fn is_idle(&self) -> bool {
+        self.mode == ViMode::Normal && self.count.is_none() && self.operator.is_none() && self.awaiting.is_none()
+    }
+
+    fn cancel(&mut self) {
+        self.count = None;
+        self.operator = None;
+        self.awaiting = None;
+    }
+
+    fn finish_change(&mut self) {
+        if !self.replaying {
+            self.last_change = std::mem::take(&mut self.recording);
+        }
+    }
+
+    // A key in normal or visual mode
+    fn command(&mut self, key: &KeyEvent, editor: &mut LineEditor) -> ViResult {
+        if let Some(awaiting) = self.awaiting.take() {
+            match (awaiting, key.code) {
+                (Awaiting::Find(find), KeyCode::Char(c)) => {
+                    self.last_find = Some((find, c));
+                    self.apply_motion(Motion::Find(find, c, false), editor);
+                }
+                (Awaiting::Replace, KeyCode::Char(c)) => self.replace_chars(c, editor),
+                _ => self.cancel(),
+            }
+            self.clamp_cursor(editor);
+            return ViResult::Handled;
+        }
+        // Ctrl and Alt keys keep their usual bindings
+        if key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) {
+            self.cancel();
+            return ViResult::Unhandled;
+        }
+
+        if let Some(motion) = motion(key.code) {
+            if !(key.code == KeyCode::Char('0') && self.count.is_some()) {
+                self.apply_motion(motion, editor);
+                self.clamp_cursor(editor);
+                return ViResult::Handled;
+            }
+        }
+
+        let action = match key.code {
+            KeyCode::Char(c @ '0'..='9') => {
+                let digit = c as usize - '0' as usize;
+                self.count = Some((self.count.unwrap_or(0) * 10 + digit).min(MAX_COUNT));
+                return ViResult::Handled;
+            }
+            KeyCode::Enter => Some(Action::AcceptLine),
+            KeyCode::Char('j') | KeyCode::Down => Some(Action::HistoryNext),
+            KeyCode::Char('k') | KeyCode::Up => Some(Action::HistoryPrev),
+            KeyCode::Tab => Some(Action::Complete),
+            _ => None,
+        };
+        if let Some(action) = action {
+            self.cancel();
+            self.mode = ViMode::Normal;
+            return ViResult::Action(action);
+        }
+
+        match key.code {
+            KeyCode::Esc => {
+                self.mode = ViMode::Normal;
+                self.cancel();
+            }
+            KeyCode::Char('v') => {
+                self.mode = match self.mode {
+                    ViMode::Visual => ViMode::Normal,
+                    _ => ViMode::Visual,
+                };
+                self.anchor = Line::of(editor).cursor;
+                self.cancel();
+            }
+            KeyCode::Char('d') => self.operator_key(Operator::Delete, editor),
+            KeyCode::Char('c') => self.operator_key(Operator::Change, editor),
+            KeyCode::Char('y') => self.operator_key(Operator::Yank, editor),
+            KeyCode::Char('x') | KeyCode::Delete => self.shorthand(Operator::Delete, 'l', editor),
+            KeyCode::Char('X') => self.shorthand(Operator::Delete, 'h', editor),
+            KeyCode::Char('D') => self.shorthand(Operator::Delete, '$', editor),
+            KeyCode::Char('s') => self.shorthand(Operator::Change, 'l', editor),
+            KeyCode::Char('S') => self.shorthand(Operator::Change, 'c', editor),
+            KeyCode::Char('C') => self.shorthand(Operator::Change, '$', editor),
+            KeyCode::Char('Y') => self.shorthand(Operator::Yank, 'y', editor),
+            KeyCode::Char(c @ ('f' | 'F' | 't' | 'T')) => {
+                let find = match c {
+                    'f' => Find::To,
+                    'F' => Find::ToBack,
+                    't' => Find::Till,
+                    _ => Find::TillBack,
+                };
+                self.awaiting = Some(Awaiting::Find(find));
+            }
+            KeyCode::Char(c @ (';' | ',')) => match self.last_find {
+                Some((find, target)) => {
+                    let find = if c == ',' { find.reversed() } else { find };
+                    self.apply_motion(Motion::Find(find, target, true), editor);
+                }
+                None => self.cancel(),
+            },
+            KeyCode::Char(c @ ('i' | 'a' | 'I' | 'A' | 'o' | 'O')) => self.insert(c, editor),
+            KeyCode::Char(c @ ('p' | 'P')) => self.put(c == 'p', editor),
+            KeyCode::Char('r') if self.operator.is_none() => self.awaiting = Some(Awaiting::Replace),
+            KeyCode::Char('~') => self.toggle_case(editor),
+            KeyCode::Char('u') => {
+                self.cancel();
+                editor.undo();
+            }
+            KeyCode::Char('.') => self.repeat(editor),
+            _ => self.cancel(),
+        }
+        self.clamp_cursor(editor);
+        ViResult::Handled
+    }
+
+    fn operator_key(&mut self, operator: Operator, editor: &mut LineEditor) {
+        if self.mode == ViMode::Visual {
+            let selection = self.selection(editor).unwrap_or_default();
+            self.mode = ViMode::Normal;
+            self.operate(operator, selection, editor);
+            return;
+        }
+        match self.operator.take() {
+            // dd, cc and yy work on the whole line
+            Some((pending, _)) if pending == operator => {
+                self.count = None;
+                let line = Line::of(editor);
+                let mut range = line.start..line.end;
+                if operator == Operator::Delete {
+                    // Taking the line break along, when there's one
+                    if line.end < line.chars.len() {
+                        range.end += 1;
+                    } else if line.start > 0 {
+                        range.start -= 1;
+                    }
+                }
+                self.operate(operator, range, editor);
+            }
+            Some(_) => self.cancel(),
+            None => self.operator = Some((operator, self.count.take().unwrap_or(1))),
+        }
+    }
+
+    // x, D, s, C and friends are an operator and a motion in one key
+    fn shorthand(&mut self, operator: Operator, motion: char, editor: &mut LineEditor) {
+        if self.mode == ViMode::Visual {
+            return self.operator_key(operator, editor);
+        }
+        self.operator = Some((operator, 1));
+        let key = KeyEvent::new(KeyCode::Char(motion), KeyModifiers::NONE);
+        self.command(&key, editor);
+    }
+
+    fn apply_motion(&mut self, motion: Motion, editor: &mut LineEditor) {
+        let operator = self.operator.take();
+        let operator_count = operator.map_or(1, |(_, count)| count);
+        let count = self.count.take().unwrap_or(1).saturating_mul(operator_count).min(MAX_COUNT);
+        let line = Line::of(editor);
+        let Some((target, inclusive)) = target(&line, motion, count, operator.map(|(operator, _)| operator)) else {
+            return;
+        };
+        match operator {
+            Some((operator, _)) => {
+                let start = line.cursor.min(target);
+                let end = line.cursor.max(target) + usize::from(inclusive);
+                self.operate(operator, start..end.min(line.chars.len()), editor);
+            }
+            None => editor.move_to(offset(editor.text(), target)),
+        }
+    }
+
+    fn operate(&mut self, operator: Operator, range: Range<usize>, editor: &mut LineEditor) {
+        let start = offset(editor.text(), range.start);
+        let end = offset(editor.text(), range.end);
+        self.register = editor.text()[start..end].to_string();
+        match operator {
+            Operator::Yank => editor.move_to(start),
+            Operator::Delete => {
+                editor.delete_range(start, end);
+                self.finish_change();
+            }
+            // The rest of the change is typed in insert mode
+            Operator::Change => {
+                editor.delete_range(start, end);
+                self.mode = ViMode::Insert;
+            }
+        }
+    }
+
+    fn insert(&mut self, key: char, editor: &mut LineEditor) {
+        self.cancel();
+        let line = Line::of(editor);
+        let position = match key {
+            'a' if line.end > line.start => line.cursor + 1,
+            'I' => first_non_blank(&line),
+            'A' | 'o' => line.end,
+            'O' => line.start,
+            _ => line.cursor,
+        };
+        editor.move_to(offset(editor.text(), position));
+        match key {
+            'o' => editor.insert_str("\n"),
+            'O' => {
+                editor.insert_str("\n");
+                editor.move_to(offset(editor.text(), position));
+            }
+            _ => {}
+        }
+        self.mode = ViMode::Insert;
+    }
+
+    // p puts the register after the cursor and P before it, leaving the
+    // cursor on the last character put; in visual mode it replaces the
+    // selection
+    fn put(&mut self, after: bool, editor: &mut LineEditor) {
+        let count = self.count.take().unwrap_or(1);
+        self.cancel();
+        if self.register.is_empty() {
+            return;
+        }
+        let text = self.register.repeat(count.min(MAX_PUT / self.register.len()).max(1));
+        let line = Line::of(editor);
+        if let Some(selection) = self.selection(editor) {
+            self.mode = ViMode::Normal;
+            let start = offset(editor.text(), selection.start);
+            let end = offset(editor.text(), selection.end);
+            editor.replace_range(start, end, &text);
+        } else {
+            let position = if after && line.end > line.start { line.cursor + 1 } else { line.cursor };
+            editor.move_to(offset(editor.text(), position));
+            editor.insert_str(&text);
+        }
+        let end = Line::of(editor).cursor;
+        editor.move_to(offset(editor.text(), end.saturating_sub(1)));
+        self.finish_change();
+    }
+
+    fn replace_chars(&mut self, c: char, editor: &mut LineEditor) {
+        let count = self.count.take().unwrap_or(1);
+        let line = Line::of(editor);
+        if count > line.end - line.cursor {
+            return;
+        }
+        let start = offset(editor.text(), line.cursor);
+        let end = offset(editor.text(), line.cursor + count);
+        editor.replace_range(start, end, &c.to_string().repeat(count));
+        editor.move_to(offset(editor.text(), line.cursor + count - 1));
+        self.finish_change();
+    }
+
+    fn toggle_case(&mut self, editor: &mut LineEditor) {
+        let count = self.count.take().unwrap_or(1);
+        self.cancel();
+        let line = Line::of(editor);
+        let range = match self.selection(editor) {
+            Some(selection) => selection,
+            None => line.cursor..(line.cursor + count).min(line.end),
+        };
+        let toggled: String = line.chars[range.clone()]
+            .iter()
+            .map(|&c| {
+                if c.is_uppercase() {
+                    c.to_lowercase().to_string()
+                } else {
+                    c.to_uppercase().to_string()
+                }
+            })
+            .collect();
+        let start = offset(editor.text(), range.start);
+        let end = offset(editor.text(), range.end);
+        editor.replace_range(start, end, &toggled);
+        if self.mode == ViMode::Visual {
+            self.mode = ViMode::Normal;
+            editor.move_to(start);
+        }
+        self.finish_change();
+    }
+
+    // Play the keys of the last change again; a count replaces the one it
+    // was made with
+    fn repeat(&mut self, editor: &mut LineEditor) {
+        let count = self.count.take();
+        self.cancel();
+        self.recording.clear();
+        if self.replaying {
+            return;
+        }
+        let mut keys = self.last_change.clone();
+        if let Some(count) = count {
+            let digits = keys
+                .iter()
+                .take_while(|key| matches!(key.code, KeyCode::Char(c) if c.is_ascii_digit()))
+                .count();
+            let typed = count.to_string().chars().map(|c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE)).collect::<Vec<_>>();
+            keys.splice(..digits, typed);
+        }
+        self.replaying = true;
+        for key in &keys {
+            if self.handle_key(key, editor) == ViResult::Unhandled {
+                editor.handle_key(key);
+            }
+        }
+        self.replaying = false;
+    }
+}
+
+fn motion(code: KeyCode) -> Option<Motion> {
+    Some(match code {
+        KeyCode::Char('h') | KeyCode::Left | KeyCode::Backspace => Motion::Left,
+        KeyCode::Char('l') | KeyCode::Right | KeyCode::Char(' ') => Motion::Right,
+        KeyCode::Char('0') | KeyCode::Home => Motion::LineStart,
+        KeyCode::Char('^') => Motion::FirstNonBlank,
+        KeyCode::Char('$') | KeyCode::End => Motion::LineEnd,
+        KeyCode::Char('w') => Motion::WordForward(false),
+        KeyCode::Char('W') => Motion::WordForward(true),
+        KeyCode::Char('b') => Motion::WordBackward(false),
+        KeyCode::Char('B') => Motion::WordBackward(true),
+        KeyCode::Char('e') => Motion::WordEnd(false),
+        KeyCode::Char('E') => Motion::WordEnd(true),
+        _ => return None,
+    })
+}
+
+// Where a motion goes, and whether an operator takes the character there
+// too
+fn target(line: &Line, motion: Motion, count: usize, operator: Option<Operator>) -> Option<(usize, bool)> {
+    let chars = &line.chars;
+    let cursor = line.cursor;
+    Some(match motion {
+        Motion::Left => (cursor.saturating_sub(count).max(line.start), false),
+        Motion::Right => ((cursor + count).min(line.end), false),
+        Motion::LineStart => (line.start, false),
+        Motion::FirstNonBlank => (first_non_blank(line), false),
+        Motion::LineEnd => (line.last(), line.end > line.start),
+        // cw changes to the end of the word, not up to the next one
+        Motion::WordForward(big) if operator == Some(Operator::Change) && chars.get(cursor).is_some_and(|c| !c.is_whitespace()) => {
+            let mut end = cursor;
+            let word = class(chars[end], big);
+            while end + 1 < line.end && class(chars[end + 1], big) == word {
+                end += 1;
+            }
+            ((1..count).fold(end, |i, _| word_end(chars, i, big)), true)
+        }
+        Motion::WordForward(big) => {
+            let next = (0..count).fold(cursor, |i, _| word_start(chars, i, big));
+            // An operator stops at the end of the line
+            match operator {
+                Some(_) if cursor < line.end => (next.min(line.end), false),
+                _ => (next, false),
+            }
+        }
+        Motion::WordBackward(big) => ((0..count).fold(cursor, |i, _| prev_word_start(chars, i, big)), false),
+        Motion::WordEnd(big) => ((0..count).fold(cursor, |i, _| word_end(chars, i, big)), true),
+        Motion::Find(find, c, repeat) => {
+            let inclusive = matches!(find, Find::To | Find::Till);
+            (find_char(line, find, c, count, repeat)?, inclusive)
+        }
+    })
+}
+
+fn first_non_blank(line: &Line) -> usize {
+    line.chars[line.start..line.end]
+        .iter()
+        .position(|c| !c.is_whitespace())
+        .map_or(line.last(), |i| line.start + i)
+}
+
+// Blanks, word characters and punctuation; a WORD is anything but blanks
+fn class(c: char, big: bool) -> u8 {
+    if c.is_whitespace() {
+        0
+    } else if big || c.is_alphanumeric() || c == '_' {
+        1
+    } else {
+        2
+    }
+}
+
+fn word_start(chars: &[char], mut i: usize, big: bool) -> usize {
+    if let Some(&c) = chars.get(i) {
+        let start = class(c, big);
+        while i < chars.len() && start != 0 && class(chars[i], big) == start {
+            i += 1;
+        }
+    }
+    while i < chars.len() && class(chars[i], big) == 0 {
+        i += 1;
+    }
+    i.min(chars.len())
+}
+
+fn prev_word_start(chars: &[char], mut i: usize, big: bool) -> usize {
+    while i > 0 && class(chars[i - 1], big) == 0 {
+        i -= 1;
+    }
+    if i == 0 {
+        return 0;
+    }
+    let class_before = class(chars[i - 1], big);
+    while i > 0 && class(chars[i - 1], big) == class_before {
+        i -= 1;
+    }
+    i
+}
+
+fn word_end(chars: &[char], i: usize, big: bool) -> usize {
+    let mut j = i + 1;
+    while j < chars.len() && class(chars[j], big) == 0 {
+        j += 1;
+    }
+    if j >= chars.len() {
+        return chars.len().saturating_sub(1).max(i.min(chars.len()));
+    }
+    let end = class(chars[j], big);
+    while j + 1 < chars.len() && class(chars[j + 1], big) == end {
+        j += 1;
+    }
+    j
+}
+
+// The count-th `c` on the line either side of the cursor. A repeated t or
+// T looks past the character it stopped next to.
+fn find_char(line: &Line, find: Find, c: char, count: usize, repeat: bool) -> Option<usize> {
+    let skip = usize::from(repeat && matches!(find, Find::Till | Find::TillBack));
+    let nth = count.max(1) - 1;
+    match find {
+        Find::To | Find::Till => {
+            let found = (line.cursor + 1 + skip..line.end).filter(|&i| line.chars[i] == c).nth(nth)?;
+            Some(if find == Find::Till { found - 1 } else { found })
+        }
+        Find::ToBack | Find::TillBack => {
+            let found = (line.start..line.cursor.saturating_sub(skip)).rev().filter(|&i| line.chars[i] == c).nth(nth)?;
+            Some(if find == Find::TillBack { found + 1 } else { found })
+        }
+    }
+}
+
+// The byte offset of a char index
+fn offset(text: &str, index: usize) -> usize {
+    text.char_indices().nth(index).map_or(text.len(), |(i, _)| i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Type `keys` into a line holding `text` in normal mode, with the
+    // cursor at `cursor`
+    fn run(text: &str, cursor: usize, keys: &str) -> (String, usize, ViMode) {
+        let mut editor = LineEditor::new();
+        editor.set_text(text);
+        editor.move_to(offset(text, cursor));
+        let mut vi = ViEditor::new();
+        vi.mode = ViMode::Normal;
+        let mut chars = keys.chars();
+        while let Some(c) = chars.next() {
+            let code = match c {
+                '<' => {
+                    let name: String = chars.by_ref().take_while(|&c| c != '>').collect();
+                    match name.as_str() {
+                        "esc" => KeyCode::Esc,
+                        _ => panic!("unknown key {}", name),
+                    }
+                }
+                c => KeyCode::Char(c),
+            };
+            let key = KeyEvent::new(code, KeyModifiers::NONE);
+            if vi.handle_key(&key, &mut editor) == ViResult::Unhandled {
+                editor.handle_key(&key);
+            }
+        }
+        (editor.text().to_string(), editor.before_cursor().chars().count(), vi.mode())
+    }
+
+    fn text(text: &str, cursor: usize, keys: &str) -> String {
+        run(text, cursor, keys).0
+    }
+
+    #[test]
+    fn motions() {
+        let line = "git commit -m 'fix it'";
+        assert_eq!(run(line, 0, "w").1, 4);
+        assert_eq!(run(line, 0, "3w").1, 12);
+        assert_eq!(run(line, 0, "3W").1, 14);
+        assert_eq!(run(line, 0, "e").1, 2);
+        assert_eq!(run(line, 14, "b").1, 12);
+        assert_eq!(run(line, 14, "B").1, 11);
+        assert_eq!(run(line, 5, "0").1, 0);
+        assert_eq!(run(line, 5, "$").1, 21);
+        assert_eq!(run(line, 0, "fm").1, 6);
+        assert_eq!(run(line, 0, "3fm").1, 12);
+        assert_eq!(run(line, 0, "tm").1, 5);
+        assert_eq!(run(line, 0, "fm;").1, 7);
+        assert_eq!(run(line, 21, "Fm").1, 12);
+        assert_eq!(run(line, 0, "fm;,").1, 6);
+        assert_eq!(run(line, 0, "fz").1, 0);
+        assert_eq!(run("  ls", 3, "^").1, 2);
+    }
+
+    #[test]
+    fn operators_with_counts() {
+        let line = "one two three four";
+        assert_eq!(text(line, 0, "dw"), "two three four");
+        assert_eq!(text(line, 0, "2dw"), "three four");
+        assert_eq!(text(line, 0, "d2w"), "three four");
+        assert_eq!(text(line, 4, "d$"), "one ");
+        assert_eq!(text(line, 4, "D"), "one ");
+        assert_eq!(text(line, 4, "d0"), "two three four");
+        assert_eq!(text(line, 0, "dtf"), "four");
+        assert_eq!(text(line, 0, "de"), " two three four");
+        assert_eq!(text(line, 0, "3x"), " two three four");
+        assert_eq!(text(line, 4, "dd"), "");
+        assert_eq!(run(line, 0, "cwsix<esc>"), ("six two three four".to_string(), 2, ViMode::Normal));
+        assert_eq!(text(line, 4, "c2wten<esc>"), "one ten four");
+        assert_eq!(text(line, 0, "ywP"), "one one two three four");
+        assert_eq!(text(line, 0, "dwwP"), "two one three four");
+        assert_eq!(text(line, 0, "2rx"), "xxe two three four");
+        assert_eq!(text(line, 0, "3~"), "ONE two three four");
+        assert_eq!(text(line, 0, "dwu"), line);
+        assert_eq!(text("a\nb\nc", 2, "dd"), "a\nc");
+    }
+
+    #[test]
+    fn huge_counts_are_capped() {
+        let line = "one two three";
+        let huge = "99999999999999999999999";
+        assert_eq!(text(line, 0, &format!("{}x", huge)), "");
+        assert_eq!(text(line, 4, &format!("{}dw", huge)), "one ");
+        assert_eq!(text(line, 0, &format!("{}d{}w", huge, huge)), "");
+        assert_eq!(text(line, 0, &format!("{}rx", huge)), line);
+        assert_eq!(text(line, 0, &format!("{}~", huge)), "ONE TWO THREE");
+        assert_eq!(run(line, 0, &format!("{}l", huge)).1, 12);
+        let put = text("ab", 0, &format!("yl{}p", huge));
+        assert_eq!(put.len(), MAX_COUNT + 2);
+        assert_eq!(text("ab", 0, &format!("x{}.", huge)), "");
+    }
+
+    #[test]
+    fn insert_commands() {
+        assert_eq!(run("ls", 1, "a -l<esc>"), ("ls -l".to_string(), 4, ViMode::Normal));
+        assert_eq!(text("ls", 1, "Isudo <esc>"), "sudo ls");
+        assert_eq!(text("ls", 0, "A -a<esc>"), "ls -a");
+        assert_eq!(text("ls", 0, "ocd<esc>"), "ls\ncd");
+        assert_eq!(run("ls", 0, "i"), ("ls".to_string(), 0, ViMode::Insert));
+    }
+
+    #[test]
+    fn dot_repeats_the_last_change() {
+        let line = "a b c d e f";
+        assert_eq!(text(line, 0, "dw.."), "d e f");
+        assert_eq!(text(line, 0, "dw3."), "e f");
+        assert_eq!(text(line, 0, "x.w."), "b  d e f");
+        assert_eq!(text("x y", 0, "cwz<esc>w."), "z z");
+        assert_eq!(text("ls", 0, "A -l<esc>."), "ls -l -l");
+        // Motions and yanks aren't changes
+        assert_eq!(text(line, 0, "xwyw."), "  c d e f");
+    }
+
+    #[test]
+    fn visual_mode() {
+        let line = "echo hello world";
+        assert_eq!(run(line, 5, "ved"), ("echo  world".to_string(), 5, ViMode::Normal));
+        assert_eq!(text(line, 5, "vecbye<esc>"), "echo bye world");
+        assert_eq!(text(line, 0, "vey$p"), "echo hello worldecho");
+        assert_eq!(text(line, 5, "ve~"), "echo HELLO world");
+        assert_eq!(run(line, 5, "vev").2, ViMode::Normal);
+
+        let mut editor = LineEditor::new();
+        editor.set_text(line);
+        editor.move_to(5);
+        let mut vi = ViEditor::new();
+        vi.handle_key(&KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE), &mut editor);
+        for c in "lve".chars() {
+            vi.handle_key(&KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE), &mut editor);
+        }
+        assert_eq!(vi.selection(&editor), Some(5..10));
+    }
+
+    #[test]
+    fn history_and_accepting_go_to_the_terminal() {
+        let mut editor = LineEditor::new();
+        editor.set_text("ls");
+        let mut vi = ViEditor::new();
+        let mut key = |code| vi.handle_key(&KeyEvent::new(code, KeyModifiers::NONE), &mut editor);
+        assert_eq!(key(KeyCode::Char('k')), ViResult::Unhandled);
+        assert_eq!(key(KeyCode::Esc), ViResult::Handled);
+        assert_eq!(key(KeyCode::Char('k')), ViResult::Action(Action::HistoryPrev));
+        assert_eq!(key(KeyCode::Char('j')), ViResult::Action(Action::HistoryNext));
+        assert_eq!(key(KeyCode::Enter), ViResult::Action(Action::AcceptLine));
+        let ctrl_r = KeyEvent::new(KeyCode::Char('r'), KeyModifiers::CONTROL);
+        assert_eq!(vi.handle_key(&ctrl_r, &mut editor), ViResult::Unhandled);
+    }
+}
@@ -0,0 +1,144 @@
+// How many colors the real terminal can show, and bringing colors down to
+// that: truecolor to the nearest of the 256-color palette, or either to the
+// nearest of the 16 ANSI colors
+use std::env;
+
+use serde::Deserialize;
+
+use crate::screen::cell::CellColor;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum ColorDepth {
+    #[serde(rename = "16")]
+    Ansi16,
+    #[serde(rename = "256")]
+    Ansi256,
+    #[serde(rename = "truecolor")]
+    TrueColor,
+}
+
+// Levels of the 6x6x6 color cube at 16-231 of the 256-color palette
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+// xterm's defaults for the 16 ANSI colors, which is as close as we can
+// guess at what the terminal shows for them
+const ANSI_RGB: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+impl ColorDepth {
+    pub fn from_env() -> Self {
+        Self::detect(env::var("COLORTERM").ok().as_deref(), env::var("TERM").ok().as_deref())
+    }
+
+    // $COLORTERM announces truecolor; otherwise $TERM names the palette
+    // size, as in `xterm-256color` or `xterm-direct`
+    pub fn detect(colorterm: Option<&str>, term: Option<&str>) -> Self {
+        if matches!(colorterm, Some("truecolor" | "24bit")) {
+            return ColorDepth::TrueColor;
+        }
+        match term {
+            Some(term) if term.ends_with("-direct") => ColorDepth::TrueColor,
+            Some(term) if term.contains("256color") => ColorDepth::Ansi256,
+            _ => ColorDepth::Ansi16,
+        }
+    }
+
+    pub fn downgrade(self, color: CellColor) -> CellColor {
+        match (self, color) {
+            (ColorDepth::TrueColor, color) | (_, color @ CellColor::Default) => color,
+            (ColorDepth::Ansi256, CellColor::Rgb(r, g, b)) => CellColor::Indexed(nearest_256(r, g, b)),
+            (ColorDepth::Ansi256, color) => color,
+            (ColorDepth::Ansi16, CellColor::Indexed(n)) if n < 16 => color,
+            (ColorDepth::Ansi16, CellColor::Indexed(n)) => {
+                let (r, g, b) = palette_rgb(n);
+                CellColor::Indexed(nearest_16(r, g, b))
+            }
+            (ColorDepth::Ansi16, CellColor::Rgb(r, g, b)) => CellColor::Indexed(nearest_16(r, g, b)),
+        }
+    }
+}
+
+fn distance((r1, g1, b1): (u8, u8, u8), (r2, g2, b2): (u8, u8, u8)) -> u32 {
+    let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+    d(r1, r2) + d(g1, g2) + d(b1, b2)
+}
+
+// What a color of the 256-color palette looks like
+fn palette_rgb(n: u8) -> (u8, u8, u8) {
+    match n {
+        0..=15 => ANSI_RGB[n as usize],
+        16..=231 => {
+            let n = n - 16;
+            (CUBE_LEVELS[(n / 36) as usize], CUBE_LEVELS[(n / 6 % 6) as usize], CUBE_LEVELS[(n % 6) as usize])
+        }
+        _ => {
+            let level = 8 + 10 * (n - 232);
+            (level, level, level)
+        }
+    }
+}
+
+// The closest of the cube and the gray ramp, leaving out the 16 colors the
+// terminal may have changed
+fn nearest_256(r: u8, g: u8, b: u8) -> u8 {
+    let level = |c: u8| {
+        (0..CUBE_LEVELS.len())
+            .min_by_key(|&i| (CUBE_LEVELS[i] as i32 - c as i32).abs())
+            .unwrap_or(0) as u8
+    };
+    let cube = 16 + 36 * level(r) + 6 * level(g) + level(b);
+    let average = (r as u32 + g as u32 + b as u32) / 3;
+    let gray = 232 + ((average.saturating_sub(3)) / 10).min(23) as u8;
+    [cube, gray]
+        .into_iter()
+        .min_by_key(|&n| distance(palette_rgb(n), (r, g, b)))
+        .unwrap_or(cube)
+}
+
+fn nearest_16(r: u8, g: u8, b: u8) -> u8 {
+    (0..16u8).min_by_key(|&n| distance(ANSI_RGB[n as usize], (r, g, b))).unwrap_or(7)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_depth_from_the_environment() {
+        assert_eq!(ColorDepth::detect(Some("truecolor"), Some("xterm-256color")), ColorDepth::TrueColor);
+        assert_eq!(ColorDepth::detect(None, Some("xterm-direct")), ColorDepth::TrueColor);
+        assert_eq!(ColorDepth::detect(None, Some("screen-256color")), ColorDepth::Ansi256);
+        assert_eq!(ColorDepth::detect(Some(""), Some("xterm")), ColorDepth::Ansi16);
+        assert_eq!(ColorDepth::detect(None, None), ColorDepth::Ansi16);
+    }
+
+    #[test]
+    fn downgrades_to_the_nearest_color() {
+        let rgb = CellColor::Rgb;
+        assert_eq!(ColorDepth::TrueColor.downgrade(rgb(1, 2, 3)), rgb(1, 2, 3));
+        assert_eq!(ColorDepth::Ansi256.downgrade(rgb(255, 0, 0)), CellColor::Indexed(196));
+        assert_eq!(ColorDepth::Ansi256.downgrade(rgb(0x80, 0x80, 0x80)), CellColor::Indexed(244));
+        assert_eq!(ColorDepth::Ansi256.downgrade(rgb(0xff, 0x87, 0x00)), CellColor::Indexed(208));
+        assert_eq!(ColorDepth::Ansi256.downgrade(CellColor::Indexed(3)), CellColor::Indexed(3));
+        assert_eq!(ColorDepth::Ansi16.downgrade(rgb(250, 10, 10)), CellColor::Indexed(9));
+        assert_eq!(ColorDepth::Ansi16.downgrade(CellColor::Indexed(28)), CellColor::Indexed(2));
+        assert_eq!(ColorDepth::Ansi16.downgrade(CellColor::Indexed(12)), CellColor::Indexed(12));
+        assert_eq!(ColorDepth::Ansi16.downgrade(CellColor::Default), CellColor::Default);
+    }
+}
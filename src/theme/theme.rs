@@ -0,0 +1,263 @@
+// Colors of everything drawn: named slots for what the terminal draws
+// itself and the 16 ANSI colors child output asks for. A theme is one of the
+// built-in ones or a file in `~/.config/rust-terminal-emulator/themes`, with
+// the `[theme]` section of the config file on top.
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Deserializer};
+
+use super::depth::ColorDepth;
+use crate::config::config::parse_toml;
+use crate::prompt::prompt::parse_color;
+use crate::screen::cell::CellColor;
+
+const BUILTIN_THEMES: &[(&str, &str)] = &[
+    ("default", ""),
+    ("dracula", include_str!("themes/dracula.toml")),
+    ("gruvbox-dark", include_str!("themes/gruvbox-dark.toml")),
+    ("nord", include_str!("themes/nord.toml")),
+    ("solarized-dark", include_str!("themes/solarized-dark.toml")),
+];
+
+// How deep themes may build on one another before it's taken for a loop
+const MAX_INHERITANCE: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Theme {
+    // Text of the prompt without a color of its own
+    pub prompt: CellColor,
+    // Messages about what went wrong, and unknown commands as they're typed
+    pub error: CellColor,
+    // Behind the vi selection and the picked completion
+    pub selection: CellColor,
+    // Behind search matches
+    pub search_match: CellColor,
+    // Labels of the search bars at the bottom
+    pub status_bar: CellColor,
+    // The autosuggestion after the cursor
+    pub suggestion: CellColor,
+    // Descriptions in the completion menu
+    pub description: CellColor,
+    // What black, red, ... and bright white look like
+    pub ansi: [CellColor; 16],
+    pub depth: ColorDepth,
+}
+
+// The `[theme]` section of the config file, and theme files, which have the
+// same keys. Every color is optional and replaces the one of the theme
+// `name` names; in a theme file that's the theme it builds on.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThemeConfig {
+    pub name: Option<String>,
+    // `16`, `256` or `truecolor`; worked out from $COLORTERM and $TERM
+    // when unset
+    pub colors: Option<ColorDepth>,
+    #[serde(deserialize_with = "color")]
+    pub prompt: Option<CellColor>,
+    #[serde(deserialize_with = "color")]
+    pub error: Option<CellColor>,
+    #[serde(deserialize_with = "color")]
+    pub selection: Option<CellColor>,
+    #[serde(deserialize_with = "color")]
+    pub search_match: Option<CellColor>,
+    #[serde(deserialize_with = "color")]
+    pub status_bar: Option<CellColor>,
+    #[serde(deserialize_with = "color")]
+    pub suggestion: Option<CellColor>,
+    #[serde(deserialize_with = "color")]
+    pub description: Option<CellColor>,
+    // Black, red, green, yellow, blue, magenta, cyan and white, then their
+    // bright forms; as many as are given
+    #[serde(deserialize_with = "ansi")]
+    pub ansi: Vec<CellColor>,
+}
+
+impl Default for Theme {
+    // Plain colors the terminal's own palette decides the look of
+    fn default() -> Self {
+        Self {
+            prompt: CellColor::Indexed(2),
+            error: CellColor::Indexed(1),
+            selection: CellColor::Indexed(8),
+            search_match: CellColor::Indexed(3),
+            status_bar: CellColor::Indexed(2),
+            suggestion: CellColor::Indexed(8),
+            description: CellColor::Indexed(8),
+            ansi: std::array::from_fn(|n| CellColor::Indexed(n as u8)),
+            depth: ColorDepth::TrueColor,
+        }
+    }
+}
+
+impl Theme {
+    // Names of the themes that come built in
+    pub fn builtin_names() -> impl Iterator<Item = &'static str> {
+        BUILTIN_THEMES.iter().map(|(name, _)| *name)
+    }
+
+    // `~/.config/rust-terminal-emulator/themes` on Linux
+    pub fn user_dir() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("rust-terminal-emulator").join("themes"))
+    }
+
+    // The theme `config` describes, drawn with as many colors as the
+    // terminal has
+    pub fn load(config: &ThemeConfig) -> Result<Self, String> {
+        let mut theme = Self::resolve(config, 0)?;
+        theme.depth = config.colors.unwrap_or_else(ColorDepth::from_env);
+        Ok(theme)
+    }
+
+    fn resolve(config: &ThemeConfig, depth: usize) -> Result<Self, String> {
+        let mut theme = match &config.name {
+            None => Self::default(),
+            Some(_) if depth >= MAX_INHERITANCE => return Err("themes build on each other in a loop".to_string()),
+            Some(name) => Self::resolve(&find(name)?, depth + 1)?,
+        };
+        theme.apply(config);
+        Ok(theme)
+    }
+
+    fn apply(&mut self, config: &ThemeConfig) {
+        let slots = [
+            (&mut self.prompt, config.prompt),
+            (&mut self.error, config.error),
+            (&mut self.selection, config.selection),
+            (&mut self.search_match, config.search_match),
+            (&mut self.status_bar, config.status_bar),
+            (&mut self.suggestion, config.suggestion),
+            (&mut self.description, config.description),
+        ];
+        for (slot, color) in slots {
+            if let Some(color) = color {
+                *slot = color;
+            }
+        }
+        for (slot, color) in self.ansi.iter_mut().zip(&config.ansi) {
+            *slot = *color;
+        }
+    }
+
+    // What to draw for a color a cell or prompt asks for
+    pub fn color(&self, color: CellColor) -> CellColor {
+        match color {
+            // With only 16 colors, the terminal's own palette is as near as
+            // we can get to the theme's
+            CellColor::Indexed(n) if n < 16 && self.depth != ColorDepth::Ansi16 => {
+                self.depth.downgrade(self.ansi[n as usize])
+            }
+            color => self.depth.downgrade(color),
+        }
+    }
+}
+
+// A theme file given by path, one in the user's themes directory, or
+// failing that a built-in theme
+fn find(name: &str) -> Result<ThemeConfig, String> {
+    if name.contains('/') {
+        let path = match (name.strip_prefix("~/"), dirs::home_dir()) {
+            (Some(rest), Some(home)) => home.join(rest),
+            _ => PathBuf::from(name),
+        };
+        let text = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        return parse_toml(&text).map_err(|e| format!("{}: {}", path.display(), e));
+    }
+    if let Some(path) = Theme::user_dir().map(|dir| dir.join(format!("{}.toml", name))) {
+        match fs::read_to_string(&path) {
+            Ok(text) => return parse_toml(&text).map_err(|e| format!("{}: {}", path.display(), e)),
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(format!("{}: {}", path.display(), e)),
+            Err(_) => {}
+        }
+    }
+    match BUILTIN_THEMES.iter().find(|(builtin, _)| *builtin == name) {
+        Some((_, text)) => parse_toml(text).map_err(|e| format!("theme `{}`: {}", name, e)),
+        None => Err(format!("unknown theme `{}`", name)),
+    }
+}
+
+fn color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<CellColor>, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    parse_color(&name).map(Some).map_err(serde::de::Error::custom)
+}
+
+fn ansi<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<CellColor>, D::Error> {
+    let names = Vec::<String>::deserialize(deserializer)?;
+    if names.len() > 16 {
+        return Err(serde::de::Error::custom("there are only 16 ANSI colors"));
+    }
+    names
+        .iter()
+        .map(|name| parse_color(name).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(text: &str) -> ThemeConfig {
+        parse_toml(text).unwrap()
+    }
+
+    #[test]
+    fn builtin_themes_load() {
+        for name in Theme::builtin_names() {
+            let theme = Theme::load(&config(&format!("name = \"{}\"\ncolors = \"truecolor\"", name))).unwrap();
+            if name != "default" {
+                assert!(matches!(theme.ansi[1], CellColor::Rgb(..)), "{}", name);
+            }
+        }
+        let error = Theme::load(&config("name = \"no-such-theme\"")).unwrap_err();
+        assert_eq!(error, "unknown theme `no-such-theme`");
+    }
+
+    #[test]
+    fn config_colors_go_on_top() {
+        let theme = Theme::load(&config("name = \"dracula\"\nerror = \"bright_red\"\nansi = [\"#000001\"]\ncolors = \"truecolor\"")).unwrap();
+        assert_eq!(theme.error, CellColor::Indexed(9));
+        assert_eq!(theme.prompt, CellColor::Rgb(0x50, 0xfa, 0x7b));
+        assert_eq!(theme.ansi[0], CellColor::Rgb(0, 0, 1));
+        assert_eq!(theme.ansi[1], CellColor::Rgb(0xff, 0x55, 0x55));
+        assert!(parse_toml::<ThemeConfig>("ansi = [\"red\", \"nope\"]").unwrap_err().starts_with("line 1: invalid color"));
+        assert!(parse_toml::<ThemeConfig>("selected = \"red\"").unwrap_err().starts_with("line 1: unknown field"));
+    }
+
+    #[test]
+    fn theme_files_build_on_others() {
+        let dir = std::env::temp_dir().join(format!("rte-theme-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let base = dir.join("base.toml");
+        let mine = dir.join("mine.toml");
+        fs::write(&base, "name = \"nord\"\nprompt = \"#010203\"").unwrap();
+        fs::write(&mine, format!("name = \"{}\"\nerror = \"magenta\"", base.display())).unwrap();
+        let looped = dir.join("loop.toml");
+        fs::write(&looped, format!("name = \"{}\"", looped.display())).unwrap();
+
+        let theme = Theme::load(&config(&format!("name = \"{}\"", mine.display())));
+        let looping = Theme::load(&config(&format!("name = \"{}\"", looped.display())));
+        let missing = Theme::load(&config(&format!("name = \"{}\"", dir.join("gone.toml").display())));
+        let _ = fs::remove_dir_all(&dir);
+
+        let theme = theme.unwrap();
+        assert_eq!(theme.prompt, CellColor::Rgb(1, 2, 3));
+        assert_eq!(theme.error, CellColor::Indexed(5));
+        assert_eq!(theme.status_bar, CellColor::Rgb(0x88, 0xc0, 0xd0));
+        assert!(looping.unwrap_err().contains("loop"));
+        assert!(missing.unwrap_err().contains("gone.toml"));
+    }
+
+    #[test]
+    fn colors_go_through_the_palette_and_depth() {
+        let mut theme = Theme::load(&config("name = \"dracula\"\ncolors = \"truecolor\"")).unwrap();
+        assert_eq!(theme.color(CellColor::Indexed(1)), CellColor::Rgb(0xff, 0x55, 0x55));
+        assert_eq!(theme.color(CellColor::Indexed(100)), CellColor::Indexed(100));
+        theme.depth = ColorDepth::Ansi256;
+        assert_eq!(theme.color(CellColor::Indexed(1)), CellColor::Indexed(203));
+        theme.depth = ColorDepth::Ansi16;
+        assert_eq!(theme.color(CellColor::Indexed(1)), CellColor::Indexed(1));
+        assert_eq!(theme.color(CellColor::Rgb(0, 0, 250)), CellColor::Indexed(4));
+    }
+}